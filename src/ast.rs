@@ -1,4 +1,6 @@
-use crate::types::Type;
+use crate::types::{Type, Value};
+use num_bigint::BigInt;
+use rust_decimal::Decimal;
 use std::collections::HashMap;
 
 /// Abstract Syntax Tree nodes for the Boba language
@@ -6,8 +8,12 @@ use std::collections::HashMap;
 pub enum Expr {
     // Literals
     IntLiteral(i64),
+    BigIntLiteral(BigInt),
     FloatLiteral(f64),
+    DecimalLiteral(Decimal),
+    CharLiteral(char),
     StringLiteral(String),
+    BytesLiteral(Vec<u8>),
     BoolLiteral(bool),
     NullLiteral,
     
@@ -16,18 +22,73 @@ pub enum Expr {
     Map(Vec<(Expr, Expr)>),
     
     // Variables
-    Identifier(String),
-    VarDeclaration(String, Box<Expr>),
+    Identifier {
+        name: String,
+        slot: Option<VarSlot>,
+    },
+    /// `name = value`, which assigns to a visible variable or declares one
+    VarDeclaration {
+        name: String,
+        value: Box<Expr>,
+        line: usize,
+        slot: Option<VarSlot>,
+    },
+    Index {
+        target: Box<Expr>,
+        index: Box<Expr>,
+    },
+    /// `target[start:end]`, where either bound can be left out
+    Slice {
+        target: Box<Expr>,
+        start: Option<Box<Expr>>,
+        end: Option<Box<Expr>>,
+    },
+    
+    // Records
+    RecordLiteral {
+        name: String,
+        fields: Vec<(String, Expr)>,
+    },
+    FieldAccess {
+        target: Box<Expr>,
+        field: String,
+    },
+    FieldAssignment {
+        target: Box<Expr>,
+        field: String,
+        value: Box<Expr>,
+    },
+    /// `target[index] = value`, replacing a list element or setting a map
+    /// entry
+    IndexAssignment {
+        target: Box<Expr>,
+        index: Box<Expr>,
+        value: Box<Expr>,
+    },
     
     // Operations
     BinaryOp {
         left: Box<Expr>,
         operator: BinaryOperator,
         right: Box<Expr>,
+        line: usize,
     },
     UnaryOp {
         operator: UnaryOperator,
         expr: Box<Expr>,
+        line: usize,
+    },
+    
+    // Enums
+    VariantLiteral {
+        enum_name: String,
+        variant: String,
+        args: Vec<Expr>,
+    },
+    Match {
+        scrutinee: Box<Expr>,
+        arms: Vec<MatchArm>,
+        dispatch: MatchDispatch,
     },
     
     // Control flow
@@ -43,25 +104,67 @@ pub enum Expr {
         update: Option<Box<Expr>>,
         body: Vec<Expr>,
     },
-    Continue,
-    Break,
-    Return(Vec<Expr>),
+    Continue {
+        line: usize,
+    },
+    Break {
+        line: usize,
+    },
+    Return {
+        values: Vec<Expr>,
+        line: usize,
+    },
     
     // Function
     FunctionDeclaration {
         name: String,
+        type_params: Vec<String>,
         params: Vec<(String, Type)>,
         return_types: Vec<Type>,
         body: Vec<Expr>,
+        line: usize,
+        slot: Option<VarSlot>,
     },
+    /// `name(args)`, calling a variable holding a function when `slot` is
+    /// set and the top-level function otherwise
     FunctionCall {
         name: String,
         args: Vec<Expr>,
+        slot: Option<VarSlot>,
+        line: usize,
     },
-    
+    Lambda {
+        params: Vec<(String, Type)>,
+        return_types: Vec<Type>,
+        body: Vec<Expr>,
+        line: usize,
+    },
+    Call {
+        callee: Box<Expr>,
+        args: Vec<Expr>,
+        line: usize,
+    },
+    /// `target.method(args)`, an interface method or a call of a record
+    /// field holding a function
+    MethodCall {
+        target: Box<Expr>,
+        method: String,
+        args: Vec<Expr>,
+        line: usize,
+        /// The type whose implementation is called, filled in by the type
+        /// checker when the target's type is known
+        receiver: Option<String>,
+    },
+
     // Built-in functions
     Output(Vec<Expr>),
     OutputFormatted(Box<Expr>),
+    /// A `{value:spec}` in an `outputf` string, the value's text laid out
+    /// as the spec says
+    Format {
+        expr: Box<Expr>,
+        spec: FormatSpec,
+    },
     OutputAddress(Box<Expr>),
     Input(Box<Expr>),
     InputFormatted(Box<Expr>),
@@ -70,15 +173,153 @@ pub enum Expr {
     TypeConversion {
         expr: Box<Expr>,
         target_type: Type,
+        line: usize,
     },
+    /// `expr is type` or `expr is not type`
     TypeCheck {
         expr: Box<Expr>,
         check_type: Type,
         is_negated: bool,
+        line: usize,
+    },
+    /// `expr as type`, checked at runtime when the value's type isn't known
+    /// statically
+    Cast {
+        expr: Box<Expr>,
+        target_type: Type,
+    },
+    /// An int, or a collection holding ints, that the type checker accepted
+    /// as `target_type`, which has floats in their place. The type checker
+    /// puts these in so that the ints become floats.
+    IntToFloat {
+        expr: Box<Expr>,
+        target_type: Type,
     },
 }
 
+/// Where a variable lives at runtime: `depth` scopes out from the one it is
+/// used in, at `index` in that scope. Filled in by the resolver; parameters,
+/// loop variables and pattern bindings take the first indices of their
+/// scope in declaration order.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VarSlot {
+    pub depth: usize,
+    pub index: usize,
+}
+
 #[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Vec<Expr>,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// `_`, matches anything
+    Wildcard,
+    /// A name, matches anything and binds it
+    Binding(String),
+    /// An int, float, string, bool or null literal
+    Literal(Expr),
+    /// `Shape.Circle(r)`
+    Variant {
+        enum_name: String,
+        variant: String,
+        fields: Vec<Pattern>,
+    },
+    /// `[a, b]`, or `[first, ...rest]` when `rest` is given
+    List {
+        items: Vec<Pattern>,
+        rest: Option<Box<Pattern>>,
+    },
+    /// `Point { x: 0.0, y }`; fields that aren't mentioned match anything
+    Record {
+        name: String,
+        fields: Vec<(String, Pattern)>,
+    },
+}
+
+impl Pattern {
+    /// The names the pattern binds, in the order matching binds them
+    pub fn bindings(&self) -> Vec<&str> {
+        let mut names = Vec::new();
+        self.collect_bindings(&mut names);
+        names
+    }
+    
+    fn collect_bindings<'a>(&'a self, names: &mut Vec<&'a str>) {
+        match self {
+            Pattern::Wildcard | Pattern::Literal(_) => {}
+            Pattern::Binding(name) => names.push(name),
+            Pattern::Variant { fields, .. } => {
+                for field in fields {
+                    field.collect_bindings(names);
+                }
+            }
+            Pattern::List { items, rest } => {
+                for item in items {
+                    item.collect_bindings(names);
+                }
+                if let Some(rest) = rest {
+                    rest.collect_bindings(names);
+                }
+            }
+            Pattern::Record { fields, .. } => {
+                for (_, field) in fields {
+                    field.collect_bindings(names);
+                }
+            }
+        }
+    }
+}
+
+/// Arms of a `match` grouped by the enum variant they can match, so that
+/// matching a variant only tries the arms that could possibly apply
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct MatchDispatch {
+    pub by_variant: HashMap<String, Vec<usize>>,
+    /// Arms whose pattern matches any variant, also the arms to try for
+    /// values that aren't variants
+    pub fallback: Vec<usize>,
+}
+
+impl MatchDispatch {
+    pub fn new(arms: &[MatchArm]) -> Self {
+        let mut dispatch = MatchDispatch::default();
+        
+        // Every variant mentioned gets its own arms in order, interleaved
+        // with the catch-all arms that come before or after them
+        for (i, arm) in arms.iter().enumerate() {
+            match &arm.pattern {
+                Pattern::Variant { variant, .. } => {
+                    if !dispatch.by_variant.contains_key(variant) {
+                        let earlier = dispatch.fallback.clone();
+                        dispatch.by_variant.insert(variant.clone(), earlier);
+                    }
+                    dispatch.by_variant.get_mut(variant).unwrap().push(i);
+                }
+                _ => {
+                    for candidates in dispatch.by_variant.values_mut() {
+                        candidates.push(i);
+                    }
+                    dispatch.fallback.push(i);
+                }
+            }
+        }
+        
+        dispatch
+    }
+    
+    pub fn candidates(&self, value: &Value) -> &[usize] {
+        match value {
+            Value::Variant { variant, .. } => self.by_variant.get(variant).unwrap_or(&self.fallback),
+            _ => &self.fallback,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum BinaryOperator {
     Add,
     Subtract,
@@ -95,7 +336,23 @@ pub enum BinaryOperator {
     Or,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// The part after the `:` in `{price:>10.2}`: an alignment, a width and the
+/// digits to show after the point
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct FormatSpec {
+    pub align: Option<Align>,
+    pub width: Option<usize>,
+    pub precision: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum UnaryOperator {
     Negate,
     Not,
@@ -105,13 +362,68 @@ pub enum UnaryOperator {
 #[derive(Debug, Clone)]
 pub struct Program {
     pub functions: HashMap<String, FunctionDef>,
+    pub records: HashMap<String, RecordDef>,
+    pub enums: HashMap<String, EnumDef>,
+    pub interfaces: HashMap<String, InterfaceDef>,
+    pub impls: Vec<ImplDef>,
+    /// Constants in declaration order
+    pub constants: Vec<ConstDef>,
     pub main_block: Vec<Expr>,
 }
 
+/// `const NAME = value`, evaluated before the program runs
+#[derive(Debug, Clone)]
+pub struct ConstDef {
+    pub name: String,
+    pub value: Expr,
+    pub line: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct FunctionDef {
     pub name: String,
+    pub type_params: Vec<String>,
+    /// Interfaces the type parameters must implement, e.g. `("T", "Show")`
+    /// for `<T: Show>`
+    pub bounds: Vec<(String, String)>,
     pub params: Vec<(String, Type)>,
     pub return_types: Vec<Type>,
     pub body: Vec<Expr>,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordDef {
+    pub name: String,
+    pub fields: Vec<(String, Type)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct EnumDef {
+    pub name: String,
+    /// Variants in declaration order with their payload types
+    pub variants: Vec<(String, Vec<Type>)>,
+}
+
+#[derive(Debug, Clone)]
+pub struct InterfaceDef {
+    pub name: String,
+    pub methods: Vec<MethodSig>,
+}
+
+/// A method required by an interface. The first parameter is `self` and
+/// `Self` stands for the implementing type, written as `Type::Param("Self")`.
+#[derive(Debug, Clone)]
+pub struct MethodSig {
+    pub name: String,
+    pub params: Vec<(String, Type)>,
+    pub return_types: Vec<Type>,
+}
+
+/// `impl Interface for Type { ... }`
+#[derive(Debug, Clone)]
+pub struct ImplDef {
+    pub interface: String,
+    pub target: Type,
+    pub methods: Vec<FunctionDef>,
 }
@@ -0,0 +1,226 @@
+use crate::types::{Type, Value};
+use rust_decimal::{Decimal, RoundingStrategy};
+
+/// A function every program can call without declaring it. A function the
+/// program declares with the same name is called instead.
+pub struct Builtin {
+    pub name: &'static str,
+    pub params: &'static [Type],
+    pub returns: Type,
+    pub call: fn(&[Value]) -> Result<Value, String>,
+}
+
+impl Builtin {
+    pub fn to_type(&self) -> Type {
+        Type::Function {
+            type_params: Vec::new(),
+            params: self.params.to_vec(),
+            returns: vec![self.returns.clone()],
+        }
+    }
+}
+
+/// A builtin taking two ints and giving an int
+macro_rules! int_builtin {
+    ($name:literal, $op:path) => {
+        Builtin {
+            name: $name,
+            params: &[Type::Int, Type::Int],
+            returns: Type::Int,
+            call: |args| match args {
+                [Value::Int(a), Value::Int(b)] => Ok(Value::Int($op(*a, *b))),
+                _ => Err(args_error($name, &[Type::Int, Type::Int], args)),
+            },
+        }
+    };
+}
+
+/// The builtins, in the order the VM numbers them. Integer arithmetic
+/// stops the program when it overflows; the int builtins give a defined
+/// result instead, wrapping around or stopping at the largest or smallest
+/// int.
+pub static BUILTINS: &[Builtin] = &[
+    int_builtin!("wrapping_add", i64::wrapping_add),
+    int_builtin!("wrapping_sub", i64::wrapping_sub),
+    int_builtin!("wrapping_mul", i64::wrapping_mul),
+    int_builtin!("saturating_add", i64::saturating_add),
+    int_builtin!("saturating_sub", i64::saturating_sub),
+    int_builtin!("saturating_mul", i64::saturating_mul),
+    // `round(x, places)` rounds halves away from zero
+    Builtin {
+        name: "round",
+        params: &[Type::Decimal, Type::Int],
+        returns: Type::Decimal,
+        call: |args| match args {
+            [Value::Decimal(n), Value::Int(places)] => round_decimal("round", *n, *places, "half_up"),
+            _ => Err(args_error("round", &[Type::Decimal, Type::Int], args)),
+        },
+    },
+    // `round_with(x, places, mode)` rounds the way `mode` says, one of
+    // the `ROUNDING_MODES`
+    Builtin {
+        name: "round_with",
+        params: &[Type::Decimal, Type::Int, Type::String],
+        returns: Type::Decimal,
+        call: |args| match args {
+            [Value::Decimal(n), Value::Int(places), Value::String(mode)] => round_decimal("round_with", *n, *places, mode),
+            _ => Err(args_error("round_with", &[Type::Decimal, Type::Int, Type::String], args)),
+        },
+    },
+    // The number of characters in a string, bytes in bytes, or elements in
+    // a list or map
+    Builtin {
+        name: "len",
+        params: &[Type::Any],
+        returns: Type::Int,
+        call: |args| match args {
+            [Value::String(s)] => Ok(Value::Int(s.chars().count() as i64)),
+            [Value::Bytes(bytes)] => Ok(Value::Int(bytes.len() as i64)),
+            [Value::List(items)] => Ok(Value::Int(items.len() as i64)),
+            [Value::Map(map)] => Ok(Value::Int(map.len() as i64)),
+            [other] => Err(format!("Function 'len' expects a string, bytes, list or map, got {}", other.get_type())),
+            _ => Err(args_error("len", &[Type::Any], args)),
+        },
+    },
+    // Bytes as two lowercase hex digits each, and back
+    Builtin {
+        name: "to_hex",
+        params: &[Type::Bytes],
+        returns: Type::String,
+        call: |args| match args {
+            [Value::Bytes(bytes)] => Ok(Value::String(bytes.iter().map(|byte| format!("{:02x}", byte)).collect())),
+            _ => Err(args_error("to_hex", &[Type::Bytes], args)),
+        },
+    },
+    Builtin {
+        name: "from_hex",
+        params: &[Type::String],
+        returns: Type::Bytes,
+        call: |args| match args {
+            [Value::String(s)] => from_hex(s),
+            _ => Err(args_error("from_hex", &[Type::String], args)),
+        },
+    },
+    // Standard base64, with `+`, `/` and `=` padding
+    Builtin {
+        name: "to_base64",
+        params: &[Type::Bytes],
+        returns: Type::String,
+        call: |args| match args {
+            [Value::Bytes(bytes)] => Ok(Value::String(to_base64(bytes))),
+            _ => Err(args_error("to_base64", &[Type::Bytes], args)),
+        },
+    },
+    Builtin {
+        name: "from_base64",
+        params: &[Type::String],
+        returns: Type::Bytes,
+        call: |args| match args {
+            [Value::String(s)] => from_base64(s),
+            _ => Err(args_error("from_base64", &[Type::String], args)),
+        },
+    },
+];
+
+/// The ways `round_with` can round, and what they do with the digits that
+/// are dropped
+const ROUNDING_MODES: &[(&str, RoundingStrategy)] = &[
+    // To the nearest, halves away from zero: 2.345 to 2.35, -2.345 to -2.35
+    ("half_up", RoundingStrategy::MidpointAwayFromZero),
+    // To the nearest, halves towards zero: 2.345 to 2.34
+    ("half_down", RoundingStrategy::MidpointTowardZero),
+    // To the nearest, halves to an even last digit: 2.345 to 2.34, 2.355 to 2.36
+    ("half_even", RoundingStrategy::MidpointNearestEven),
+    // Away from zero: 2.341 to 2.35
+    ("up", RoundingStrategy::AwayFromZero),
+    // Towards zero: 2.349 to 2.34
+    ("down", RoundingStrategy::ToZero),
+    // Towards positive infinity: -2.349 to -2.34
+    ("ceiling", RoundingStrategy::ToPositiveInfinity),
+    // Towards negative infinity: -2.341 to -2.35
+    ("floor", RoundingStrategy::ToNegativeInfinity),
+];
+
+/// Rounds to `places` digits after the point, keeping trailing zeros so
+/// that `round(2.5d, 2)` prints as 2.50
+fn round_decimal(name: &str, n: Decimal, places: i64, mode: &str) -> Result<Value, String> {
+    let Some(&(_, strategy)) = ROUNDING_MODES.iter().find(|(mode_name, _)| *mode_name == mode) else {
+        let modes: Vec<_> = ROUNDING_MODES.iter().map(|(mode_name, _)| *mode_name).collect();
+        return Err(format!("Unknown rounding mode '{}', expected one of {}", mode, modes.join(", ")));
+    };
+    if !(0..=Decimal::MAX_SCALE as i64).contains(&places) {
+        return Err(format!(
+            "Function '{}' rounds to between 0 and {} places, got {}",
+            name,
+            Decimal::MAX_SCALE,
+            places
+        ));
+    }
+
+    let mut rounded = n.round_dp_with_strategy(places as u32, strategy);
+    rounded.rescale(places as u32);
+    Ok(Value::Decimal(rounded))
+}
+
+fn from_hex(s: &str) -> Result<Value, String> {
+    if let Some(c) = s.chars().find(|c| !c.is_ascii_hexdigit()) {
+        return Err(format!("Cannot convert '{}' from hex: '{}' is not a hex digit", s, c));
+    }
+    if !s.len().is_multiple_of(2) {
+        return Err(format!("Cannot convert '{}' from hex: it has an odd number of digits", s));
+    }
+    let bytes = (0..s.len()).step_by(2).map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap()).collect();
+    Ok(Value::Bytes(bytes))
+}
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn to_base64(bytes: &[u8]) -> String {
+    let mut text = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        // The chunk's bits, padded with zeros to 24
+        let bits = chunk.iter().enumerate().fold(0u32, |bits, (i, &byte)| bits | (byte as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64_ALPHABET[(bits >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+fn from_base64(s: &str) -> Result<Value, String> {
+    let invalid = || format!("Cannot convert '{}' from base64", s);
+    if !s.len().is_multiple_of(4) {
+        return Err(invalid());
+    }
+    let mut bytes = Vec::with_capacity(s.len() / 4 * 3);
+    let chunk_count = s.len() / 4;
+    for (n, chunk) in s.as_bytes().chunks(4).enumerate() {
+        // Padding can only end the last chunk
+        let padding = chunk.iter().rev().take_while(|&&c| c == b'=').count();
+        if padding > 2 || (padding > 0 && n + 1 < chunk_count) {
+            return Err(invalid());
+        }
+        let mut bits = 0u32;
+        for (i, &c) in chunk[..4 - padding].iter().enumerate() {
+            let value = BASE64_ALPHABET.iter().position(|&a| a == c).ok_or_else(invalid)?;
+            bits |= (value as u32) << (18 - 6 * i);
+        }
+        bytes.extend(&bits.to_be_bytes()[1..4 - padding]);
+    }
+    Ok(Value::Bytes(bytes))
+}
+
+pub fn lookup(name: &str) -> Option<(usize, &'static Builtin)> {
+    BUILTINS.iter().enumerate().find(|(_, builtin)| builtin.name == name)
+}
+
+/// Values typed `any` can reach a builtin with the wrong types
+fn args_error(name: &str, params: &[Type], args: &[Value]) -> String {
+    let params: Vec<_> = params.iter().map(|param| param.to_string()).collect();
+    let types: Vec<_> = args.iter().map(|arg| arg.get_type().to_string()).collect();
+    format!("Function '{}' expects ({}), got ({})", name, params.join(", "), types.join(", "))
+}
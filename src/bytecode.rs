@@ -0,0 +1,188 @@
+use crate::ast::{BinaryOperator, FormatSpec, MatchDispatch, Pattern, UnaryOperator};
+use crate::types::{Type, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::rc::Rc;
+
+/// A VM instruction. Instructions work on the operand stack of the current
+/// call; `u32` operands index the pools of the function being run unless
+/// noted otherwise.
+#[derive(Debug, Clone, Copy)]
+pub enum Op {
+    /// Pushes `constants[i]`
+    Constant(u32),
+    Pop,
+    Dup,
+    /// Drops all but the first `n` operands, e.g. the partly evaluated
+    /// expressions that a `break` jumps out of
+    Truncate(u32),
+
+    GetLocal(u32),
+    SetLocal(u32),
+    GetCell(u32),
+    SetCell(u32),
+    /// Puts the value on top of the stack in a new cell, so that closures
+    /// created earlier keep the previous one
+    DeclareCell(u32),
+    /// Moves a loop variable's value to a new cell for the next iteration
+    RenewCell(u32),
+    GetUpvalue(u32),
+    SetUpvalue(u32),
+    /// Pushes the top-level function `module.functions[i]`
+    GetFunction(u32),
+
+    List(u32),
+    Map(u32),
+    Index,
+    /// Pops the end and start of a slice, either of which is null when left
+    /// out, and slices the value below them
+    Slice,
+    /// Builds the record `module.records[i]` from its field values, which
+    /// are on the stack in declaration order
+    Record(u32),
+    /// Builds the enum value `module.variants[i]` from its payload
+    Variant(u32),
+    /// Replaces a record with its field `names[i]`
+    GetField(u32),
+    /// Pops a value, a variable's value and the indices on `paths[i]`, and
+    /// pushes the variable's value with the part at the end of the path
+    /// replaced by the value
+    SetPath(u32),
+
+    Binary(BinaryOperator),
+    Unary(UnaryOperator),
+    /// Jumps, leaving the left operand as the result, if it decides the
+    /// `and` or `or`
+    ShortCircuit(BinaryOperator, u32),
+    Convert(u32),
+    Cast(u32),
+    /// Makes the ints in a value that `types[i]` says are floats floats
+    IntToFloat(u32),
+    TypeCheck(u32, bool),
+
+    Jump(u32),
+    /// Pops a condition and jumps if it is false. The name is the kind of
+    /// condition for the error when it isn't a bool.
+    JumpIfFalse(u32, &'static str),
+    /// Pops a value and jumps to the first arm of `matches[i]` that matches it
+    Match(u32),
+
+    /// Creates a closure of `module.protos[i]`
+    Closure(u32),
+    /// Checks that the value on top of the stack can be called, before its
+    /// arguments are evaluated. The name is the variable it came from.
+    CheckFunction(Option<u32>),
+    /// Calls the function below the `n` arguments on the stack. Calls end
+    /// with the line they are on, for tracebacks.
+    Call(u32, u32),
+    /// Calls the top-level function `module.functions[i]` with `n` arguments
+    CallFunction(u32, u32, u32),
+    /// Calls method `names[i]` of the receiver below the `n` arguments. The
+    /// last name is the receiver's type when the type checker knows it.
+    CallMethod(u32, u32, u32, Option<u32>),
+    /// Calls `builtins::BUILTINS[i]` with `n` arguments
+    CallBuiltin(u32, u32),
+    /// Returns the first of the `n` values on top of the stack. The values
+    /// of an explicit `return` are checked against the declared return
+    /// types, the value of the body's last statement isn't.
+    Return(u32, bool),
+
+    Output(u32),
+    OutputFormatted,
+    /// Replaces a value with its text laid out as `formats[i]` says
+    Format(u32),
+    /// Raises the runtime error `names[i]`
+    Fail(u32),
+}
+
+/// Where a variable lives in a call. Variables that closures capture are
+/// kept in shared cells.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Slot {
+    Local(u32),
+    Cell(u32),
+}
+
+/// A variable a closure captures from the function that creates it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Capture {
+    Cell(u32),
+    Upvalue(u32),
+}
+
+#[derive(Debug)]
+pub struct MatchTable {
+    pub dispatch: MatchDispatch,
+    pub arms: Vec<MatchArmCode>,
+}
+
+#[derive(Debug)]
+pub struct MatchArmCode {
+    pub pattern: Pattern,
+    /// Where each of the pattern's bindings goes, in binding order
+    pub bindings: Vec<Slot>,
+    pub target: u32,
+}
+
+/// A step from a variable to the part of it an assignment changes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathStep {
+    /// The field `names[i]`
+    Field(u32),
+    /// An index taken from the stack
+    Index,
+}
+
+/// A compiled function
+#[derive(Debug, Default)]
+pub struct Proto {
+    pub name: String,
+    pub params: Vec<(String, Type)>,
+    pub return_types: Vec<Type>,
+    pub param_slots: Vec<Slot>,
+    pub locals: usize,
+    pub cells: usize,
+    pub captures: Vec<Capture>,
+    pub code: Vec<Op>,
+    pub constants: Vec<Value>,
+    pub names: Vec<String>,
+    pub types: Vec<Type>,
+    pub formats: Vec<FormatSpec>,
+    pub matches: Vec<MatchTable>,
+    pub paths: Vec<Vec<PathStep>>,
+}
+
+pub struct Closure {
+    pub proto: Rc<Proto>,
+    pub upvalues: Vec<Rc<RefCell<Value>>>,
+}
+
+// A closure can capture a cell holding itself, so compare by identity and
+// don't print the captured values
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<function {}>", self.proto.name)
+    }
+}
+
+/// A compiled program
+pub struct Module {
+    pub protos: Vec<Rc<Proto>>,
+    /// Top-level functions, as closures without captures
+    pub functions: Vec<Value>,
+    /// Interface methods by implementing type and method name
+    pub methods: HashMap<(String, String), Value>,
+    /// Record names with their fields in declaration order
+    pub records: Vec<(String, Vec<String>)>,
+    /// Enum names, variant names and payload sizes
+    pub variants: Vec<(String, String, usize)>,
+    /// `main`, or the statements outside functions if there is none
+    pub entry: Rc<Proto>,
+}
@@ -0,0 +1,912 @@
+use crate::ast::{BinaryOperator, Expr, Program};
+use crate::builtins;
+use crate::bytecode::{Capture, Closure, MatchArmCode, MatchTable, Module, Op, PathStep, Proto, Slot};
+use crate::types::{Type, Value};
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// Compiles a checked program to bytecode. Variables are resolved to slots
+/// here, following the tree-walker's scoping rules, so the VM never looks
+/// up a name. Errors the tree-walker would only find when it gets there,
+/// like an undefined variable, compile to instructions that raise them.
+pub fn compile(program: &Program) -> Module {
+    let mut names: Vec<&String> = program.functions.keys().collect();
+    names.sort();
+
+    let mut compiler = Compiler {
+        program,
+        function_indices: names.iter().enumerate().map(|(i, name)| ((*name).clone(), i as u32)).collect(),
+        records: Vec::new(),
+        record_indices: HashMap::new(),
+        variants: Vec::new(),
+        variant_indices: HashMap::new(),
+        protos: Vec::new(),
+        functions: Vec::new(),
+    };
+
+    let mut functions = Vec::new();
+    for name in names {
+        let func_def = &program.functions[name];
+        let (proto, _) = compiler.compile_function(
+            name,
+            &func_def.params,
+            &func_def.return_types,
+            &func_def.body,
+            FunctionKind::TopLevel,
+        );
+        functions.push(Value::Closure(Rc::new(Closure { proto, upvalues: Vec::new() })));
+    }
+
+    let mut methods = HashMap::new();
+    for impl_def in &program.impls {
+        let type_name = impl_def.target.to_string();
+        for method in &impl_def.methods {
+            let name = format!("{}.{}", type_name, method.name);
+            let (proto, _) = compiler.compile_function(
+                &name,
+                &method.params,
+                &method.return_types,
+                &method.body,
+                FunctionKind::TopLevel,
+            );
+            let closure = Value::Closure(Rc::new(Closure { proto, upvalues: Vec::new() }));
+            methods.insert((type_name.clone(), method.name.clone()), closure);
+        }
+    }
+
+    // `main` runs like the statements outside functions, without being called
+    let entry_body = match program.functions.get("main") {
+        Some(main) => &main.body,
+        None => &program.main_block,
+    };
+    let (entry, _) = compiler.compile_function("main", &[], &[], entry_body, FunctionKind::Entry);
+
+    Module {
+        protos: compiler.protos,
+        functions,
+        methods,
+        records: compiler.records,
+        variants: compiler.variants,
+        entry,
+    }
+}
+
+struct Compiler<'a> {
+    program: &'a Program,
+    /// Indices of the top-level functions in `Module::functions`
+    function_indices: HashMap<String, u32>,
+    records: Vec<(String, Vec<String>)>,
+    record_indices: HashMap<String, u32>,
+    variants: Vec<(String, String, usize)>,
+    variant_indices: HashMap<(String, String), u32>,
+    protos: Vec<Rc<Proto>>,
+    /// The functions being compiled, innermost last
+    functions: Vec<FunctionState>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionKind {
+    /// The statements the program runs, which aren't called
+    Entry,
+    TopLevel,
+    /// A nested or anonymous function, which can see the variables of the
+    /// function it is in
+    Nested,
+}
+
+struct FunctionState {
+    proto: Proto,
+    kind: FunctionKind,
+    scopes: Vec<Vec<(String, Slot)>>,
+    /// Names used by functions nested in this one. Variables with these
+    /// names are kept in cells so that closures can share them.
+    captured: HashSet<String>,
+    /// Names of the captured variables, by upvalue index
+    upvalues: Vec<String>,
+    loops: Vec<LoopState>,
+    /// Number of operands on the stack at the current instruction
+    depth: u32,
+}
+
+struct LoopState {
+    depth: u32,
+    continue_target: u32,
+    breaks: Vec<usize>,
+}
+
+enum Var {
+    Slot(Slot),
+    Upvalue(u32),
+}
+
+impl Compiler<'_> {
+    fn state(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn compile_function(
+        &mut self,
+        name: &str,
+        params: &[(String, Type)],
+        return_types: &[Type],
+        body: &[Expr],
+        kind: FunctionKind,
+    ) -> (Rc<Proto>, u32) {
+        self.functions.push(FunctionState {
+            proto: Proto {
+                name: name.to_string(),
+                params: params.to_vec(),
+                return_types: return_types.to_vec(),
+                ..Proto::default()
+            },
+            kind,
+            scopes: vec![Vec::new()],
+            captured: captured_names(body),
+            upvalues: Vec::new(),
+            loops: Vec::new(),
+            depth: 0,
+        });
+
+        for (param, _) in params {
+            let slot = self.declare(param);
+            self.state().proto.param_slots.push(slot);
+        }
+        self.compile_statements(body);
+        self.emit(Op::Return(1, false));
+
+        let proto = Rc::new(self.functions.pop().unwrap().proto);
+        self.protos.push(proto.clone());
+        (proto, self.protos.len() as u32 - 1)
+    }
+
+    /// Declares a new variable in the innermost scope
+    fn declare(&mut self, name: &str) -> Slot {
+        let state = self.state();
+        let slot = if state.captured.contains(name) {
+            state.proto.cells += 1;
+            Slot::Cell(state.proto.cells as u32 - 1)
+        } else {
+            state.proto.locals += 1;
+            Slot::Local(state.proto.locals as u32 - 1)
+        };
+        state.scopes.last_mut().unwrap().push((name.to_string(), slot));
+        slot
+    }
+
+    fn resolve(&mut self, name: &str) -> Option<Var> {
+        self.resolve_in(self.functions.len() - 1, name)
+    }
+
+    /// Finds the variable `name` refers to in `functions[level]`, capturing
+    /// it from the enclosing functions if needed
+    fn resolve_in(&mut self, level: usize, name: &str) -> Option<Var> {
+        let state = &self.functions[level];
+        for scope in state.scopes.iter().rev() {
+            if let Some((_, slot)) = scope.iter().rev().find(|(n, _)| n == name) {
+                return Some(Var::Slot(*slot));
+            }
+        }
+        if let Some(i) = state.upvalues.iter().position(|n| n == name) {
+            return Some(Var::Upvalue(i as u32));
+        }
+        if state.kind != FunctionKind::Nested {
+            return None;
+        }
+
+        let capture = match self.resolve_in(level - 1, name)? {
+            Var::Slot(Slot::Cell(i)) => Capture::Cell(i),
+            Var::Upvalue(i) => Capture::Upvalue(i),
+            Var::Slot(Slot::Local(_)) => unreachable!("captured variable '{}' isn't in a cell", name),
+        };
+        let state = &mut self.functions[level];
+        state.upvalues.push(name.to_string());
+        state.proto.captures.push(capture);
+        Some(Var::Upvalue(state.upvalues.len() as u32 - 1))
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let effect: i64 = match op {
+            Op::Constant(_) | Op::Dup | Op::GetLocal(_) | Op::GetCell(_) | Op::GetUpvalue(_)
+            | Op::GetFunction(_) | Op::Closure(_) | Op::Fail(_) => 1,
+            Op::Pop | Op::Index | Op::Binary(_) | Op::JumpIfFalse(..) | Op::Match(_) => -1,
+            Op::SetPath(i) => {
+                let path = &self.functions.last().unwrap().proto.paths[i as usize];
+                -1 - path.iter().filter(|step| **step == PathStep::Index).count() as i64
+            }
+            Op::List(n) => 1 - n as i64,
+            Op::Map(n) => 1 - 2 * n as i64,
+            Op::Slice => -2,
+            Op::Record(i) => 1 - self.records[i as usize].1.len() as i64,
+            Op::Variant(i) => 1 - self.variants[i as usize].2 as i64,
+            Op::Call(n, _) | Op::CallMethod(_, n, _, _) | Op::Return(n, _) => -(n as i64),
+            Op::CallFunction(_, n, _) | Op::CallBuiltin(_, n) | Op::Output(n) => 1 - n as i64,
+            _ => 0,
+        };
+        let state = self.state();
+        state.depth = match op {
+            Op::Truncate(n) => n,
+            _ => (state.depth as i64 + effect) as u32,
+        };
+        state.proto.code.push(op);
+        state.proto.code.len() - 1
+    }
+
+    fn here(&mut self) -> u32 {
+        self.state().proto.code.len() as u32
+    }
+
+    /// Points the jump at `at` to the next instruction
+    fn patch(&mut self, at: usize) {
+        let target = self.here();
+        match &mut self.state().proto.code[at] {
+            Op::Jump(t) | Op::JumpIfFalse(t, _) | Op::ShortCircuit(_, t) => *t = target,
+            op => unreachable!("patching {:?}", op),
+        }
+    }
+
+    fn constant(&mut self, value: Value) {
+        let constants = &mut self.state().proto.constants;
+        constants.push(value);
+        let index = constants.len() as u32 - 1;
+        self.emit(Op::Constant(index));
+    }
+
+    fn name(&mut self, name: &str) -> u32 {
+        let names = &mut self.state().proto.names;
+        match names.iter().position(|n| n == name) {
+            Some(i) => i as u32,
+            None => {
+                names.push(name.to_string());
+                names.len() as u32 - 1
+            }
+        }
+    }
+
+    fn type_index(&mut self, ty: &Type) -> u32 {
+        let types = &mut self.state().proto.types;
+        types.push(ty.clone());
+        types.len() as u32 - 1
+    }
+
+    fn fail(&mut self, message: String) {
+        let index = self.name(&message);
+        self.emit(Op::Fail(index));
+    }
+
+    fn record_index(&mut self, name: &str, fields: Vec<String>) -> u32 {
+        if let Some(&i) = self.record_indices.get(name) {
+            return i;
+        }
+        self.records.push((name.to_string(), fields));
+        let index = self.records.len() as u32 - 1;
+        self.record_indices.insert(name.to_string(), index);
+        index
+    }
+
+    fn variant_index(&mut self, enum_name: &str, variant: &str, arity: usize) -> u32 {
+        let key = (enum_name.to_string(), variant.to_string());
+        if let Some(&i) = self.variant_indices.get(&key) {
+            return i;
+        }
+        self.variants.push((enum_name.to_string(), variant.to_string(), arity));
+        let index = self.variants.len() as u32 - 1;
+        self.variant_indices.insert(key, index);
+        index
+    }
+
+    /// Emits a read of the variable
+    fn load(&mut self, var: Var) {
+        self.emit(match var {
+            Var::Slot(Slot::Local(i)) => Op::GetLocal(i),
+            Var::Slot(Slot::Cell(i)) => Op::GetCell(i),
+            Var::Upvalue(i) => Op::GetUpvalue(i),
+        });
+    }
+
+    /// Stores the value on top of the stack in a newly declared variable,
+    /// leaving it on the stack
+    fn store_new(&mut self, slot: Slot) {
+        self.emit(match slot {
+            Slot::Local(i) => Op::SetLocal(i),
+            Slot::Cell(i) => Op::DeclareCell(i),
+        });
+    }
+
+    /// Assigns the value on top of the stack to the nearest variable called
+    /// `name`, declaring it if there is none, and leaves it on the stack
+    fn assign(&mut self, name: &str) {
+        let op = match self.resolve(name) {
+            Some(Var::Slot(Slot::Local(i))) => Op::SetLocal(i),
+            Some(Var::Slot(Slot::Cell(i))) => Op::SetCell(i),
+            Some(Var::Upvalue(i)) => Op::SetUpvalue(i),
+            None => {
+                let slot = self.declare(name);
+                return self.store_new(slot);
+            }
+        };
+        self.emit(op);
+    }
+
+    /// Compiles statements leaving the value of the last one on the stack
+    fn compile_statements(&mut self, body: &[Expr]) {
+        if body.is_empty() {
+            self.constant(Value::Null);
+            return;
+        }
+        for (i, expr) in body.iter().enumerate() {
+            self.compile_expr(expr);
+            if i + 1 < body.len() {
+                self.emit(Op::Pop);
+            }
+        }
+    }
+
+    fn compile_block(&mut self, body: &[Expr]) {
+        self.state().scopes.push(Vec::new());
+        self.compile_statements(body);
+        self.state().scopes.pop();
+    }
+
+    fn compile_exprs(&mut self, exprs: &[Expr]) {
+        for expr in exprs {
+            self.compile_expr(expr);
+        }
+    }
+
+    /// Compiles an expression leaving its value on the stack
+    fn compile_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::IntLiteral(n) => self.constant(Value::Int(*n)),
+            Expr::BigIntLiteral(n) => self.constant(Value::BigInt(n.clone())),
+            Expr::DecimalLiteral(n) => self.constant(Value::Decimal(*n)),
+            Expr::FloatLiteral(n) => self.constant(Value::Float(*n)),
+            Expr::CharLiteral(c) => self.constant(Value::Char(*c)),
+            Expr::StringLiteral(s) => self.constant(Value::String(s.clone())),
+            Expr::BytesLiteral(b) => self.constant(Value::Bytes(b.clone())),
+            Expr::BoolLiteral(b) => self.constant(Value::Bool(*b)),
+            Expr::NullLiteral => self.constant(Value::Null),
+
+            Expr::List(items) => {
+                self.compile_exprs(items);
+                self.emit(Op::List(items.len() as u32));
+            }
+            Expr::Map(entries) => {
+                for (key, value) in entries {
+                    self.compile_expr(key);
+                    self.compile_expr(value);
+                }
+                self.emit(Op::Map(entries.len() as u32));
+            }
+
+            Expr::VarDeclaration { name, value, .. } => {
+                self.compile_expr(value);
+                self.assign(name);
+            }
+
+            Expr::Identifier { name, .. } => match self.resolve(name) {
+                Some(var) => self.load(var),
+                // Named functions can be used as values
+                None => match self.function_indices.get(name) {
+                    Some(&i) => {
+                        self.emit(Op::GetFunction(i));
+                    }
+                    None => self.fail(format!("Undefined variable: {}", name)),
+                },
+            },
+
+            Expr::Index { target, index } => {
+                self.compile_expr(target);
+                self.compile_expr(index);
+                self.emit(Op::Index);
+            }
+            Expr::Slice { target, start, end } => {
+                self.compile_expr(target);
+                for bound in [start, end] {
+                    match bound {
+                        Some(bound) => self.compile_expr(bound),
+                        None => self.constant(Value::Null),
+                    }
+                }
+                self.emit(Op::Slice);
+            }
+
+            Expr::RecordLiteral { name, fields } => {
+                let record_def = match self.program.records.get(name) {
+                    Some(record_def) => record_def,
+                    None => return self.fail(format!("Undefined type: {}", name)),
+                };
+
+                // Fields are evaluated in declaration order regardless of the
+                // literal's order
+                let mut field_exprs = Vec::new();
+                for (field_name, _) in &record_def.fields {
+                    match fields.iter().find(|(f, _)| f == field_name) {
+                        Some((_, field_expr)) => field_exprs.push(field_expr),
+                        None => return self.fail(format!("Missing field '{}' in '{}' literal", field_name, name)),
+                    }
+                }
+                for field_expr in field_exprs {
+                    self.compile_expr(field_expr);
+                }
+                let field_names = record_def.fields.iter().map(|(f, _)| f.clone()).collect();
+                let index = self.record_index(name, field_names);
+                self.emit(Op::Record(index));
+            }
+
+            Expr::FieldAccess { target, field } => {
+                self.compile_expr(target);
+                let index = self.name(field);
+                self.emit(Op::GetField(index));
+            }
+
+            Expr::FieldAssignment { target, field, value } => {
+                self.compile_expr(value);
+                let depth = self.state().depth;
+                self.emit(Op::Dup);
+                let mut path = Vec::new();
+                match self.compile_place(target, &mut path) {
+                    Some(name) => {
+                        path.push(PathStep::Field(self.name(field)));
+                        self.set_path(name, path);
+                    }
+                    None => self.state().depth = depth,
+                }
+            }
+            Expr::IndexAssignment { target, index, value } => {
+                self.compile_expr(value);
+                let depth = self.state().depth;
+                self.emit(Op::Dup);
+                let mut path = Vec::new();
+                match self.compile_place(target, &mut path) {
+                    Some(name) => {
+                        self.compile_expr(index);
+                        path.push(PathStep::Index);
+                        self.set_path(name, path);
+                    }
+                    None => self.state().depth = depth,
+                }
+            }
+
+            Expr::VariantLiteral { enum_name, variant, args } => {
+                let arity = self
+                    .program
+                    .enums
+                    .get(enum_name)
+                    .and_then(|def| def.variants.iter().find(|(v, _)| v == variant))
+                    .map(|(_, payload)| payload.len());
+                match arity {
+                    None => self.fail(format!("Undefined variant: {}.{}", enum_name, variant)),
+                    Some(arity) if arity != args.len() => self.fail(format!(
+                        "Variant '{}.{}' expects {} values, got {}",
+                        enum_name, variant, arity, args.len()
+                    )),
+                    Some(arity) => {
+                        self.compile_exprs(args);
+                        let index = self.variant_index(enum_name, variant, arity);
+                        self.emit(Op::Variant(index));
+                    }
+                }
+            }
+
+            Expr::Match { scrutinee, arms, dispatch } => {
+                self.compile_expr(scrutinee);
+                let table = self.state().proto.matches.len() as u32;
+                self.state().proto.matches.push(MatchTable { dispatch: dispatch.clone(), arms: Vec::new() });
+                self.emit(Op::Match(table));
+
+                let depth = self.state().depth;
+                let mut arm_codes = Vec::new();
+                let mut ends = Vec::new();
+                for arm in arms {
+                    let target = self.here();
+                    self.state().scopes.push(Vec::new());
+                    let bindings = arm.pattern.bindings().into_iter().map(|name| self.declare(name)).collect();
+                    self.compile_statements(&arm.body);
+                    self.state().scopes.pop();
+                    ends.push(self.emit(Op::Jump(0)));
+                    self.state().depth = depth;
+
+                    arm_codes.push(MatchArmCode { pattern: arm.pattern.clone(), bindings, target });
+                }
+                for end in ends {
+                    self.patch(end);
+                }
+                self.state().depth = depth + 1;
+                self.state().proto.matches[table as usize].arms = arm_codes;
+            }
+
+            Expr::Output(args) => {
+                self.compile_exprs(args);
+                self.emit(Op::Output(args.len() as u32));
+            }
+
+            Expr::OutputFormatted(format_expr) => {
+                self.compile_expr(format_expr);
+                self.emit(Op::OutputFormatted);
+            }
+
+            Expr::Format { expr, spec } => {
+                self.compile_expr(expr);
+                let formats = &mut self.state().proto.formats;
+                formats.push(*spec);
+                let index = formats.len() as u32 - 1;
+                self.emit(Op::Format(index));
+            }
+
+            Expr::Return { values, .. } => {
+                // Like the tree-walker, every value is checked but only the
+                // first is returned
+                for value in values {
+                    self.compile_expr(value);
+                }
+                if values.is_empty() {
+                    self.constant(Value::Null);
+                }
+                self.emit(Op::Return(values.len().max(1) as u32, true));
+                self.state().depth += 1;
+            }
+
+            Expr::Break { .. } | Expr::Continue { .. } => {
+                let depth = self.state().depth;
+                let is_break = matches!(expr, Expr::Break { .. });
+                let kind = self.state().kind;
+                let innermost = self.state().loops.last().map(|l| (l.depth, l.continue_target));
+                match innermost {
+                    Some((loop_depth, continue_target)) => {
+                        self.emit(Op::Truncate(loop_depth));
+                        if is_break {
+                            let jump = self.emit(Op::Jump(0));
+                            self.state().loops.last_mut().unwrap().breaks.push(jump);
+                        } else {
+                            self.emit(Op::Jump(continue_target));
+                        }
+                    }
+                    // Outside a loop the statements of the program just stop
+                    None if kind == FunctionKind::Entry => {
+                        self.constant(Value::Null);
+                        self.emit(Op::Return(1, false));
+                    }
+                    None => {
+                        let name = self.state().proto.name.clone();
+                        self.fail(format!("'break' or 'continue' outside of a loop in function '{}'", name));
+                    }
+                }
+                self.state().depth = depth + 1;
+            }
+
+            Expr::If { condition, then_branch, else_if_branches, else_branch } => {
+                let depth = self.state().depth;
+                let mut ends = Vec::new();
+
+                self.compile_expr(condition);
+                let mut next = self.emit(Op::JumpIfFalse(0, "If"));
+                self.compile_block(then_branch);
+                ends.push(self.emit(Op::Jump(0)));
+
+                for (cond, branch) in else_if_branches {
+                    self.state().depth = depth;
+                    self.patch(next);
+                    self.compile_expr(cond);
+                    next = self.emit(Op::JumpIfFalse(0, "Else-if"));
+                    self.compile_block(branch);
+                    ends.push(self.emit(Op::Jump(0)));
+                }
+
+                self.state().depth = depth;
+                self.patch(next);
+                match else_branch {
+                    Some(branch) => self.compile_block(branch),
+                    None => self.constant(Value::Null),
+                }
+                for end in ends {
+                    self.patch(end);
+                }
+            }
+
+            Expr::Loop { init, condition, update, body } => {
+                let depth = self.state().depth;
+                self.state().scopes.push(Vec::new());
+
+                // The loop variable always belongs to the loop, even if an
+                // outer variable has the same name
+                let mut loop_var = None;
+                match init.as_deref() {
+                    Some(Expr::VarDeclaration { name, value, .. }) => {
+                        self.compile_expr(value);
+                        let slot = self.declare(name);
+                        self.store_new(slot);
+                        self.emit(Op::Pop);
+                        loop_var = Some(slot);
+                    }
+                    Some(init_expr) => {
+                        self.compile_expr(init_expr);
+                        self.emit(Op::Pop);
+                    }
+                    None => {}
+                }
+                let first = self.emit(Op::Jump(0));
+
+                // Every iteration after the first gets its own copy of the
+                // loop variable, then runs the update
+                let top = self.here();
+                if let Some(Slot::Cell(i)) = loop_var {
+                    self.emit(Op::RenewCell(i));
+                }
+                if let Some(update_expr) = update {
+                    self.compile_expr(update_expr);
+                    self.emit(Op::Pop);
+                }
+
+                self.patch(first);
+                let exit = condition.as_deref().map(|cond_expr| {
+                    self.compile_expr(cond_expr);
+                    self.emit(Op::JumpIfFalse(0, "Loop"))
+                });
+
+                self.state().loops.push(LoopState { depth, continue_target: top, breaks: Vec::new() });
+                self.compile_block(body);
+                self.emit(Op::Pop);
+                self.emit(Op::Jump(top));
+
+                let loop_state = self.state().loops.pop().unwrap();
+                if let Some(exit) = exit {
+                    self.patch(exit);
+                }
+                for jump in loop_state.breaks {
+                    self.patch(jump);
+                }
+                self.state().scopes.pop();
+                self.state().depth = depth;
+                self.constant(Value::Null);
+            }
+
+            Expr::BinaryOp { left, operator, right, .. } => {
+                self.compile_expr(left);
+                if matches!(operator, BinaryOperator::And | BinaryOperator::Or) {
+                    let jump = self.emit(Op::ShortCircuit(*operator, 0));
+                    self.compile_expr(right);
+                    self.emit(Op::Binary(*operator));
+                    self.patch(jump);
+                } else {
+                    self.compile_expr(right);
+                    self.emit(Op::Binary(*operator));
+                }
+            }
+
+            Expr::UnaryOp { operator, expr, .. } => {
+                self.compile_expr(expr);
+                self.emit(Op::Unary(*operator));
+            }
+
+            Expr::FunctionCall { name, args, line, .. } => {
+                // A variable holding a function shadows a named function
+                if let Some(var) = self.resolve(name) {
+                    self.load(var);
+                    let index = self.name(name);
+                    self.emit(Op::CheckFunction(Some(index)));
+                    self.compile_exprs(args);
+                    self.emit(Op::Call(args.len() as u32, *line as u32));
+                } else if let Some(&index) = self.function_indices.get(name) {
+                    self.compile_exprs(args);
+                    self.emit(Op::CallFunction(index, args.len() as u32, *line as u32));
+                } else if let Some((index, _)) = builtins::lookup(name) {
+                    self.compile_exprs(args);
+                    self.emit(Op::CallBuiltin(index as u32, args.len() as u32));
+                } else {
+                    self.fail(format!("Undefined function: {}", name));
+                }
+            }
+
+            Expr::Call { callee, args, line } => {
+                self.compile_expr(callee);
+                self.emit(Op::CheckFunction(None));
+                self.compile_exprs(args);
+                self.emit(Op::Call(args.len() as u32, *line as u32));
+            }
+
+            Expr::MethodCall { target, method, args, line, receiver } => {
+                self.compile_expr(target);
+                self.compile_exprs(args);
+                let index = self.name(method);
+                let receiver = receiver.as_ref().map(|type_name| self.name(type_name));
+                self.emit(Op::CallMethod(index, args.len() as u32, *line as u32, receiver));
+            }
+
+            Expr::Lambda { params, return_types, body, .. } => {
+                let (_, index) = self.compile_function("anonymous", params, return_types, body, FunctionKind::Nested);
+                self.emit(Op::Closure(index));
+            }
+
+            Expr::FunctionDeclaration { name, params, return_types, body, .. } => {
+                // Declared first so that the function can call itself
+                let slot = self.declare(name);
+                if let Slot::Cell(i) = slot {
+                    self.constant(Value::Null);
+                    self.emit(Op::DeclareCell(i));
+                    self.emit(Op::Pop);
+                }
+                let (_, index) = self.compile_function(name, params, return_types, body, FunctionKind::Nested);
+                self.emit(Op::Closure(index));
+                self.emit(match slot {
+                    Slot::Local(i) => Op::SetLocal(i),
+                    Slot::Cell(i) => Op::SetCell(i),
+                });
+            }
+
+            Expr::Cast { expr, target_type } => {
+                self.compile_expr(expr);
+                let index = self.type_index(target_type);
+                self.emit(Op::Cast(index));
+            }
+
+            Expr::IntToFloat { expr, target_type } => {
+                self.compile_expr(expr);
+                let index = self.type_index(target_type);
+                self.emit(Op::IntToFloat(index));
+            }
+
+            Expr::TypeCheck { expr, check_type, is_negated, .. } => {
+                self.compile_expr(expr);
+                let index = self.type_index(check_type);
+                self.emit(Op::TypeCheck(index, *is_negated));
+            }
+
+            Expr::TypeConversion { expr, target_type, .. } => {
+                self.compile_expr(expr);
+                let index = self.type_index(target_type);
+                self.emit(Op::Convert(index));
+            }
+
+            Expr::OutputAddress(_) | Expr::Input(_) | Expr::InputFormatted(_) => {
+                self.fail(format!("Unsupported expression: {:?}", expr));
+            }
+        }
+    }
+
+    /// Assigns the value on top of the stack to `target.field`, writing the
+    /// changed record back through every record on the path to the variable
+    /// Compiles the variable an assignment target like `p.x` or `xs[i].name`
+    /// is in, followed by the indices on the path to the part being changed,
+    /// and returns the variable's name. Indices are evaluated from the
+    /// variable outwards.
+    fn compile_place<'a>(&mut self, place: &'a Expr, path: &mut Vec<PathStep>) -> Option<&'a str> {
+        match place {
+            Expr::Identifier { name, .. } if self.resolve(name).is_some() => {
+                self.compile_expr(place);
+                Some(name)
+            }
+            Expr::Identifier { name, .. } => {
+                self.fail(format!("Cannot assign to function '{}'", name));
+                None
+            }
+            Expr::FieldAccess { target, field } => {
+                let name = self.compile_place(target, path)?;
+                let index = self.name(field);
+                path.push(PathStep::Field(index));
+                Some(name)
+            }
+            Expr::Index { target, index } => {
+                let name = self.compile_place(target, path)?;
+                self.compile_expr(index);
+                path.push(PathStep::Index);
+                Some(name)
+            }
+            other => {
+                self.fail(format!("Invalid assignment target: {:?}", other));
+                None
+            }
+        }
+    }
+
+    /// Updates the variable `name` with the value below it on the stack at
+    /// the end of `path`, leaving the value
+    fn set_path(&mut self, name: &str, path: Vec<PathStep>) {
+        let index = self.state().proto.paths.len() as u32;
+        self.state().proto.paths.push(path);
+        self.emit(Op::SetPath(index));
+        self.assign(name);
+        self.emit(Op::Pop);
+    }
+}
+
+/// Names used inside the functions nested in `body`
+fn captured_names(body: &[Expr]) -> HashSet<String> {
+    let mut names = HashSet::new();
+    for expr in body {
+        collect_names(expr, false, &mut names);
+    }
+    names
+}
+
+fn collect<'a>(exprs: impl IntoIterator<Item = &'a Expr>, nested: bool, names: &mut HashSet<String>) {
+    for expr in exprs {
+        collect_names(expr, nested, names);
+    }
+}
+
+/// Adds the names `expr` uses to `names` if it is inside a nested function
+fn collect_names(expr: &Expr, nested: bool, names: &mut HashSet<String>) {
+    match expr {
+        Expr::IntLiteral(_)
+        | Expr::BigIntLiteral(_)
+        | Expr::DecimalLiteral(_)
+        | Expr::FloatLiteral(_)
+        | Expr::CharLiteral(_)
+        | Expr::StringLiteral(_)
+        | Expr::BytesLiteral(_)
+        | Expr::BoolLiteral(_)
+        | Expr::NullLiteral
+        | Expr::Break { .. }
+        | Expr::Continue { .. } => {}
+
+        Expr::Identifier { name, .. } => {
+            if nested {
+                names.insert(name.clone());
+            }
+        }
+        Expr::VarDeclaration { name, value, .. } => {
+            if nested {
+                names.insert(name.clone());
+            }
+            collect(std::iter::once(&**value), nested, names);
+        }
+        Expr::FunctionCall { name, args, .. } => {
+            if nested {
+                names.insert(name.clone());
+            }
+            collect(args.iter(), nested, names);
+        }
+        Expr::FunctionDeclaration { name, body, .. } => {
+            if nested {
+                names.insert(name.clone());
+            }
+            collect(body.iter(), true, names);
+        }
+        Expr::Lambda { body, .. } => collect(body.iter(), true, names),
+
+        Expr::List(items) | Expr::Output(items) | Expr::VariantLiteral { args: items, .. } => {
+            collect(items.iter(), nested, names)
+        }
+        Expr::Map(entries) => collect(entries.iter().flat_map(|(k, v)| [k, v]), nested, names),
+        Expr::Index { target, index } => collect([&**target, &**index], nested, names),
+        Expr::Slice { target, start, end } => {
+            let bounds = [start, end].into_iter().flatten().map(|e| &**e);
+            collect(std::iter::once(&**target).chain(bounds), nested, names);
+        }
+        Expr::RecordLiteral { fields, .. } => collect(fields.iter().map(|(_, e)| e), nested, names),
+        Expr::FieldAssignment { target, value, .. } => collect([&**target, &**value], nested, names),
+        Expr::IndexAssignment { target, index, value } => collect([&**target, &**index, &**value], nested, names),
+        Expr::BinaryOp { left, right, .. } => collect([&**left, &**right], nested, names),
+        Expr::Match { scrutinee, arms, .. } => {
+            collect(std::iter::once(&**scrutinee), nested, names);
+            collect(arms.iter().flat_map(|arm| &arm.body), nested, names);
+        }
+        Expr::If { condition, then_branch, else_if_branches, else_branch } => {
+            collect(std::iter::once(&**condition), nested, names);
+            collect(then_branch.iter(), nested, names);
+            for (cond, branch) in else_if_branches {
+                collect(std::iter::once(cond).chain(branch), nested, names);
+            }
+            collect(else_branch.iter().flatten(), nested, names);
+        }
+        Expr::Loop { init, condition, update, body } => {
+            let parts = [init, condition, update].into_iter().flatten().map(|e| &**e);
+            collect(parts.chain(body), nested, names);
+        }
+        Expr::Return { values, .. } => collect(values.iter(), nested, names),
+        Expr::Call { callee, args, .. } => collect(std::iter::once(&**callee).chain(args), nested, names),
+        Expr::MethodCall { target, args, .. } => collect(std::iter::once(&**target).chain(args), nested, names),
+
+        Expr::FieldAccess { target: expr, .. }
+        | Expr::UnaryOp { expr, .. }
+        | Expr::OutputFormatted(expr)
+        | Expr::Format { expr, .. }
+        | Expr::OutputAddress(expr)
+        | Expr::Input(expr)
+        | Expr::InputFormatted(expr)
+        | Expr::TypeConversion { expr, .. }
+        | Expr::TypeCheck { expr, .. }
+        | Expr::Cast { expr, .. }
+        | Expr::IntToFloat { expr, .. } => collect(std::iter::once(&**expr), nested, names),
+    }
+}
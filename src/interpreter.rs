@@ -1,107 +1,577 @@
-use crate::ast::{Expr, Program};
-use crate::types::{Type, Value};
+use crate::ast::{
+    Align, BinaryOperator, EnumDef, Expr, FormatSpec, MatchArm, Pattern, Program, RecordDef, UnaryOperator, VarSlot,
+};
+use crate::builtins;
+use crate::types::{MapValue, Type, Value};
+use im::Vector;
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, ToPrimitive, Zero};
+use rust_decimal::{Decimal, RoundingStrategy};
+use std::cell::{Cell, RefCell};
 use std::collections::HashMap;
+use std::fmt;
 use std::io::{self, Write};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// A lexical scope. Closures keep a reference to the scope they were
+/// created in, so captured variables are shared with the enclosing code.
+/// Variables are found by the index the resolver gave them.
+pub struct Scope {
+    variables: RefCell<Vec<Value>>,
+    parent: Option<Rc<Scope>>,
+}
+
+impl Scope {
+    pub fn new(parent: Option<Rc<Scope>>) -> Self {
+        Self {
+            variables: RefCell::new(Vec::new()),
+            parent,
+        }
+    }
+    
+    fn ancestor(&self, depth: usize) -> &Scope {
+        let mut scope = self;
+        for _ in 0..depth {
+            scope = scope.parent.as_deref().expect("resolved variable outside of any scope");
+        }
+        scope
+    }
+    
+    /// The variable's value, if it has been declared yet
+    fn lookup(&self, slot: VarSlot) -> Option<Value> {
+        self.ancestor(slot.depth).variables.borrow().get(slot.index).cloned()
+    }
+    
+    /// Sets a variable, declaring it if its index is new to the scope
+    fn store(&self, slot: VarSlot, value: Value) {
+        let mut variables = self.ancestor(slot.depth).variables.borrow_mut();
+        if slot.index >= variables.len() {
+            variables.resize(slot.index + 1, Value::Null);
+        }
+        variables[slot.index] = value;
+    }
+}
+
+// Scopes can contain closures that refer back to them, so compare by
+// identity and don't print their contents
+impl PartialEq for Scope {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl fmt::Debug for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<scope>")
+    }
+}
+
+/// Pending non-local control flow raised by `return`, `break` or `continue`
+enum Flow {
+    Return(Vec<Value>),
+    Break,
+    Continue,
+}
+
+/// How many calls can be active at once unless configured otherwise
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 10_000;
+
+/// Limits on what a program may use, set from the command line, so that a
+/// runaway program stops with an error instead of overflowing the stack or
+/// running forever. Only the call depth is limited by default.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    /// The most calls that can be active at once
+    pub max_call_depth: usize,
+    /// The most steps a program can take. Every function call and every
+    /// repeat of a loop is a step.
+    pub max_steps: Option<u64>,
+    /// How long a program can run for
+    pub max_time: Option<Duration>,
+    /// The most elements a list or map can have
+    pub max_collection_size: Option<usize>,
+    /// The most bytes a string can have
+    pub max_string_length: Option<usize>,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_steps: None,
+            max_time: None,
+            max_collection_size: None,
+            max_string_length: None,
+        }
+    }
+}
+
+fn resource_limit_error(message: String) -> String {
+    format!("Resource limit exceeded: {}", message)
+}
+
+/// Keeps track of what a running program has used of its limits
+pub(crate) struct Budget {
+    pub limits: Limits,
+    steps: Cell<u64>,
+    deadline: Option<Instant>,
+}
+
+impl Budget {
+    /// Starts the clock on the program's time limit
+    pub fn new(limits: &Limits) -> Self {
+        Self {
+            limits: limits.clone(),
+            steps: Cell::new(0),
+            deadline: limits.max_time.map(|max_time| Instant::now() + max_time),
+        }
+    }
+    
+    /// Counts a function call or a repeat of a loop. Every long-running
+    /// program takes steps, so this is also where the time is checked.
+    pub fn step(&self) -> Result<(), String> {
+        let steps = self.steps.get() + 1;
+        self.steps.set(steps);
+        if let Some(max_steps) = self.limits.max_steps {
+            if steps > max_steps {
+                return Err(resource_limit_error(format!("more than {} steps", max_steps)));
+            }
+        }
+        if let Some(deadline) = self.deadline {
+            if Instant::now() > deadline {
+                let max_time = self.limits.max_time.unwrap_or_default();
+                return Err(resource_limit_error(format!("ran for more than {:?}", max_time)));
+            }
+        }
+        Ok(())
+    }
+    
+    /// Checks a string or collection the program creates against the size
+    /// limits. Only the value itself is measured, not the values in it.
+    pub fn check_size(&self, value: &Value) -> Result<(), String> {
+        let size = match value {
+            Value::String(s) => return self.check_string(s),
+            Value::Bytes(b) => {
+                return match self.limits.max_string_length {
+                    Some(max_length) if b.len() > max_length => Err(resource_limit_error(format!(
+                        "{} bytes, more than the string length limit of {}",
+                        b.len(),
+                        max_length
+                    ))),
+                    _ => Ok(()),
+                };
+            },
+            // A bigint that keeps growing uses as much memory as a string of
+            // its digits would
+            Value::BigInt(n) => {
+                let digits = (n.bits() as f64 * std::f64::consts::LOG10_2) as usize;
+                return match self.limits.max_string_length {
+                    Some(max_length) if digits > max_length => Err(resource_limit_error(format!(
+                        "a bigint of about {} digits, more than the string length limit of {}",
+                        digits, max_length
+                    ))),
+                    _ => Ok(()),
+                };
+            },
+            Value::List(items) => items.len(),
+            Value::Map(map) => map.len(),
+            _ => return Ok(()),
+        };
+        match self.limits.max_collection_size {
+            Some(max_size) if size > max_size => Err(resource_limit_error(format!(
+                "a list or map of {} elements, more than the limit of {}",
+                size, max_size
+            ))),
+            _ => Ok(()),
+        }
+    }
+    
+    pub fn check_string(&self, s: &str) -> Result<(), String> {
+        self.check_string_length(s.len())
+    }
+    
+    /// Checks the length of a string before it is built, for strings that
+    /// could be too big to allocate
+    pub fn check_string_length(&self, length: usize) -> Result<(), String> {
+        match self.limits.max_string_length {
+            Some(max_length) if length > max_length => Err(resource_limit_error(format!(
+                "a string of {} bytes, more than the limit of {}",
+                length,
+                max_length
+            ))),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// The error for a call that would go past the call depth limit
+pub(crate) fn call_depth_error(limit: usize, name: &str) -> String {
+    format!("Maximum call depth of {} exceeded calling '{}'", limit, name)
+}
+
+/// A call in progress, as listed in the call stack of a runtime error
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct CallFrame {
+    pub name: String,
+    /// The line of the call, or `None` for the methods that output and
+    /// comparisons call
+    pub line: Option<usize>,
+}
+
+/// How many entries are shown at each end of a long call stack
+const TRACEBACK_ENDS: usize = 10;
+
+/// Adds the calls that were in progress to a runtime error, below the
+/// entry point and most recent last. A run of calls from the same place, as
+/// in a recursion, is listed once, and only the ends of a long call stack
+/// are shown.
+pub(crate) fn with_traceback(error: String, calls: &[CallFrame]) -> String {
+    // Each entry with the number of calls it stands for
+    let mut entries = vec![("main".to_string(), 1)];
+    let mut calls = calls.iter().peekable();
+    while let Some(call) = calls.next() {
+        let mut count = 1;
+        while calls.next_if_eq(&call).is_some() {
+            count += 1;
+        }
+        let mut entry = match call.line {
+            Some(line) => format!("{}, called on line {}", call.name, line),
+            None => call.name.clone(),
+        };
+        if count > 1 {
+            entry.push_str(&format!(" ({} calls)", count));
+        }
+        entries.push((entry, count));
+    }
+    
+    if entries.len() > 2 * TRACEBACK_ENDS {
+        let end = entries.len() - TRACEBACK_ENDS;
+        let hidden: usize = entries[TRACEBACK_ENDS..end].iter().map(|(_, count)| count).sum();
+        entries.splice(TRACEBACK_ENDS..end, [(format!("... {} more calls", hidden), hidden)]);
+    }
+    
+    let mut message = error;
+    message.push_str("\nCall stack, most recent call last:");
+    for (entry, _) in entries {
+        message.push_str(&format!("\n  {}", entry));
+    }
+    message
+}
+
+/// Runs the interface methods a program implements. Output, comparisons
+/// and method calls share their logic between the tree-walker and the VM,
+/// which differ only in how they call a function.
+pub(crate) trait Methods {
+    fn has_method(&self, type_name: &str, method: &str) -> bool;
+    
+    /// Calls a method the program implements, from `line` when the
+    /// program calls it explicitly
+    fn call_impl(&mut self, type_name: &str, method: &str, args: Vec<Value>, line: Option<usize>) -> Result<Value, String>;
+    
+    fn budget(&self) -> &Budget;
+}
 
 pub struct Environment {
-    variables: HashMap<String, Value>,
-    functions: HashMap<String, Value>,
+    scope: Rc<Scope>,
+    functions: Rc<HashMap<String, Value>>,
+    records: Rc<HashMap<String, RecordDef>>,
+    enums: Rc<HashMap<String, EnumDef>>,
+    /// Interface methods by implementing type and method name
+    methods: Rc<HashMap<(String, String), Value>>,
+    flow: Option<Flow>,
+    /// The calls in progress, outermost first. A call that fails stays on
+    /// the stack, so that the error can say where it happened.
+    call_stack: Rc<RefCell<Vec<CallFrame>>>,
+    budget: Rc<Budget>,
 }
 
 impl Environment {
     pub fn new() -> Self {
+        Self::with_scope(Rc::new(Scope::new(None)))
+    }
+
+    fn with_scope(scope: Rc<Scope>) -> Self {
         Self {
-            variables: HashMap::new(),
-            functions: HashMap::new(),
+            scope,
+            functions: Rc::new(HashMap::new()),
+            records: Rc::new(HashMap::new()),
+            enums: Rc::new(HashMap::new()),
+            methods: Rc::new(HashMap::new()),
+            flow: None,
+            call_stack: Rc::new(RefCell::new(Vec::new())),
+            budget: Rc::new(Budget::new(&Limits::default())),
         }
     }
 
-    pub fn define(&mut self, name: String, value: Value) {
-        self.variables.insert(name, value);
+    /// Declares the variable at `index` of the innermost scope
+    pub fn define(&mut self, index: usize, value: Value) {
+        self.scope.store(VarSlot { depth: 0, index }, value);
+    }
+    
+    pub fn assign(&mut self, slot: VarSlot, value: Value) {
+        self.scope.store(slot, value);
+    }
+    
+    pub fn get(&self, slot: VarSlot) -> Option<Value> {
+        self.scope.lookup(slot)
+    }
+    
+    pub fn get_function(&self, name: &str) -> Option<&Value> {
+        self.functions.get(name)
     }
 
-    pub fn get(&self, name: &str) -> Option<&Value> {
-        self.variables.get(name)
+    fn push_scope(&mut self) {
+        self.scope = Rc::new(Scope::new(Some(self.scope.clone())));
     }
 
-    pub fn define_function(&mut self, name: String, value: Value) {
-        self.functions.insert(name, value);
+    fn pop_scope(&mut self) {
+        if let Some(parent) = self.scope.parent.clone() {
+            self.scope = parent;
+        }
     }
+}
 
-    pub fn get_function(&self, name: &str) -> Option<&Value> {
-        self.functions.get(name)
+impl Methods for Environment {
+    fn has_method(&self, type_name: &str, method: &str) -> bool {
+        self.methods.contains_key(&(type_name.to_string(), method.to_string()))
+    }
+    
+    fn call_impl(&mut self, type_name: &str, method: &str, args: Vec<Value>, line: Option<usize>) -> Result<Value, String> {
+        let func = self.methods[&(type_name.to_string(), method.to_string())].clone();
+        call_function(func, args, line, self)
+    }
+    
+    fn budget(&self) -> &Budget {
+        &self.budget
     }
 }
 
-pub fn interpret(program: Program) -> Result<(), String> {
+pub fn interpret(program: Program, limits: &Limits) -> Result<(), String> {
     let mut env = Environment::new();
+    env.budget = Rc::new(Budget::new(limits));
+    env.records = Rc::new(program.records);
+    env.enums = Rc::new(program.enums);
+    
+    let mut methods = HashMap::new();
+    for impl_def in &program.impls {
+        let type_name = impl_def.target.to_string();
+        for method in &impl_def.methods {
+            let method_value = Value::Function {
+                name: format!("{}.{}", type_name, method.name),
+                params: method.params.clone(),
+                return_types: method.return_types.clone(),
+                body: Rc::new(method.body.clone()),
+                closure: None,
+            };
+            methods.insert((type_name.clone(), method.name.clone()), method_value);
+        }
+    }
+    env.methods = Rc::new(methods);
     
     // Register functions
+    let mut functions = HashMap::new();
     for (name, func_def) in &program.functions {
         let func_value = Value::Function {
             name: name.clone(),
             params: func_def.params.clone(),
             return_types: func_def.return_types.clone(),
-            body: func_def.body.clone(),
+            body: Rc::new(func_def.body.clone()),
+            closure: None,
         };
-        env.define_function(name.clone(), func_value);
+        functions.insert(name.clone(), func_value);
     }
+    env.functions = Rc::new(functions);
     
     // Look for main function
-    if let Some(Value::Function { body, .. }) = env.get_function("main").cloned() {
-        for expr in body {
-            evaluate_expr(&expr, &mut env)?;
-        }
+    let result = if let Some(Value::Function { body, .. }) = env.get_function("main").cloned() {
+        execute_statements(&body, &mut env)
     } else {
         // Execute main block if no main function
-        for expr in program.main_block {
-            evaluate_expr(&expr, &mut env)?;
+        execute_statements(&program.main_block, &mut env)
+    };
+    
+    result.map(|_| ()).map_err(|e| with_traceback(e, &env.call_stack.borrow()))
+}
+
+/// Runs statements in order until one of them raises control flow
+fn execute_statements(body: &[Expr], env: &mut Environment) -> Result<Value, String> {
+    let mut result = Value::Null;
+    for expr in body {
+        result = evaluate_expr(expr, env)?;
+        if env.flow.is_some() {
+            break;
         }
     }
-    
-    Ok(())
+    Ok(result)
+}
+
+/// Runs a block in a fresh scope
+fn execute_block(body: &[Expr], env: &mut Environment) -> Result<Value, String> {
+    env.push_scope();
+    let result = execute_statements(body, env);
+    env.pop_scope();
+    result
 }
 
 fn evaluate_expr(expr: &Expr, env: &mut Environment) -> Result<Value, String> {
     match expr {
         Expr::IntLiteral(n) => Ok(Value::Int(*n)),
+        Expr::BigIntLiteral(n) => Ok(Value::BigInt(n.clone())),
+        Expr::DecimalLiteral(n) => Ok(Value::Decimal(*n)),
         Expr::FloatLiteral(n) => Ok(Value::Float(*n)),
+        Expr::CharLiteral(c) => Ok(Value::Char(*c)),
         Expr::StringLiteral(s) => Ok(Value::String(s.clone())),
+        Expr::BytesLiteral(b) => Ok(Value::Bytes(b.clone())),
         Expr::BoolLiteral(b) => Ok(Value::Bool(*b)),
         Expr::NullLiteral => Ok(Value::Null),
         
         Expr::List(items) => {
-            let mut values = Vec::new();
+            let mut values = Vector::new();
             for item in items {
-                values.push(evaluate_expr(item, env)?);
+                values.push_back(evaluate_expr(item, env)?);
             }
-            Ok(Value::List(values))
+            let list = Value::List(values);
+            env.budget.check_size(&list)?;
+            Ok(list)
         },
         
         Expr::Map(entries) => {
-            let mut values = Vec::new();
+            let mut map = MapValue::new();
             for (key, value) in entries {
                 let key_val = evaluate_expr(key, env)?;
                 let val_val = evaluate_expr(value, env)?;
-                values.push((key_val, val_val));
+                map.insert(key_val, val_val)?;
             }
-            Ok(Value::Map(values))
+            let map = Value::Map(map);
+            env.budget.check_size(&map)?;
+            Ok(map)
         },
         
-        Expr::VarDeclaration(name, value_expr) => {
+        Expr::VarDeclaration { name, value: value_expr, slot, .. } => {
             let value = evaluate_expr(value_expr, env)?;
-            env.define(name.clone(), value.clone());
+            let slot = slot.ok_or_else(|| format!("Unresolved variable: {}", name))?;
+            env.assign(slot, value.clone());
             Ok(value)
         },
         
-        Expr::Identifier(name) => {
-            if let Some(value) = env.get(name) {
-                Ok(value.clone())
+        Expr::Identifier { name, slot } => {
+            if let Some(slot) = slot {
+                env.get(*slot).ok_or_else(|| format!("Undefined variable: {}", name))
+            } else if let Some(func) = env.get_function(name) {
+                // Named functions can be used as values
+                Ok(func.clone())
             } else {
                 Err(format!("Undefined variable: {}", name))
             }
         },
         
+        Expr::Index { target, index } => {
+            let target_val = evaluate_expr(target, env)?;
+            let index_val = evaluate_expr(index, env)?;
+            index_value(target_val, index_val)
+        },
+        
+        Expr::Slice { target, start, end } => {
+            let target_val = evaluate_expr(target, env)?;
+            let mut bound = |bound: &Option<Box<Expr>>| match bound {
+                Some(bound) => evaluate_expr(bound, env),
+                None => Ok(Value::Null),
+            };
+            let start_val = bound(start)?;
+            let end_val = bound(end)?;
+            slice_value(target_val, start_val, end_val)
+        },
+        
+        Expr::RecordLiteral { name, fields } => {
+            let record_def = env
+                .records
+                .get(name)
+                .cloned()
+                .ok_or_else(|| format!("Undefined type: {}", name))?;
+            
+            // Store fields in declaration order regardless of the literal's order
+            let mut values = Vec::new();
+            for (field_name, _) in &record_def.fields {
+                let field_expr = fields
+                    .iter()
+                    .find(|(f, _)| f == field_name)
+                    .map(|(_, e)| e)
+                    .ok_or_else(|| format!("Missing field '{}' in '{}' literal", field_name, name))?;
+                values.push((field_name.clone(), evaluate_expr(field_expr, env)?));
+            }
+            
+            Ok(Value::Record { name: name.clone(), fields: values })
+        },
+        
+        Expr::FieldAccess { target, field } => {
+            let target_val = evaluate_expr(target, env)?;
+            get_field(&target_val, field)
+        },
+        
+        Expr::FieldAssignment { target, field, value } => {
+            let new_value = evaluate_expr(value, env)?;
+            let (slot, current, mut path) = assignment_path(target, env)?;
+            path.push(Accessor::Field(field));
+            env.assign(slot, update_path(current, &path, new_value.clone(), &env.budget)?);
+            Ok(new_value)
+        },
+        
+        Expr::IndexAssignment { target, index, value } => {
+            let new_value = evaluate_expr(value, env)?;
+            let (slot, current, mut path) = assignment_path(target, env)?;
+            path.push(Accessor::Index(evaluate_expr(index, env)?));
+            env.assign(slot, update_path(current, &path, new_value.clone(), &env.budget)?);
+            Ok(new_value)
+        },
+        
+        Expr::VariantLiteral { enum_name, variant, args } => {
+            let arity = env
+                .enums
+                .get(enum_name)
+                .and_then(|def| def.variants.iter().find(|(v, _)| v == variant))
+                .map(|(_, payload)| payload.len())
+                .ok_or_else(|| format!("Undefined variant: {}.{}", enum_name, variant))?;
+            
+            if args.len() != arity {
+                return Err(format!(
+                    "Variant '{}.{}' expects {} values, got {}",
+                    enum_name, variant, arity, args.len()
+                ));
+            }
+            
+            let mut fields = Vec::new();
+            for arg in args {
+                fields.push(evaluate_expr(arg, env)?);
+            }
+            
+            Ok(Value::Variant {
+                enum_name: enum_name.clone(),
+                variant: variant.clone(),
+                fields,
+            })
+        },
+        
+        Expr::Match { scrutinee, arms, dispatch } => {
+            let value = evaluate_expr(scrutinee, env)?;
+            
+            for &i in dispatch.candidates(&value) {
+                let MatchArm { pattern, body, .. } = &arms[i];
+                let mut bindings = Vec::new();
+                if match_pattern(pattern, &value, &mut bindings) {
+                    env.push_scope();
+                    for (index, (_, bound)) in bindings.into_iter().enumerate() {
+                        env.define(index, bound);
+                    }
+                    let result = execute_statements(body, env);
+                    env.pop_scope();
+                    return result;
+                }
+            }
+            
+            Err(format!("No match arm matches value {}", describe_value(&value)))
+        },
+        
         Expr::Output(args) => {
             let mut values = Vec::new();
             for arg in args {
@@ -110,13 +580,11 @@ fn evaluate_expr(expr: &Expr, env: &mut Environment) -> Result<Value, String> {
             }
             
             // Print values
-            for (i, value) in values.iter().enumerate() {
-                if i > 0 {
-                    print!(" ");
-                }
-                print_value(value);
+            let mut line = Vec::new();
+            for value in &values {
+                line.push(display_value(value, env)?);
             }
-            println!();
+            println!("{}", line.join(" "));
             io::stdout().flush().map_err(|e| e.to_string())?;
             
             Ok(Value::Null)
@@ -125,100 +593,191 @@ fn evaluate_expr(expr: &Expr, env: &mut Environment) -> Result<Value, String> {
         Expr::OutputFormatted(format_expr) => {
             let format_value = evaluate_expr(format_expr, env)?;
             
-            if let Value::String(format_str) = format_value {
-                // Simple format string handling
-                let result = format_str.replace("{", "").replace("}", "");
-                println!("{}", result);
-                io::stdout().flush().map_err(|e| e.to_string())?;
-            } else {
-                return Err("outputf requires a string argument".to_string());
+            output_formatted(format_value)
+        },
+        
+        Expr::Format { expr, spec } => {
+            let value = evaluate_expr(expr, env)?;
+            Ok(Value::String(format_value(&value, spec, env)?))
+        },
+        
+        Expr::Return { values, .. } => {
+            // Every value is checked against the signature, but a call only
+            // evaluates to the first
+            let mut returned = Vec::with_capacity(values.len().max(1));
+            for value in values {
+                returned.push(evaluate_expr(value, env)?);
             }
-            
+            if returned.is_empty() {
+                returned.push(Value::Null);
+            }
+            let value = returned[0].clone();
+            env.flow = Some(Flow::Return(returned));
+            Ok(value)
+        },
+        
+        Expr::Break { .. } => {
+            env.flow = Some(Flow::Break);
             Ok(Value::Null)
         },
         
-        Expr::Return(values) => {
-            if values.is_empty() {
-                Ok(Value::Null)
-            } else if values.len() == 1 {
-                evaluate_expr(&values[0], env)
-            } else {
-                // For multiple return values, we'd need a tuple type
-                // For simplicity, we'll just return the first value
-                evaluate_expr(&values[0], env)
-            }
+        Expr::Continue { .. } => {
+            env.flow = Some(Flow::Continue);
+            Ok(Value::Null)
         },
         
-        Expr::FunctionCall { name, args } => {
-            if let Some(Value::Function { params, body, .. }) = env.get_function(name).cloned() {
-                // Create a new environment for the function
-                let mut func_env = Environment::new();
-                
-                // Copy function definitions
-                for (fname, fval) in &env.functions {
-                    func_env.define_function(fname.clone(), fval.clone());
-                }
-                
-                // Evaluate arguments and bind to parameters
-                if args.len() != params.len() {
-                    return Err(format!(
-                        "Function '{}' expects {} arguments, got {}",
-                        name,
-                        params.len(),
-                        args.len()
-                    ));
-                }
-                
-                for (i, arg) in args.iter().enumerate() {
-                    let arg_value = evaluate_expr(arg, env)?;
-                    func_env.define(params[i].0.clone(), arg_value);
-                }
-                
-                // Execute function body
-                let mut result = Value::Null;
-                for expr in body {
-                    result = evaluate_expr(&expr, &mut func_env)?;
-                    if matches!(expr, Expr::Return(_)) {
-                        break;
-                    }
+        Expr::If { condition, then_branch, else_if_branches, else_branch } => {
+            if is_truthy(&evaluate_expr(condition, env)?, "If")? {
+                return execute_block(then_branch, env);
+            }
+            
+            for (cond, branch) in else_if_branches {
+                if is_truthy(&evaluate_expr(cond, env)?, "Else-if")? {
+                    return execute_block(branch, env);
                 }
-                
-                Ok(result)
-            } else {
-                Err(format!("Undefined function: {}", name))
+            }
+            
+            match else_branch {
+                Some(branch) => execute_block(branch, env),
+                None => Ok(Value::Null),
             }
         },
         
-        Expr::TypeConversion { expr, target_type } => {
-            let value = evaluate_expr(expr, env)?;
+        Expr::Loop { init, condition, update, body } => {
+            evaluate_loop(init.as_deref(), condition.as_deref(), update.as_deref(), body, env)
+        },
+        
+        Expr::BinaryOp { left, operator, right, .. } => {
+            let left_val = evaluate_expr(left, env)?;
             
-            match (value, target_type) {
-                (Value::Int(n), Type::Float) => Ok(Value::Float(n as f64)),
-                (Value::Float(n), Type::Int) => Ok(Value::Int(n as i64)),
-                (Value::Int(n), Type::String) => Ok(Value::String(n.to_string())),
-                (Value::Float(n), Type::String) => Ok(Value::String(n.to_string())),
-                (Value::Bool(b), Type::String) => Ok(Value::String(b.to_string())),
-                (Value::String(s), Type::Int) => {
-                    match s.parse::<i64>() {
-                        Ok(n) => Ok(Value::Int(n)),
-                        Err(_) => Err(format!("Cannot convert '{}' to int", s)),
-                    }
-                },
-                (Value::String(s), Type::Float) => {
-                    match s.parse::<f64>() {
-                        Ok(n) => Ok(Value::Float(n)),
-                        Err(_) => Err(format!("Cannot convert '{}' to float", s)),
-                    }
-                },
-                (Value::String(s), Type::Bool) => {
-                    match s.to_lowercase().as_str() {
-                        "true" => Ok(Value::Bool(true)),
-                        "false" => Ok(Value::Bool(false)),
-                        _ => Err(format!("Cannot convert '{}' to bool", s)),
-                    }
+            // Logical operators short-circuit
+            match (operator, &left_val) {
+                (BinaryOperator::And, Value::Bool(false)) => return Ok(Value::Bool(false)),
+                (BinaryOperator::Or, Value::Bool(true)) => return Ok(Value::Bool(true)),
+                _ => {}
+            }
+            
+            let right_val = evaluate_expr(right, env)?;
+            if let Some(result) = compare_with_methods(&left_val, operator, &right_val, env)? {
+                return Ok(result);
+            }
+            let result = evaluate_binary_op(left_val, operator, right_val)?;
+            env.budget.check_size(&result)?;
+            Ok(result)
+        },
+        
+        Expr::UnaryOp { operator, expr, .. } => {
+            let value = evaluate_expr(expr, env)?;
+            evaluate_unary_op(operator, value)
+        },
+        
+        Expr::FunctionCall { name, args, slot, line } => {
+            // A variable holding a function shadows a named function
+            let func = match slot.and_then(|slot| env.get(slot)) {
+                Some(value @ Value::Function { .. }) => value,
+                Some(value) => return Err(format!("'{}' is not a function: it holds a {}", name, value.get_type())),
+                None if slot.is_some() => return Err(format!("Undefined variable: {}", name)),
+                None => match env.get_function(name) {
+                    Some(value) => value.clone(),
+                    None => match builtins::lookup(name) {
+                        Some((_, builtin)) => {
+                            let mut arg_values = Vec::new();
+                            for arg in args {
+                                arg_values.push(evaluate_expr(arg, env)?);
+                            }
+                            let result = (builtin.call)(&arg_values)?;
+                            env.budget.check_size(&result)?;
+                            return Ok(result);
+                        }
+                        None => return Err(format!("Undefined function: {}", name)),
+                    },
                 },
-                (v, t) => Err(format!("Cannot convert {:?} to {:?}", v, t)),
+            };
+            
+            let mut arg_values = Vec::new();
+            for arg in args {
+                arg_values.push(evaluate_expr(arg, env)?);
+            }
+            
+            call_function(func, arg_values, Some(*line), env)
+        },
+        
+        Expr::Call { callee, args, line } => {
+            let func = evaluate_expr(callee, env)?;
+            if !matches!(func, Value::Function { .. }) {
+                return Err(format!("Cannot call a value of type {}", func.get_type()));
+            }
+            
+            let mut arg_values = Vec::new();
+            for arg in args {
+                arg_values.push(evaluate_expr(arg, env)?);
+            }
+            
+            call_function(func, arg_values, Some(*line), env)
+        },
+        
+        Expr::MethodCall { target, method, args, line, receiver: receiver_type } => {
+            let receiver = evaluate_expr(target, env)?;
+            
+            let mut arg_values = Vec::new();
+            for arg in args {
+                arg_values.push(evaluate_expr(arg, env)?);
+            }
+            
+            // A record field holding a function is called like a method
+            if let Value::Record { fields, .. } = &receiver {
+                if let Some((_, func)) = fields.iter().find(|(f, _)| f == method) {
+                    return call_function(func.clone(), arg_values, Some(*line), env);
+                }
             }
+            
+            arg_values.insert(0, receiver);
+            call_method(method, receiver_type.as_deref(), arg_values, Some(*line), env)
+        },
+        
+        Expr::Lambda { params, return_types, body, .. } => Ok(Value::Function {
+            name: "anonymous".to_string(),
+            params: params.clone(),
+            return_types: return_types.clone(),
+            body: Rc::new(body.clone()),
+            closure: Some(env.scope.clone()),
+        }),
+        
+        Expr::FunctionDeclaration { name, params, return_types, body, slot, .. } => {
+            // Nested functions are closures bound in the current scope, which
+            // they capture, so they can also call themselves recursively
+            let func = Value::Function {
+                name: name.clone(),
+                params: params.clone(),
+                return_types: return_types.clone(),
+                body: Rc::new(body.clone()),
+                closure: Some(env.scope.clone()),
+            };
+            let slot = slot.ok_or_else(|| format!("Unresolved function: {}", name))?;
+            env.assign(slot, func.clone());
+            Ok(func)
+        },
+        
+        Expr::Cast { expr, target_type } => {
+            let value = evaluate_expr(expr, env)?;
+            cast_value(value, target_type)
+        },
+        
+        Expr::IntToFloat { expr, target_type } => {
+            let value = evaluate_expr(expr, env)?;
+            promote_ints(value, target_type)
+        },
+        
+        Expr::TypeCheck { expr, check_type, is_negated, .. } => {
+            let value = evaluate_expr(expr, env)?;
+            Ok(Value::Bool(value_has_type(&value, check_type) != *is_negated))
+        },
+        
+        Expr::TypeConversion { expr, target_type, .. } => {
+            let value = evaluate_expr(expr, env)?;
+            let result = convert_value(value, target_type)?;
+            env.budget.check_size(&result)?;
+            Ok(result)
         },
         
         // Add other expression types as needed
@@ -226,37 +785,1034 @@ fn evaluate_expr(expr: &Expr, env: &mut Environment) -> Result<Value, String> {
     }
 }
 
-fn print_value(value: &Value) {
-    match value {
-        Value::Int(n) => print!("{}", n),
-        Value::Float(n) => print!("{}", n),
-        Value::String(s) => print!("{}", s),
-        Value::Bool(b) => print!("{}", b),
-        Value::Null => print!("null"),
-        Value::List(items) => {
-            print!("[");
-            for (i, item) in items.iter().enumerate() {
-                if i > 0 {
-                    print!(", ");
-                }
-                print_value(item);
+/// Runs a loop. Variables declared by the loop's initializer are bound
+/// afresh for every iteration, so closures created in the body capture the
+/// value of that iteration; the update runs on the next iteration's copy.
+fn evaluate_loop(
+    init: Option<&Expr>,
+    condition: Option<&Expr>,
+    update: Option<&Expr>,
+    body: &[Expr],
+    env: &mut Environment,
+) -> Result<Value, String> {
+    env.push_scope();
+    let loop_scope = env.scope.clone();
+    
+    let mut loop_vars = Vec::new();
+    if let Some(init_expr) = init {
+        if let Expr::VarDeclaration { value: value_expr, .. } = init_expr {
+            // The loop variable always belongs to the loop, even if an outer
+            // variable has the same name. It is the first variable of each
+            // iteration's scope.
+            loop_vars.push(evaluate_expr(value_expr, env)?);
+        } else {
+            evaluate_expr(init_expr, env)?;
+        }
+    }
+    
+    let result = run_iterations(loop_vars, condition, update, body, env);
+    
+    env.scope = loop_scope;
+    env.pop_scope();
+    result
+}
+
+fn run_iterations(
+    mut loop_vars: Vec<Value>,
+    condition: Option<&Expr>,
+    update: Option<&Expr>,
+    body: &[Expr],
+    env: &mut Environment,
+) -> Result<Value, String> {
+    let loop_scope = env.scope.clone();
+    let count = loop_vars.len();
+    
+    loop {
+        // Fresh bindings for this iteration, copied from the previous one
+        let previous = env.scope.clone();
+        env.scope = Rc::new(Scope::new(Some(loop_scope.clone())));
+        if !Rc::ptr_eq(&previous, &loop_scope) {
+            env.budget.step()?;
+            loop_vars = previous.variables.borrow()[..count].to_vec();
+        }
+        for (index, value) in loop_vars.drain(..).enumerate() {
+            env.define(index, value);
+        }
+        
+        // The update runs before every iteration but the first
+        if let Some(update_expr) = update {
+            if !Rc::ptr_eq(&previous, &loop_scope) {
+                evaluate_expr(update_expr, env)?;
+            }
+        }
+        
+        if let Some(cond_expr) = condition {
+            if !is_truthy(&evaluate_expr(cond_expr, env)?, "Loop")? {
+                break;
             }
-            print!("]");
+        }
+        
+        execute_block(body, env)?;
+        
+        match env.flow {
+            Some(Flow::Break) => {
+                env.flow = None;
+                break;
+            }
+            Some(Flow::Continue) => env.flow = None,
+            Some(Flow::Return(_)) => break,
+            None => {}
+        }
+    }
+    
+    Ok(Value::Null)
+}
+
+/// Tests `value` against `pattern`, collecting the variables it binds
+pub(crate) fn match_pattern(pattern: &Pattern, value: &Value, bindings: &mut Vec<(String, Value)>) -> bool {
+    match (pattern, value) {
+        (Pattern::Wildcard, _) => true,
+        (Pattern::Binding(name), _) => {
+            bindings.push((name.clone(), value.clone()));
+            true
+        }
+        (Pattern::Literal(literal), _) => match (literal, value) {
+            (Expr::IntLiteral(a), Value::Int(b)) => a == b,
+            (Expr::BigIntLiteral(a), Value::BigInt(b)) => a == b,
+            (Expr::DecimalLiteral(a), Value::Decimal(b)) => a == b,
+            (Expr::IntLiteral(a), Value::Float(b)) => *a as f64 == *b,
+            (Expr::FloatLiteral(a), Value::Float(b)) => a == b,
+            (Expr::FloatLiteral(a), Value::Int(b)) => *a == *b as f64,
+            (Expr::CharLiteral(a), Value::Char(b)) => a == b,
+            (Expr::StringLiteral(a), Value::String(b)) => a == b,
+            (Expr::BytesLiteral(a), Value::Bytes(b)) => a == b,
+            (Expr::BoolLiteral(a), Value::Bool(b)) => a == b,
+            (Expr::NullLiteral, Value::Null) => true,
+            _ => false,
         },
-        Value::Map(entries) => {
-            print!("[");
-            for (i, (k, v)) in entries.iter().enumerate() {
-                if i > 0 {
-                    print!(", ");
+        (
+            Pattern::Variant { variant, fields: field_patterns, .. },
+            Value::Variant { variant: value_variant, fields, .. },
+        ) => {
+            variant == value_variant
+                && field_patterns.len() == fields.len()
+                && field_patterns
+                    .iter()
+                    .zip(fields)
+                    .all(|(p, v)| match_pattern(p, v, bindings))
+        }
+        (Pattern::List { items: item_patterns, rest }, Value::List(items)) => {
+            let length_ok = match rest {
+                Some(_) => items.len() >= item_patterns.len(),
+                None => items.len() == item_patterns.len(),
+            };
+            if !length_ok {
+                return false;
+            }
+            if !item_patterns.iter().zip(items).all(|(p, v)| match_pattern(p, v, bindings)) {
+                return false;
+            }
+            match rest {
+                Some(rest_pattern) => {
+                    let remaining = Value::List(items.skip(item_patterns.len()));
+                    match_pattern(rest_pattern, &remaining, bindings)
                 }
-                print_value(k);
-                print!(":");
-                print_value(v);
+                None => true,
+            }
+        }
+        (Pattern::Record { name, fields: field_patterns }, Value::Record { name: value_name, fields }) => {
+            name == value_name
+                && field_patterns.iter().all(|(field, p)| {
+                    fields
+                        .iter()
+                        .find(|(f, _)| f == field)
+                        .is_some_and(|(_, v)| match_pattern(p, v, bindings))
+                })
+        }
+        _ => false,
+    }
+}
+
+pub(crate) fn get_field(target: &Value, field: &str) -> Result<Value, String> {
+    match target {
+        Value::Record { name, fields } => fields
+            .iter()
+            .find(|(f, _)| f == field)
+            .map(|(_, v)| v.clone())
+            .ok_or_else(|| format!("Type '{}' has no field '{}'", name, field)),
+        other => Err(format!("Cannot access field '{}' of {}", field, other.get_type())),
+    }
+}
+
+pub(crate) fn set_field(record: &mut Value, field: &str, value: Value) -> Result<(), String> {
+    match record {
+        Value::Record { name, fields } => match fields.iter_mut().find(|(f, _)| f == field) {
+            Some((_, slot)) => {
+                *slot = value;
+                Ok(())
+            }
+            None => Err(format!("Type '{}' has no field '{}'", name, field)),
+        },
+        other => Err(format!("Cannot assign field '{}' of {}", field, other.get_type())),
+    }
+}
+
+/// Sets a list element or a map entry, adding the entry if the map doesn't
+/// have the key yet
+pub(crate) fn set_index(target: &mut Value, index: Value, value: Value) -> Result<(), String> {
+    match (target, index) {
+        (Value::List(items), Value::Int(i)) => {
+            if i < 0 || i as usize >= items.len() {
+                return Err(format!("List index {} out of bounds for list of length {}", i, items.len()));
+            }
+            items.set(i as usize, value);
+            Ok(())
+        },
+        (Value::Bytes(bytes), Value::Int(i)) => {
+            if i < 0 || i as usize >= bytes.len() {
+                return Err(format!("Bytes index {} out of bounds for bytes of length {}", i, bytes.len()));
+            }
+            bytes[i as usize] = match value {
+                Value::Int(byte) => u8::try_from(byte).map_err(|_| format!("A byte must be from 0 to 255, got {}", byte))?,
+                other => return Err(format!("A byte must be an int, got {}", other.get_type())),
+            };
+            Ok(())
+        },
+        (Value::Map(map), key) => map.insert(key, value),
+        (target, index) => Err(format!("Cannot assign to index {} of {}", index.get_type(), target.get_type())),
+    }
+}
+
+/// A step from a variable to the part of it that an assignment changes
+pub(crate) enum Accessor<'a> {
+    Field(&'a str),
+    Index(Value),
+}
+
+/// Returns `target` with the part at the end of `path` replaced by `value`.
+/// Values are copied on assignment, so this is how `p.x = 1` or
+/// `xs[i].name = "a"` change a variable: only the values along the path are
+/// copied, everything else is shared with the old value.
+pub(crate) fn update_path(mut target: Value, path: &[Accessor], value: Value, budget: &Budget) -> Result<Value, String> {
+    let Some((step, rest)) = path.split_first() else {
+        return Ok(value);
+    };
+    match step {
+        Accessor::Field(field) => {
+            let inner = if rest.is_empty() { Value::Null } else { get_field(&target, field)? };
+            set_field(&mut target, field, update_path(inner, rest, value, budget)?)?;
+        }
+        Accessor::Index(index) => {
+            let inner = if rest.is_empty() { Value::Null } else { index_value(target.clone(), index.clone())? };
+            set_index(&mut target, index.clone(), update_path(inner, rest, value, budget)?)?;
+            // Assigning to a new key makes a map bigger
+            budget.check_size(&target)?;
+        }
+    }
+    Ok(target)
+}
+
+pub(crate) fn index_value(target: Value, index: Value) -> Result<Value, String> {
+    match (target, index) {
+        (Value::List(items), Value::Int(i)) => {
+            if i < 0 || i as usize >= items.len() {
+                return Err(format!("List index {} out of bounds for list of length {}", i, items.len()));
+            }
+            Ok(items[i as usize].clone())
+        },
+        // Strings are indexed by character, not by byte
+        (Value::String(s), Value::Int(i)) => {
+            match usize::try_from(i).ok().and_then(|i| s.chars().nth(i)) {
+                Some(c) => Ok(Value::Char(c)),
+                None => Err(format!("String index {} out of bounds for string of length {}", i, s.chars().count())),
+            }
+        },
+        (Value::Bytes(bytes), Value::Int(i)) => {
+            if i < 0 || i as usize >= bytes.len() {
+                return Err(format!("Bytes index {} out of bounds for bytes of length {}", i, bytes.len()));
+            }
+            Ok(Value::Int(bytes[i as usize] as i64))
+        },
+        (Value::Map(map), key) => {
+            match map.get(&key)? {
+                Some(value) => Ok(value.clone()),
+                None => Err(format!("Key not found in map: {}", describe_value(&key))),
+            }
+        },
+        (target, index) => Err(format!("Cannot index {} with {}", target.get_type(), index.get_type())),
+    }
+}
+
+/// `target[start:end]` of a list, string or bytes, from `start` up to but
+/// not including `end`. A null bound is the start or the end of the value.
+pub(crate) fn slice_value(target: Value, start: Value, end: Value) -> Result<Value, String> {
+    let length = match &target {
+        Value::List(items) => items.len(),
+        Value::String(s) => s.chars().count(),
+        Value::Bytes(bytes) => bytes.len(),
+        other => return Err(format!("Cannot slice {}", other.get_type())),
+    };
+    let bound = |bound: Value, default: usize| match bound {
+        Value::Null => Ok(default),
+        Value::Int(i) if i >= 0 && i as usize <= length => Ok(i as usize),
+        Value::Int(i) => Err(format!("Slice bound {} out of bounds for {} of length {}", i, target.get_type(), length)),
+        other => Err(format!("Slice bounds must be ints, got {}", other.get_type())),
+    };
+    let start = bound(start, 0)?;
+    let end = bound(end, length)?;
+    if start > end {
+        return Err(format!("Slice start {} is after its end {}", start, end));
+    }
+    
+    Ok(match target {
+        Value::List(items) => Value::List(items.skip(start).take(end - start)),
+        Value::String(s) => Value::String(s.chars().skip(start).take(end - start).collect()),
+        Value::Bytes(bytes) => Value::Bytes(bytes[start..end].to_vec()),
+        _ => unreachable!(),
+    })
+}
+
+pub(crate) fn cast_value(value: Value, target_type: &Type) -> Result<Value, String> {
+    if value_has_type(&value, target_type) {
+        promote_ints(value, target_type)
+    } else {
+        Err(format!("Cannot cast value of type {} to {}", value.get_type(), target_type))
+    }
+}
+
+pub(crate) fn output_formatted(format_value: Value) -> Result<Value, String> {
+    // The parser has already put the values into a literal format string
+    if let Value::String(text) = format_value {
+        println!("{}", text);
+        io::stdout().flush().map_err(|e| e.to_string())?;
+    } else {
+        return Err("outputf requires a string argument".to_string());
+    }
+    
+    Ok(Value::Null)
+}
+
+/// The variable an assignment target like `p.x` or `xs[i].name` is in, its
+/// current value, and the path to the part being changed. Indices are
+/// evaluated from the variable outwards.
+fn assignment_path<'a>(target: &'a Expr, env: &mut Environment) -> Result<(VarSlot, Value, Vec<Accessor<'a>>), String> {
+    let mut places = Vec::new();
+    let mut root = target;
+    while let Expr::FieldAccess { target, .. } | Expr::Index { target, .. } = root {
+        places.push(root);
+        root = target;
+    }
+    
+    let (name, slot) = match root {
+        Expr::Identifier { name, slot: Some(slot) } => (name, *slot),
+        Expr::Identifier { name, slot: None } => return Err(format!("Cannot assign to function '{}'", name)),
+        other => return Err(format!("Invalid assignment target: {:?}", other)),
+    };
+    let current = env.get(slot).ok_or_else(|| format!("Undefined variable: {}", name))?;
+    
+    let mut path = Vec::new();
+    for place in places.into_iter().rev() {
+        path.push(match place {
+            Expr::FieldAccess { field, .. } => Accessor::Field(field),
+            Expr::Index { index, .. } => Accessor::Index(evaluate_expr(index, env)?),
+            _ => unreachable!(),
+        });
+    }
+    Ok((slot, current, path))
+}
+
+/// Whether `value` is a value of type `ty`, looking inside collections
+pub(crate) fn value_has_type(value: &Value, ty: &Type) -> bool {
+    match (value, ty.resolved()) {
+        (_, Type::Any) => true,
+        // Type parameters aren't known at runtime
+        (_, Type::Param(_)) => true,
+        (Value::Int(_), Type::Int | Type::Float) => true,
+        (Value::BigInt(_), Type::BigInt) => true,
+        (Value::Float(_), Type::Float) => true,
+        (Value::Decimal(_), Type::Decimal) => true,
+        (Value::Char(_), Type::Char) => true,
+        (Value::String(_), Type::String) => true,
+        (Value::Bytes(_), Type::Bytes) => true,
+        (Value::Bool(_), Type::Bool) => true,
+        (Value::Null, Type::Null) => true,
+        (Value::List(items), Type::List(elem_type)) => items.iter().all(|item| value_has_type(item, elem_type)),
+        (Value::Map(map), Type::Map(key_type, val_type)) => map
+            .iter()
+            .all(|(k, v)| value_has_type(k, key_type) && value_has_type(v, val_type)),
+        (Value::Record { name, .. }, Type::Record(expected)) => name == expected,
+        (Value::Variant { enum_name, .. }, Type::Enum(expected)) => enum_name == expected,
+        // Function values carry the types they were declared with, which the
+        // type checker already compared; only the shape is checked here
+        (Value::Function { params, return_types, .. }, Type::Function { params: expected_params, returns, .. }) => {
+            params.len() == expected_params.len() && return_types.len() == returns.len()
+        }
+        (Value::Closure(closure), Type::Function { params: expected_params, returns, .. }) => {
+            closure.proto.params.len() == expected_params.len() && closure.proto.return_types.len() == returns.len()
+        }
+        _ => false,
+    }
+}
+
+/// Whether `value` can be a value of type `ty` without looking inside lists
+/// and maps, which would make a call cost as much as its arguments' size
+fn value_has_shape(value: &Value, ty: &Type) -> bool {
+    match (value, ty.resolved()) {
+        (Value::List(_), Type::List(_)) | (Value::Map(_), Type::Map(..)) => true,
+        _ => value_has_type(value, ty),
+    }
+}
+
+/// An int that got past the type checker through `any` as a float
+fn int_as_float(value: Value, ty: &Type) -> Value {
+    match (value, ty.resolved()) {
+        (Value::Int(n), Type::Float) => Value::Float(n as f64),
+        (value, _) => value,
+    }
+}
+
+/// Checks an argument against the type of its parameter, in case the value
+/// got past the type checker through `any`. The type checker has already
+/// made ints floats where they need to be.
+pub(crate) fn check_argument(function: &str, param_name: &str, param_type: &Type, value: Value) -> Result<Value, String> {
+    if !value_has_shape(&value, param_type) {
+        return Err(format!(
+            "Function '{}' parameter '{}' expects {}, got {}",
+            function,
+            param_name,
+            param_type,
+            value.get_type()
+        ));
+    }
+    Ok(int_as_float(value, param_type))
+}
+
+/// Checks every value of a `return` against the function's return types and
+/// gives the first, which is the one a call evaluates to
+pub(crate) fn check_returns(function: &str, values: Vec<Value>, return_types: &[Type]) -> Result<Value, String> {
+    let mut checked = Vec::with_capacity(values.len());
+    for (index, (value, return_type)) in values.into_iter().zip(return_types).enumerate() {
+        if !value_has_shape(&value, return_type) {
+            let position = if return_types.len() > 1 { format!(" as value {}", index + 1) } else { String::new() };
+            return Err(format!(
+                "Function '{}' returns {}{}, got {}",
+                function,
+                return_type,
+                position,
+                value.get_type()
+            ));
+        }
+        checked.push(int_as_float(value, return_type));
+    }
+    Ok(checked.into_iter().next().unwrap_or(Value::Null))
+}
+
+/// `value`, which has type `ty`, with the ints in it that `ty` says are
+/// floats turned into floats, as the type checker lets an int be a float
+pub(crate) fn promote_ints(value: Value, ty: &Type) -> Result<Value, String> {
+    if !mentions_float(ty) {
+        return Ok(value);
+    }
+    Ok(match (value, ty.resolved()) {
+        (Value::Int(n), Type::Float) => Value::Float(n as f64),
+        (Value::List(items), Type::List(elem_type)) => Value::List(
+            items
+                .into_iter()
+                .map(|item| promote_ints(item, elem_type))
+                .collect::<Result<_, _>>()?,
+        ),
+        (Value::Map(map), Type::Map(key_type, val_type)) => {
+            let mut promoted = MapValue::new();
+            for (key, value) in map.iter().cloned() {
+                promoted.insert(promote_ints(key, key_type)?, promote_ints(value, val_type)?)?;
+            }
+            Value::Map(promoted)
+        }
+        (value, _) => value,
+    })
+}
+
+fn mentions_float(ty: &Type) -> bool {
+    match ty.resolved() {
+        Type::Float => true,
+        Type::List(elem_type) => mentions_float(elem_type),
+        Type::Map(key_type, val_type) => mentions_float(key_type) || mentions_float(val_type),
+        _ => false,
+    }
+}
+
+pub(crate) fn is_truthy(value: &Value, context: &str) -> Result<bool, String> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        other => Err(format!("{} condition must be boolean, got {}", context, other.get_type())),
+    }
+}
+
+/// Stack space left when a call moves to a new stack segment, and the size
+/// of the segments. A call in a debug build takes around 100 KiB of stack.
+pub(crate) const STACK_RED_ZONE: usize = 1024 * 1024;
+pub(crate) const STACK_SEGMENT_SIZE: usize = 16 * 1024 * 1024;
+
+/// Calls a function. Calls recurse on the native stack, which is extended
+/// on the heap as needed, so it is the call depth limit that stops a
+/// runaway recursion.
+fn call_function(func: Value, args: Vec<Value>, line: Option<usize>, env: &Environment) -> Result<Value, String> {
+    let name = match &func {
+        Value::Function { name, .. } => name.clone(),
+        other => return Err(format!("Cannot call a value of type {}", other.get_type())),
+    };
+    
+    let limit = env.budget.limits.max_call_depth;
+    if env.call_stack.borrow().len() >= limit {
+        return Err(call_depth_error(limit, &name));
+    }
+    env.budget.step()?;
+    
+    env.call_stack.borrow_mut().push(CallFrame { name, line });
+    let result = stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT_SIZE, || run_function(func, args, env));
+    // A failed call stays on the stack for the error's traceback
+    if result.is_ok() {
+        env.call_stack.borrow_mut().pop();
+    }
+    result
+}
+
+fn run_function(func: Value, args: Vec<Value>, env: &Environment) -> Result<Value, String> {
+    let (name, params, return_types, body, closure) = match func {
+        Value::Function { name, params, return_types, body, closure } => (name, params, return_types, body, closure),
+        other => return Err(format!("Cannot call a value of type {}", other.get_type())),
+    };
+    
+    if args.len() != params.len() {
+        return Err(format!(
+            "Function '{}' expects {} arguments, got {}",
+            name,
+            params.len(),
+            args.len()
+        ));
+    }
+    
+    // Create a new environment for the function, nested inside the scope the
+    // function captured when it was created
+    let mut func_env = Environment::with_scope(Rc::new(Scope::new(closure)));
+    
+    func_env.functions = env.functions.clone();
+    func_env.records = env.records.clone();
+    func_env.enums = env.enums.clone();
+    func_env.methods = env.methods.clone();
+    func_env.call_stack = env.call_stack.clone();
+    func_env.budget = env.budget.clone();
+    
+    // Bind arguments to parameters, checking them against the signature
+    for (index, ((param_name, param_type), arg_value)) in params.iter().zip(args).enumerate() {
+        let arg_value = check_argument(&name, param_name, param_type, arg_value)?;
+        func_env.define(index, arg_value);
+    }
+    
+    // Execute function body
+    let result = execute_statements(&body, &mut func_env)?;
+    
+    let values = match func_env.flow {
+        Some(Flow::Return(values)) => values,
+        Some(_) => return Err(format!("'break' or 'continue' outside of a loop in function '{}'", name)),
+        // Without a return the function gives the value of its last statement,
+        // which only a function declared to return nothing can do
+        None => return Ok(result),
+    };
+    
+    check_returns(&name, values, &return_types)
+}
+
+/// Calls the interface method `method` of `args[0]`, chosen by the
+/// receiver's type
+/// Calls `method` of `args[0]`, the implementation of `receiver` when the
+/// type checker knows the receiver's type, or else of the value's own type
+pub(crate) fn call_method(
+    method: &str,
+    receiver: Option<&str>,
+    args: Vec<Value>,
+    line: Option<usize>,
+    methods: &mut dyn Methods,
+) -> Result<Value, String> {
+    let type_name = match receiver {
+        Some(type_name) => type_name.to_string(),
+        None => args[0].get_type().to_string(),
+    };
+    
+    if methods.has_method(&type_name, method) {
+        return methods.call_impl(&type_name, method, args, line);
+    }
+    
+    // Show, Eq and Ord of the primitive types
+    match (method, args.as_slice()) {
+        ("show", [receiver]) if is_primitive(receiver) => Ok(Value::String(display_value(receiver, methods)?)),
+        ("eq", [receiver, other]) if is_primitive(receiver) => Ok(Value::Bool(receiver == other)),
+        ("cmp", [receiver, other]) if is_primitive(receiver) => {
+            let ordering = match (receiver, other) {
+                (Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
+                (Value::BigInt(a), Value::BigInt(b)) => a.partial_cmp(b),
+                (Value::Decimal(a), Value::Decimal(b)) => a.partial_cmp(b),
+                (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+                (Value::Char(a), Value::Char(b)) => a.partial_cmp(b),
+                (Value::String(a), Value::String(b)) => a.partial_cmp(b),
+                (Value::Bytes(a), Value::Bytes(b)) => a.partial_cmp(b),
+                _ => None,
+            };
+            let ordering = ordering.ok_or_else(|| format!("Cannot compare {} and {}", receiver.get_type(), other.get_type()))?;
+            Ok(Value::Int(ordering as i64))
+        }
+        _ => Err(format!("Type {} has no method '{}'", type_name, method)),
+    }
+}
+
+fn is_primitive(value: &Value) -> bool {
+    matches!(
+        value,
+        Value::Int(_)
+            | Value::BigInt(_)
+            | Value::Float(_)
+            | Value::Decimal(_)
+            | Value::Char(_)
+            | Value::String(_)
+            | Value::Bytes(_)
+            | Value::Bool(_)
+    )
+}
+
+/// Compares records and enum values with their Eq and Ord implementations,
+/// if they have them. Returns `None` to fall back to the built-in comparison.
+pub(crate) fn compare_with_methods(
+    left: &Value,
+    operator: &BinaryOperator,
+    right: &Value,
+    methods: &mut dyn Methods,
+) -> Result<Option<Value>, String> {
+    let type_name = match left {
+        Value::Record { name, .. } => name,
+        Value::Variant { enum_name, .. } => enum_name,
+        _ => return Ok(None),
+    };
+    
+    let method = match operator {
+        BinaryOperator::Equal | BinaryOperator::NotEqual => "eq",
+        BinaryOperator::LessThan | BinaryOperator::LessThanOrEqual |
+        BinaryOperator::GreaterThan | BinaryOperator::GreaterThanOrEqual => "cmp",
+        _ => return Ok(None),
+    };
+    if !methods.has_method(type_name, method) {
+        return Ok(None);
+    }
+    
+    let result = methods.call_impl(type_name, method, vec![left.clone(), right.clone()], None)?;
+    let value = match (operator, result) {
+        (BinaryOperator::Equal, Value::Bool(b)) => b,
+        (BinaryOperator::NotEqual, Value::Bool(b)) => !b,
+        (BinaryOperator::LessThan, Value::Int(n)) => n < 0,
+        (BinaryOperator::LessThanOrEqual, Value::Int(n)) => n <= 0,
+        (BinaryOperator::GreaterThan, Value::Int(n)) => n > 0,
+        (BinaryOperator::GreaterThanOrEqual, Value::Int(n)) => n >= 0,
+        (_, other) => return Err(format!("'{}.{}' returned {}", type_name, method, other.get_type())),
+    };
+    Ok(Some(Value::Bool(value)))
+}
+
+pub(crate) fn evaluate_unary_op(operator: &UnaryOperator, value: Value) -> Result<Value, String> {
+    match (operator, value) {
+        (UnaryOperator::Negate, Value::Int(n)) => {
+            n.checked_neg().map(Value::Int).ok_or_else(|| format!("Integer overflow: -({})", n))
+        },
+        (UnaryOperator::Negate, Value::BigInt(n)) => Ok(Value::BigInt(-n)),
+        (UnaryOperator::Negate, Value::Float(n)) => Ok(Value::Float(-n)),
+        (UnaryOperator::Negate, Value::Decimal(n)) => Ok(Value::Decimal(-n)),
+        (UnaryOperator::Not, Value::Bool(b)) => Ok(Value::Bool(!b)),
+        (op, v) => Err(format!("Cannot apply {:?} to {}", op, v.get_type())),
+    }
+}
+
+/// Converts a value for `int(x)`, `string(x)` and friends
+pub(crate) fn convert_value(value: Value, target_type: &Type) -> Result<Value, String> {
+    match (value, target_type) {
+        (Value::Int(n), Type::Float) => Ok(Value::Float(n as f64)),
+        // Floats are truncated, and must be in the range of an int
+        (Value::Float(n), Type::Int) => {
+            if n.is_nan() {
+                Err("Cannot convert nan to int".to_string())
+            } else if n >= i64::MIN as f64 && n < -(i64::MIN as f64) {
+                Ok(Value::Int(n as i64))
+            } else {
+                Err(format!("Cannot convert {} to int: out of range", format_float(n)))
             }
-            print!("]");
         },
-        Value::Function { name, .. } => {
-            print!("<function {}>", name);
+        (Value::Int(n), Type::BigInt) => Ok(Value::BigInt(n.into())),
+        (Value::Float(n), Type::BigInt) => match BigInt::from_f64(n.trunc()) {
+            Some(n) => Ok(Value::BigInt(n)),
+            None => Err(format!("Cannot convert {} to bigint", format_float(n))),
+        },
+        (Value::BigInt(n), Type::Int) => match n.to_i64() {
+            Some(n) => Ok(Value::Int(n)),
+            None => Err(format!("Cannot convert {} to int: out of range", n)),
+        },
+        (Value::BigInt(n), Type::Float) => Ok(Value::Float(bigint_to_f64(&n))),
+        (Value::Int(n), Type::String) => Ok(Value::String(n.to_string())),
+        (Value::BigInt(n), Type::String) => Ok(Value::String(n.to_string())),
+        // Floats become the shortest decimal that prints the same, so
+        // `decimal(0.1)` is 0.1 and not the float's exact binary value
+        (Value::Int(n), Type::Decimal) => Ok(Value::Decimal(n.into())),
+        (Value::BigInt(n), Type::Decimal) => match n.to_i128().and_then(Decimal::from_i128) {
+            Some(n) => Ok(Value::Decimal(n)),
+            None => Err(format!("Cannot convert {} to decimal: out of range", n)),
+        },
+        (Value::Float(n), Type::Decimal) => match Decimal::from_f64(n) {
+            Some(n) => Ok(Value::Decimal(n)),
+            None => Err(format!("Cannot convert {} to decimal", format_float(n))),
         },
+        (Value::Decimal(n), Type::Int) => match n.trunc().to_i64() {
+            Some(n) => Ok(Value::Int(n)),
+            None => Err(format!("Cannot convert {} to int: out of range", n)),
+        },
+        (Value::Decimal(n), Type::BigInt) => Ok(Value::BigInt(n.trunc().to_i128().unwrap().into())),
+        (Value::Decimal(n), Type::Float) => Ok(Value::Float(n.to_f64().unwrap())),
+        (Value::Decimal(n), Type::String) => Ok(Value::String(n.to_string())),
+        (Value::Float(n), Type::String) => Ok(Value::String(format_float(n))),
+        (Value::Bool(b), Type::String) => Ok(Value::String(b.to_string())),
+        // A char converts to and from its Unicode code point
+        (Value::Char(c), Type::Int) => Ok(Value::Int(c as i64)),
+        (Value::Int(n), Type::Char) => match u32::try_from(n).ok().and_then(char::from_u32) {
+            Some(c) => Ok(Value::Char(c)),
+            None => Err(format!("Cannot convert {} to char: not a Unicode code point", n)),
+        },
+        (Value::Char(c), Type::String) => Ok(Value::String(c.to_string())),
+        (Value::String(s), Type::Char) => {
+            let mut chars = s.chars();
+            match (chars.next(), chars.next()) {
+                (Some(c), None) => Ok(Value::Char(c)),
+                _ => Err(format!("Cannot convert '{}' to char: it must have exactly one character", s)),
+            }
+        },
+        // Strings convert to and from their UTF-8 encoding
+        (Value::String(s), Type::Bytes) => Ok(Value::Bytes(s.into_bytes())),
+        (Value::Bytes(bytes), Type::String) => match String::from_utf8(bytes) {
+            Ok(s) => Ok(Value::String(s)),
+            Err(e) => Err(format!("Cannot convert bytes to string: invalid UTF-8 at byte {}", e.utf8_error().valid_up_to())),
+        },
+        (Value::String(s), Type::Int) => {
+            match s.parse::<i64>() {
+                Ok(n) => Ok(Value::Int(n)),
+                Err(_) => Err(format!("Cannot convert '{}' to int", s)),
+            }
+        },
+        (Value::String(s), Type::BigInt) => {
+            match s.parse::<BigInt>() {
+                Ok(n) => Ok(Value::BigInt(n)),
+                Err(_) => Err(format!("Cannot convert '{}' to bigint", s)),
+            }
+        },
+        (Value::String(s), Type::Decimal) => {
+            match Decimal::from_str_exact(&s) {
+                Ok(n) => Ok(Value::Decimal(n)),
+                Err(_) => Err(format!("Cannot convert '{}' to decimal", s)),
+            }
+        },
+        (Value::String(s), Type::Float) => {
+            match s.parse::<f64>() {
+                Ok(n) => Ok(Value::Float(n)),
+                Err(_) => Err(format!("Cannot convert '{}' to float", s)),
+            }
+        },
+        (Value::String(s), Type::Bool) => {
+            match s.to_lowercase().as_str() {
+                "true" => Ok(Value::Bool(true)),
+                "false" => Ok(Value::Bool(false)),
+                _ => Err(format!("Cannot convert '{}' to bool", s)),
+            }
+        },
+        (v, t) => Err(format!("Cannot convert {} to {}", v.get_type(), t)),
     }
 }
+
+/// Bigints too big for a float become infinity
+fn bigint_to_f64(n: &BigInt) -> f64 {
+    n.to_f64().unwrap_or(if n.sign() == num_bigint::Sign::Minus { f64::NEG_INFINITY } else { f64::INFINITY })
+}
+
+pub(crate) fn evaluate_binary_op(left: Value, operator: &BinaryOperator, right: Value) -> Result<Value, String> {
+    match (left, right) {
+        // Int arithmetic that overflows is an error, the builtins like
+        // `wrapping_add` give a result instead
+        (Value::Int(a), Value::Int(b)) => {
+            let (result, symbol) = match operator {
+                BinaryOperator::Add => (a.checked_add(b), "+"),
+                BinaryOperator::Subtract => (a.checked_sub(b), "-"),
+                BinaryOperator::Multiply => (a.checked_mul(b), "*"),
+                BinaryOperator::Divide | BinaryOperator::Modulo if b == 0 => {
+                    return Err("Division by zero".to_string());
+                }
+                BinaryOperator::Divide => (a.checked_div(b), "/"),
+                // The quotient of the smallest int and -1 overflows, but the
+                // remainder is 0
+                BinaryOperator::Modulo => (Some(a.wrapping_rem(b)), "%"),
+                _ => return compare_values(&Value::Int(a), operator, &Value::Int(b)),
+            };
+            result.map(Value::Int).ok_or_else(|| format!("Integer overflow: {} {} {}", a, symbol, b))
+        },
+        (Value::BigInt(a), Value::BigInt(b)) => {
+            let result = match operator {
+                BinaryOperator::Add => a + b,
+                BinaryOperator::Subtract => a - b,
+                BinaryOperator::Multiply => a * b,
+                BinaryOperator::Divide | BinaryOperator::Modulo if b.is_zero() => {
+                    return Err("Division by zero".to_string());
+                }
+                // Rounding towards zero, like ints
+                BinaryOperator::Divide => a / b,
+                BinaryOperator::Modulo => a % b,
+                _ => return compare_values(&Value::BigInt(a), operator, &Value::BigInt(b)),
+            };
+            Ok(Value::BigInt(result))
+        },
+        // Decimal arithmetic is exact, apart from division, which rounds to
+        // 28 significant digits
+        (Value::Decimal(a), Value::Decimal(b)) => {
+            let (result, symbol) = match operator {
+                BinaryOperator::Add => (a.checked_add(b), "+"),
+                BinaryOperator::Subtract => (a.checked_sub(b), "-"),
+                BinaryOperator::Multiply => (a.checked_mul(b), "*"),
+                BinaryOperator::Divide | BinaryOperator::Modulo if b.is_zero() => {
+                    return Err("Division by zero".to_string());
+                }
+                BinaryOperator::Divide => (a.checked_div(b), "/"),
+                BinaryOperator::Modulo => (a.checked_rem(b), "%"),
+                _ => return compare_values(&Value::Decimal(a), operator, &Value::Decimal(b)),
+            };
+            result.map(Value::Decimal).ok_or_else(|| format!("Decimal overflow: {} {} {}", a, symbol, b))
+        },
+        (Value::Int(a), Value::Decimal(b)) => evaluate_binary_op(Value::Decimal(a.into()), operator, Value::Decimal(b)),
+        (Value::Decimal(a), Value::Int(b)) => evaluate_binary_op(Value::Decimal(a), operator, Value::Decimal(b.into())),
+        // Mixed int and bigint arithmetic is done in bigint, and mixed with
+        // float in float
+        (Value::Int(a), Value::BigInt(b)) => evaluate_binary_op(Value::BigInt(a.into()), operator, Value::BigInt(b)),
+        (Value::BigInt(a), Value::Int(b)) => evaluate_binary_op(Value::BigInt(a), operator, Value::BigInt(b.into())),
+        (Value::Int(a), Value::Float(b)) => evaluate_binary_op(Value::Float(a as f64), operator, Value::Float(b)),
+        (Value::Float(a), Value::Int(b)) => evaluate_binary_op(Value::Float(a), operator, Value::Float(b as f64)),
+        (Value::BigInt(a), Value::Float(b)) => evaluate_binary_op(Value::Float(bigint_to_f64(&a)), operator, Value::Float(b)),
+        (Value::Float(a), Value::BigInt(b)) => evaluate_binary_op(Value::Float(a), operator, Value::Float(bigint_to_f64(&b))),
+        (Value::Float(a), Value::Float(b)) => match operator {
+            BinaryOperator::Add => Ok(Value::Float(a + b)),
+            BinaryOperator::Subtract => Ok(Value::Float(a - b)),
+            BinaryOperator::Multiply => Ok(Value::Float(a * b)),
+            BinaryOperator::Divide => Ok(Value::Float(a / b)),
+            BinaryOperator::Modulo => Ok(Value::Float(a % b)),
+            _ => compare_values(&Value::Float(a), operator, &Value::Float(b)),
+        },
+        (Value::String(a), Value::String(b)) if *operator == BinaryOperator::Add => {
+            Ok(Value::String(a + &b))
+        }
+        (Value::Bytes(mut a), Value::Bytes(b)) if *operator == BinaryOperator::Add => {
+            a.extend(b);
+            Ok(Value::Bytes(a))
+        }
+        (left, right) => compare_values(&left, operator, &right),
+    }
+}
+
+fn compare_values(left: &Value, operator: &BinaryOperator, right: &Value) -> Result<Value, String> {
+    let ordering = match (left, right) {
+        (Value::Int(a), Value::Int(b)) => a.partial_cmp(b),
+        (Value::BigInt(a), Value::BigInt(b)) => a.partial_cmp(b),
+        (Value::Decimal(a), Value::Decimal(b)) => a.partial_cmp(b),
+        (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+        (Value::Char(a), Value::Char(b)) => a.partial_cmp(b),
+        (Value::String(a), Value::String(b)) => a.partial_cmp(b),
+        (Value::Bytes(a), Value::Bytes(b)) => a.partial_cmp(b),
+        _ => None,
+    };
+    
+    match operator {
+        BinaryOperator::Equal => Ok(Value::Bool(left == right)),
+        BinaryOperator::NotEqual => Ok(Value::Bool(left != right)),
+        BinaryOperator::And | BinaryOperator::Or => match (left, right) {
+            (Value::Bool(a), Value::Bool(b)) => Ok(Value::Bool(if *operator == BinaryOperator::And {
+                *a && *b
+            } else {
+                *a || *b
+            })),
+            _ => Err(format!("Logical operators require booleans, got {} and {}", left.get_type(), right.get_type())),
+        },
+        BinaryOperator::LessThan | BinaryOperator::LessThanOrEqual |
+        BinaryOperator::GreaterThan | BinaryOperator::GreaterThanOrEqual => {
+            let ordering = ordering.ok_or_else(|| {
+                format!("Cannot compare {} and {}", left.get_type(), right.get_type())
+            })?;
+            Ok(Value::Bool(match operator {
+                BinaryOperator::LessThan => ordering.is_lt(),
+                BinaryOperator::LessThanOrEqual => ordering.is_le(),
+                BinaryOperator::GreaterThan => ordering.is_gt(),
+                _ => ordering.is_ge(),
+            }))
+        }
+        _ => Err(format!("Cannot apply {:?} to {} and {}", operator, left.get_type(), right.get_type())),
+    }
+}
+
+/// The text of a float wherever a program shows one: the fewest digits that
+/// read back as the same float, always with a point, so that 3.0 doesn't
+/// print as 3. Numbers from 0.00001 up to 1e17 are written out in full and
+/// the rest in scientific notation, like 1.5e-7 and 2.0e20.
+pub(crate) fn format_float(n: f64) -> String {
+    if n.is_nan() {
+        return "nan".to_string();
+    }
+    if n.is_infinite() {
+        return if n > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+    
+    // `{:e}` gives the shortest digits, like 1.2345e3 or -5e-7
+    let scientific = format!("{:e}", n);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let (sign, mantissa) = match mantissa.strip_prefix('-') {
+        Some(mantissa) => ("-", mantissa),
+        None => ("", mantissa),
+    };
+    let digits = mantissa.replace('.', "");
+    
+    if !(-5..17).contains(&exponent) {
+        let (first, rest) = digits.split_at(1);
+        let rest = if rest.is_empty() { "0" } else { rest };
+        return format!("{}{}.{}e{}", sign, first, rest, exponent);
+    }
+    // How many of the digits go before the point
+    let whole = exponent + 1;
+    if whole <= 0 {
+        format!("{}0.{}{}", sign, "0".repeat(-whole as usize), digits)
+    } else if whole as usize >= digits.len() {
+        format!("{}{}{}.0", sign, digits, "0".repeat(whole as usize - digits.len()))
+    } else {
+        let (whole, fraction) = digits.split_at(whole as usize);
+        format!("{}{}.{}", sign, whole, fraction)
+    }
+}
+
+/// How an error names a value: a primitive by its text, with strings and
+/// chars quoted, and anything else by its type
+pub(crate) fn describe_value(value: &Value) -> String {
+    match value {
+        Value::Int(n) => n.to_string(),
+        Value::BigInt(n) => n.to_string(),
+        Value::Float(n) => format_float(*n),
+        Value::Decimal(n) => n.to_string(),
+        Value::Char(c) => format!("{:?}", c),
+        Value::String(s) => format!("{:?}", s),
+        Value::Bool(b) => b.to_string(),
+        Value::Null => "null".to_string(),
+        other => other.get_type().to_string(),
+    }
+}
+
+/// Formats a value for `output`. Records and enum values with a Show
+/// implementation are shown with it.
+pub(crate) fn display_value(value: &Value, methods: &mut dyn Methods) -> Result<String, String> {
+    let join = |items: &mut dyn Iterator<Item = Result<String, String>>| -> Result<String, String> {
+        Ok(items.collect::<Result<Vec<_>, _>>()?.join(", "))
+    };
+    
+    if let Value::Record { name, .. } | Value::Variant { enum_name: name, .. } = value {
+        if methods.has_method(name, "show") {
+            return match methods.call_impl(name, "show", vec![value.clone()], None)? {
+                Value::String(s) => Ok(s),
+                other => Err(format!("'{}.show' returned {}", name, other.get_type())),
+            };
+        }
+    }
+    
+    let text = match value {
+        Value::Int(n) => n.to_string(),
+        Value::BigInt(n) => n.to_string(),
+        Value::Float(n) => format_float(*n),
+        Value::Decimal(n) => n.to_string(),
+        Value::Char(c) => c.to_string(),
+        Value::String(s) => s.clone(),
+        // Bytes show the way they are written, with `\xHH` for the bytes
+        // that aren't printable ASCII
+        Value::Bytes(bytes) => format!("b\"{}\"", bytes.escape_ascii()),
+        Value::Bool(b) => b.to_string(),
+        Value::Null => "null".to_string(),
+        Value::List(items) => {
+            format!("[{}]", join(&mut items.iter().map(|item| display_value(item, methods)))?)
+        },
+        Value::Map(map) => {
+            let entries = join(&mut map.iter().map(|(k, v)| {
+                Ok(format!("{}:{}", display_value(k, methods)?, display_value(v, methods)?))
+            }))?;
+            format!("[{}]", entries)
+        },
+        Value::Record { name, fields } => {
+            if fields.is_empty() {
+                format!("{} {{}}", name)
+            } else {
+                let fields = join(&mut fields.iter().map(|(field, value)| {
+                    Ok(format!("{}: {}", field, display_value(value, methods)?))
+                }))?;
+                format!("{} {{ {} }}", name, fields)
+            }
+        },
+        Value::Variant { enum_name, variant, fields } => {
+            if fields.is_empty() {
+                format!("{}.{}", enum_name, variant)
+            } else {
+                let fields = join(&mut fields.iter().map(|field| display_value(field, methods)))?;
+                format!("{}.{}({})", enum_name, variant, fields)
+            }
+        },
+        Value::Function { name, .. } => format!("<function {}>", name),
+        Value::Closure(closure) => format!("<function {}>", closure.proto.name),
+    };
+    // Nested lists can share elements, so their text can be far longer
+    // than the memory they take
+    methods.budget().check_string(&text)?;
+    Ok(text)
+}
+
+/// A value's text for a `{value:spec}` in an `outputf` string. Numbers are
+/// aligned right and everything else left unless the spec says otherwise.
+/// A precision rounds decimals with halves away from zero, the way `round`
+/// does, since they hold exact amounts like prices, and can't go past the
+/// 28 places a decimal holds. Floats are rounded from their exact binary
+/// value with halves to even, like printf in other languages: 2.5 to no
+/// places gives 2, and 2.675 gives 2.67 to two places because the float is
+/// slightly below 2.675.
+pub(crate) fn format_value(value: &Value, spec: &FormatSpec, methods: &mut dyn Methods) -> Result<String, String> {
+    let text = match (value, spec.precision) {
+        (Value::Float(n), Some(precision)) if n.is_finite() => {
+            // The digits after the point alone are this long
+            methods.budget().check_string_length(precision as usize)?;
+            format!("{:.*}", precision as usize, n)
+        },
+        (Value::Decimal(n), Some(precision)) => {
+            if precision > Decimal::MAX_SCALE {
+                return Err(format!(
+                    "A decimal can be formatted to at most {} places, got {}",
+                    Decimal::MAX_SCALE,
+                    precision
+                ));
+            }
+            let mut rounded = n.round_dp_with_strategy(precision, RoundingStrategy::MidpointAwayFromZero);
+            rounded.rescale(precision);
+            rounded.to_string()
+        },
+        _ => display_value(value, methods)?,
+    };
+    
+    let Some(width) = spec.width else {
+        return Ok(text);
+    };
+    let padding = width.saturating_sub(text.chars().count());
+    methods.budget().check_string_length(text.len() + padding)?;
+    let numeric = matches!(value, Value::Int(_) | Value::BigInt(_) | Value::Float(_) | Value::Decimal(_));
+    let (before, after) = match spec.align.unwrap_or(if numeric { Align::Right } else { Align::Left }) {
+        Align::Left => (0, padding),
+        Align::Right => (padding, 0),
+        Align::Center => (padding / 2, padding - padding / 2),
+    };
+    Ok(format!("{}{}{}", " ".repeat(before), text, " ".repeat(after)))
+}
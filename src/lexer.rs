@@ -1,4 +1,6 @@
-use logos::Logos;
+use logos::{Lexer, Logos};
+use num_bigint::BigInt;
+use rust_decimal::Decimal;
 use std::fmt;
 use std::ops::Range;
 
@@ -36,6 +38,33 @@ pub enum Token {
     #[token("is")]
     Is,
     
+    #[token("as")]
+    As,
+    
+    #[token("type")]
+    Type,
+    
+    #[token("enum")]
+    Enum,
+    
+    #[token("alias")]
+    Alias,
+    
+    #[token("const")]
+    Const,
+    
+    #[token("match")]
+    Match,
+    
+    #[token("interface")]
+    Interface,
+    
+    #[token("impl")]
+    Impl,
+    
+    #[token("for")]
+    For,
+    
     #[token("not")]
     NotKeyword,
     
@@ -67,29 +96,83 @@ pub enum Token {
     #[token("int")]
     IntType,
     
+    #[token("bigint")]
+    BigIntType,
+    
     #[token("float")]
     FloatType,
     
+    #[token("decimal")]
+    DecimalType,
+    
+    #[token("char")]
+    CharType,
+    
     #[token("string")]
     StringType,
     
+    #[token("bytes")]
+    BytesType,
+    
     #[token("bool")]
     BoolType,
     
+    #[token("any")]
+    AnyType,
+    
     // Literals
-    #[regex(r"-?[0-9]+", |lex| lex.slice().parse().ok())]
+    // Negative numbers are parsed as unary negation so that `a-1` and
+    // `a - 1` lex as a subtraction
+    #[regex(r"[0-9]+", |lex| lex.slice().parse().ok())]
     IntLiteral(i64),
     
-    #[regex(r"-?[0-9]+\.[0-9]+", |lex| lex.slice().parse().ok())]
+    #[regex(r"[0-9]+n", |lex| lex.slice().trim_end_matches('n').parse().ok())]
+    BigIntLiteral(BigInt),
+
+    #[regex(r"[0-9]+\.[0-9]+", |lex| lex.slice().parse().ok())]
     FloatLiteral(f64),
     
+    // Decimals keep the digits they are written with, so `12.50d` prints
+    // as 12.50
+    #[regex(r"[0-9]+(\.[0-9]+)?d", |lex| Decimal::from_str_exact(lex.slice().trim_end_matches('d')).ok())]
+    DecimalLiteral(Decimal),
+    
+    // Strings come in four kinds: "with \t escapes", r"raw, without
+    // escapes", and both of those between triple quotes, which can span
+    // lines and have their indentation removed
     #[regex(r#""([^"\\]|\\.)*""#, |lex| {
         let slice = lex.slice();
-        // Remove the quotes and handle escape sequences
-        Some(slice[1..slice.len()-1].to_string())
+        unescape(&slice[1..slice.len() - 1]).ok()
+    })]
+    #[regex(r#"r"[^"]*""#, |lex| {
+        let slice = lex.slice();
+        Some(slice[2..slice.len() - 1].to_string())
     })]
+    #[token("\"\"\"", |lex| multiline_string(lex, false))]
+    #[token("r\"\"\"", |lex| multiline_string(lex, true))]
     StringLiteral(String),
     
+    // A single character between single quotes, which can be an escape
+    // like '\n' or '\u{1F600}'
+    #[regex(r"'([^'\\\n]|\\[^\n])*'", |lex| {
+        let slice = lex.slice();
+        let text = unescape(&slice[1..slice.len() - 1]).ok()?;
+        let mut chars = text.chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) => Some(c),
+            _ => None,
+        }
+    })]
+    CharLiteral(char),
+    
+    // Bytes are written like strings, with ASCII characters and `\xHH`
+    // escapes for the other bytes
+    #[regex(r#"b"([^"\\]|\\.)*""#, |lex| {
+        let slice = lex.slice();
+        unescape_bytes(&slice[2..slice.len() - 1]).ok()
+    })]
+    BytesLiteral(Vec<u8>),
+    
     #[regex(r"[a-zA-Z_][a-zA-Z0-9_]*", |lex| lex.slice().to_string())]
     Identifier(String),
     
@@ -115,6 +198,9 @@ pub enum Token {
     #[token("==")]
     DoubleEquals,
     
+    #[token("=>")]
+    FatArrow,
+    
     #[token("!=")]
     NotEquals,
     
@@ -173,12 +259,9 @@ pub enum Token {
     #[token(";")]
     Semicolon,
     
-    // Comments (ignored)
-    #[regex(r"#[^\n]*", logos::skip)]
-    Comment,
-    
-    #[regex(r"###[^#]*###", logos::skip)]
-    MultilineComment,
+    // Comments, which `tokenize` sets aside as trivia
+    #[regex(r"#[^\n]*", comment)]
+    Comment(String),
     
     // Whitespace (ignored)
     #[regex(r"[ \t\n\r]+", logos::skip)]
@@ -192,44 +275,322 @@ impl fmt::Display for Token {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Token::IntLiteral(n) => write!(f, "{}", n),
+            Token::BigIntLiteral(n) => write!(f, "{}n", n),
             Token::FloatLiteral(n) => write!(f, "{}", n),
+            Token::DecimalLiteral(n) => write!(f, "{}d", n),
+            Token::CharLiteral(c) => write!(f, "'{}'", c),
             Token::StringLiteral(s) => write!(f, "\"{}\"", s),
+            Token::BytesLiteral(b) => write!(f, "b\"{}\"", b.escape_ascii()),
             Token::Identifier(s) => write!(f, "{}", s),
             _ => write!(f, "{:?}", self),
         }
     }
 }
 
+/// A comment, which the parser doesn't see but tools can use, such as the
+/// `# allow(...)` comments that suppress warnings
+#[derive(Debug, Clone)]
+pub struct Trivia {
+    /// The comment's text, including its '#'s
+    pub text: String,
+    /// 1-based line the comment starts on
+    pub line: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct TokenWithSpan {
     pub token: Token,
     pub span: Range<usize>,
+    /// 1-based line the token starts on
+    pub line: usize,
 }
 
 pub fn tokenize(source: &str) -> Result<Vec<TokenWithSpan>, String> {
+    tokenize_with_trivia(source).map(|(tokens, _)| tokens)
+}
+
+/// Splits the source into the tokens the parser reads and the comments
+/// between them
+pub fn tokenize_with_trivia(source: &str) -> Result<(Vec<TokenWithSpan>, Vec<Trivia>), String> {
     let mut lexer = Token::lexer(source);
     let mut tokens = Vec::new();
+    let mut trivia = Vec::new();
+    let mut line = 1;
+    let mut line_counted_to = 0;
     
     while let Some(token) = lexer.next() {
         match token {
             Ok(token) => {
                 let span = lexer.span();
-                tokens.push(TokenWithSpan { token, span });
+                line += source[line_counted_to..span.start].matches('\n').count();
+                line_counted_to = span.start;
+                match token {
+                    Token::Comment(text) => trivia.push(Trivia { text, line }),
+                    token => tokens.push(TokenWithSpan { token, span, line }),
+                }
             }
             Err(_) => {
                 let span = lexer.span();
                 let line_info = get_line_info(source, span.start);
+                let text = &source[span.clone()];
+                // The span is the comment's opening '#'s
+                if text.starts_with("###") {
+                    return Err(format!(
+                        "Lexical error at line {}, column {}: unterminated comment: expected a closing {}",
+                        line_info.line, line_info.column, text.trim_end()
+                    ));
+                }
+                if let Some((offset, message)) = string_error(text) {
+                    let line_info = get_line_info(source, span.start + offset);
+                    return Err(format!(
+                        "Lexical error at line {}, column {}: {}",
+                        line_info.line, line_info.column, message
+                    ));
+                }
+                if text.bytes().all(|b| b.is_ascii_digit()) {
+                    return Err(format!(
+                        "Lexical error at line {}, column {}: {} is too big for an int; write {}n for a bigint",
+                        line_info.line, line_info.column, text, text
+                    ));
+                }
+                if let Some(digits) = text.strip_suffix('d').filter(|digits| digits.parse::<f64>().is_ok()) {
+                    return Err(format!(
+                        "Lexical error at line {}, column {}: {} has too many digits for a decimal",
+                        line_info.line, line_info.column, digits
+                    ));
+                }
                 return Err(format!(
                     "Lexical error at line {}, column {}: invalid token '{}'",
                     line_info.line,
                     line_info.column,
-                    &source[span.clone()]
+                    text
                 ));
             }
         }
     }
     
-    Ok(tokens)
+    Ok((tokens, trivia))
+}
+
+/// Lexes a comment. A fence, three or more '#'s at the start of a line,
+/// opens a block comment that ends at the next line ending in just as many
+/// '#'s, so text may follow the opening fence and come before the closing
+/// one. A line that starts and ends with the same fence, like
+/// `#### Setup ####`, is a comment on its own, and anything else from a '#'
+/// to the end of the line is a line comment.
+///
+/// Fences only nest by length: inside a block, a fence of any other length
+/// is part of the comment, so a `####` block can comment out code that has
+/// `###` blocks in it, but a block can't hold a fence as long as its own.
+fn comment(lex: &mut Lexer<Token>) -> Option<String> {
+    let source = lex.source();
+    let start = lex.span().start;
+    let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+    let slice = lex.slice();
+    let fence_len = slice.bytes().take_while(|&b| b == b'#').count();
+    let fence = &slice[..fence_len];
+    if fence_len < 3 || !source[line_start..start].trim().is_empty() || ends_with_fence(&slice[fence_len..], fence) {
+        return Some(slice.to_string());
+    }
+    
+    let mut offset = 0;
+    for line in lex.remainder().split_inclusive('\n') {
+        if ends_with_fence(line, fence) {
+            lex.bump(offset + line.trim_end().len());
+            return Some(lex.slice().to_string());
+        }
+        offset += line.len();
+    }
+    None
+}
+
+/// Whether `line` ends in `fence`, with no more '#'s before it
+fn ends_with_fence(line: &str, fence: &str) -> bool {
+    line.trim_end()
+        .strip_suffix(fence)
+        .is_some_and(|rest| !rest.ends_with('#'))
+}
+
+/// Replaces the escape sequences in a string literal, or gives the offset
+/// of the first invalid one and what is wrong with it
+fn unescape(text: &str) -> Result<String, (usize, String)> {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.char_indices();
+    
+    while let Some((i, c)) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some((_, 'n')) => '\n',
+            Some((_, 't')) => '\t',
+            Some((_, 'r')) => '\r',
+            Some((_, '0')) => '\0',
+            Some((_, '\\')) => '\\',
+            Some((_, '"')) => '"',
+            Some((_, '\'')) => '\'',
+            // `\u{1F600}`, with 1 to 6 hex digits
+            Some((_, 'u')) => {
+                let hex = text[i + 2..]
+                    .strip_prefix('{')
+                    .and_then(|rest| rest.split_once('}'))
+                    .map(|(hex, _)| hex)
+                    .filter(|hex| (1..=6).contains(&hex.len()) && hex.chars().all(|c| c.is_ascii_hexdigit()));
+                let Some(hex) = hex else {
+                    return Err((i, "invalid unicode escape: expected \\u{...} with 1 to 6 hex digits".to_string()));
+                };
+                let Some(c) = char::from_u32(u32::from_str_radix(hex, 16).unwrap()) else {
+                    return Err((i, format!("invalid unicode escape: {} is not a character", hex)));
+                };
+                // Skip the braces and the digits
+                chars.nth(hex.len() + 1);
+                c
+            }
+            Some((_, '\n')) | None => return Err((i, "a backslash can't end a line".to_string())),
+            Some((_, other)) => return Err((i, format!("invalid escape '\\{}'", other))),
+        };
+        result.push(escaped);
+    }
+    
+    Ok(result)
+}
+
+/// Replaces the escape sequences in a bytes literal, which can only hold
+/// ASCII characters and `\xHH` escapes
+fn unescape_bytes(text: &str) -> Result<Vec<u8>, (usize, String)> {
+    let mut result = Vec::with_capacity(text.len());
+    let mut chars = text.char_indices();
+    
+    while let Some((i, c)) = chars.next() {
+        if !c.is_ascii() {
+            return Err((i, format!("'{}' is not ASCII; write its bytes as \\xHH escapes", c)));
+        }
+        if c != '\\' {
+            result.push(c as u8);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some((_, 'n')) => b'\n',
+            Some((_, 't')) => b'\t',
+            Some((_, 'r')) => b'\r',
+            Some((_, '0')) => b'\0',
+            Some((_, '\\')) => b'\\',
+            Some((_, '"')) => b'"',
+            Some((_, '\'')) => b'\'',
+            // `\xFF`, with exactly 2 hex digits
+            Some((_, 'x')) => {
+                let Some(byte) = text
+                    .get(i + 2..i + 4)
+                    .filter(|hex| hex.chars().all(|c| c.is_ascii_hexdigit()))
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok()) else {
+                    return Err((i, "invalid byte escape: expected \\x and 2 hex digits".to_string()));
+                };
+                chars.nth(1);
+                byte
+            }
+            Some((_, '\n')) | None => return Err((i, "a backslash can't end a line".to_string())),
+            Some((_, other)) => return Err((i, format!("invalid escape '\\{}'", other))),
+        };
+        result.push(escaped);
+    }
+    
+    Ok(result)
+}
+
+/// Lexes the rest of a string that starts with `"""` or `r"""`, up to the
+/// closing `"""`
+fn multiline_string(lex: &mut Lexer<Token>, raw: bool) -> Option<String> {
+    let remainder = lex.remainder();
+    let bytes = remainder.as_bytes();
+    let mut end = 0;
+    loop {
+        if end >= bytes.len() {
+            return None;
+        }
+        if bytes[end..].starts_with(b"\"\"\"") {
+            break;
+        }
+        // An escaped quote doesn't end the string
+        end += if !raw && bytes[end] == b'\\' { 2 } else { 1 };
+    }
+    lex.bump(end + 3);
+    
+    let text = &remainder[..end];
+    if raw {
+        return Some(strip_indentation(text));
+    }
+    // Escapes are checked first, so that an error points at the source
+    unescape(text).ok()?;
+    unescape(&strip_indentation(text)).ok()
+}
+
+/// The text of a `"""` string that starts with a line break: the line
+/// break is left out, and so is the line of the closing quotes if it has
+/// nothing else on it. The indentation every line shares, counting the
+/// closing quotes' line, is removed.
+fn strip_indentation(text: &str) -> String {
+    let Some(text) = text.strip_prefix('\n').or_else(|| text.strip_prefix("\r\n")) else {
+        return text.to_string();
+    };
+    let is_blank = |line: &str| line.trim_matches([' ', '\t', '\r']).is_empty();
+    let indentation = |line: &str| line.len() - line.trim_start_matches([' ', '\t']).len();
+    
+    let mut lines: Vec<&str> = text.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line)).collect();
+    let closing_indentation = match lines.last() {
+        Some(&last) if is_blank(last) => {
+            lines.pop();
+            Some(indentation(last))
+        }
+        _ => None,
+    };
+    let common = lines
+        .iter()
+        .filter(|line| !is_blank(line))
+        .map(|line| indentation(line))
+        .chain(closing_indentation)
+        .min()
+        .unwrap_or(0);
+    
+    let lines: Vec<&str> = lines.iter().map(|line| line.get(common..).unwrap_or("")).collect();
+    lines.join("\n")
+}
+
+/// Where a string literal that failed to lex goes wrong, and why
+fn string_error(text: &str) -> Option<(usize, String)> {
+    if let Some(rest) = text.strip_prefix('\'') {
+        let Some(inner) = rest.strip_suffix('\'').filter(|_| !rest.is_empty()) else {
+            return Some((0, "unterminated char: expected a closing quote".to_string()));
+        };
+        return match unescape(inner) {
+            Err((offset, message)) => Some((1 + offset, message)),
+            Ok(_) => Some((0, "a char literal must have exactly one character; use double quotes for a string".to_string())),
+        };
+    }
+    if let Some(rest) = text.strip_prefix("b\"") {
+        let Some(inner) = rest.strip_suffix('"') else {
+            return Some((0, "unterminated bytes: expected a closing quote".to_string()));
+        };
+        return unescape_bytes(inner).err().map(|(offset, message)| (2 + offset, message));
+    }
+    if text == "\"\"\"" || text == "r\"\"\"" {
+        return Some((0, "unterminated string: expected a closing \"\"\"".to_string()));
+    }
+    if text.starts_with("r\"") {
+        return Some((0, "unterminated string: expected a closing quote".to_string()));
+    }
+    let start = if text.starts_with("\"\"\"") {
+        3
+    } else if text.starts_with('"') {
+        1
+    } else {
+        return None;
+    };
+    let end = text.len() - start;
+    if end <= start || !text[end..].bytes().all(|b| b == b'"') {
+        return Some((0, "unterminated string: expected a closing quote".to_string()));
+    }
+    unescape(&text[start..end]).err().map(|(offset, message)| (start + offset, message))
 }
 
 struct LineInfo {
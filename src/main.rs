@@ -5,10 +5,20 @@ mod interpreter;
 mod error;
 mod types;
 mod type_checker;
+mod match_checker;
+mod warnings;
+mod optimizer;
+mod bytecode;
+mod compiler;
+mod vm;
+mod resolver;
+mod builtins;
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use interpreter::Limits;
 use std::fs;
 use std::path::PathBuf;
+use std::time::Duration;
 use std::env;
 
 #[derive(Parser)]
@@ -21,6 +31,47 @@ struct Cli {
     /// Path to the Boba source file (.bb)
     #[arg(value_name = "FILE")]
     file: Option<PathBuf>,
+
+    /// Reject implicit `any`: empty collections and `any` values need an
+    /// explicit `as` cast
+    #[arg(long, global = true)]
+    strict: bool,
+
+    /// How to run the program
+    #[arg(long, value_enum, global = true, default_value_t = Engine::Vm)]
+    engine: Engine,
+
+    /// The most function calls that can be active at once; deeper recursion
+    /// stops the program with an error. Each active call takes memory: a few
+    /// hundred bytes on the VM, but around 10 KiB with `--engine tree` (100
+    /// KiB in a debug build), so a raised limit there needs the memory for it
+    #[arg(long, value_name = "N", global = true, default_value_t = interpreter::DEFAULT_MAX_CALL_DEPTH)]
+    max_call_depth: usize,
+
+    /// The most steps the program can take, counting every function call
+    /// and every repeat of a loop
+    #[arg(long, value_name = "N", global = true)]
+    max_steps: Option<u64>,
+
+    /// How many milliseconds the program can run for
+    #[arg(long, value_name = "MS", global = true)]
+    max_time_ms: Option<u64>,
+
+    /// The most elements a list or map can have
+    #[arg(long, value_name = "N", global = true)]
+    max_collection_size: Option<usize>,
+
+    /// The most bytes a string can have
+    #[arg(long, value_name = "N", global = true)]
+    max_string_length: Option<usize>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Engine {
+    /// Compile to bytecode and run it on the VM
+    Vm,
+    /// Walk the syntax tree, the reference implementation the VM must agree with
+    Tree,
 }
 
 #[derive(Subcommand)]
@@ -62,7 +113,14 @@ fn main() {
     match fs::read_to_string(&file_path) {
         Ok(source) => {
             println!("Running Boba program: {}", file_path.display());
-            match run_program(&source) {
+            let limits = Limits {
+                max_call_depth: cli.max_call_depth,
+                max_steps: cli.max_steps,
+                max_time: cli.max_time_ms.map(Duration::from_millis),
+                max_collection_size: cli.max_collection_size,
+                max_string_length: cli.max_string_length,
+            };
+            match run_program(&source, cli.strict, cli.engine, &limits) {
                 Ok(_) => println!("Program executed successfully"),
                 Err(e) => {
                     eprintln!("Error: {}", e);
@@ -77,10 +135,10 @@ fn main() {
     }
 }
 
-fn run_program(source: &str) -> Result<(), String> {
+fn run_program(source: &str, strict: bool, engine: Engine, limits: &Limits) -> Result<(), String> {
     // Lexical analysis
-    let tokens = match lexer::tokenize(source) {
-        Ok(tokens) => tokens,
+    let (tokens, comments) = match lexer::tokenize_with_trivia(source) {
+        Ok(lexed) => lexed,
         Err(e) => return Err(format!("Lexer error: {}", e)),
     };
 
@@ -90,14 +148,38 @@ fn run_program(source: &str) -> Result<(), String> {
         Err(e) => return Err(format!("Parser error: {}", e)),
     };
 
+    // Constant folding
+    let mut ast = match optimizer::optimize(ast) {
+        Ok(ast) => ast,
+        Err(e) => return Err(format!("Compile error: {}", e)),
+    };
+
     // Type checking
-    let type_errors = type_checker::check_types(&ast);
+    let (type_errors, type_warnings) = type_checker::check_types(&mut ast, strict);
     if !type_errors.is_empty() {
         return Err(format!("Type error: {}", type_errors[0]));
     }
 
-    // Interpretation
-    match interpreter::interpret(ast) {
+    // Warnings don't stop the program from running
+    let mut all_warnings = warnings::check_program(&ast);
+    all_warnings.extend(type_warnings);
+    all_warnings.sort_by_key(|warning| warning.line);
+    for warning in warnings::suppress(all_warnings, &comments) {
+        eprintln!("Warning: {}", warning);
+    }
+
+    // Name resolution
+    let ast = match resolver::resolve(ast) {
+        Ok(ast) => ast,
+        Err(e) => return Err(format!("Compile error: {}", e)),
+    };
+
+    // Execution
+    let result = match engine {
+        Engine::Vm => vm::run(compiler::compile(&ast), limits),
+        Engine::Tree => interpreter::interpret(ast, limits),
+    };
+    match result {
         Ok(_) => Ok(()),
         Err(e) => Err(format!("Runtime error: {}", e)),
     }
@@ -0,0 +1,452 @@
+use crate::ast::{EnumDef, Expr, Pattern, RecordDef};
+use crate::interpreter::format_float;
+use crate::types::Type;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Exhaustiveness and reachability checking for `match`, using the
+/// usefulness algorithm from Maranget's "Warnings for pattern matching".
+///
+/// Patterns are lowered to constructors applied to sub-patterns. Types with
+/// a finite set of constructors (enums, bools, null, records and lists,
+/// whose constructors are the lengths that matter) are checked constructor
+/// by constructor; everything else needs a catch-all arm.
+pub struct MatchChecker<'a> {
+    records: &'a HashMap<String, RecordDef>,
+    enums: &'a HashMap<String, EnumDef>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Ctor {
+    Variant { enum_name: String, variant: String },
+    Record(String),
+    Bool(bool),
+    Null,
+    /// An int, float or string literal, kept as written
+    Literal(String),
+    /// A list of exactly `len` items, or of at least `len` items when
+    /// `or_more` is set
+    List { len: usize, or_more: bool },
+}
+
+#[derive(Debug, Clone)]
+enum Pat {
+    Wild,
+    Ctor(Ctor, Vec<Pat>),
+    /// `[p1, ..., pn, ...rest]`: matches lists of at least n items
+    ListRest(Vec<Pat>),
+}
+
+type Row = Vec<Pat>;
+
+impl<'a> MatchChecker<'a> {
+    pub fn new(records: &'a HashMap<String, RecordDef>, enums: &'a HashMap<String, EnumDef>) -> Self {
+        Self { records, enums }
+    }
+
+    /// Checks that no arm is shadowed by the arms before it and that the arms
+    /// cover every value of `scrutinee_type`
+    pub fn check(&self, patterns: &[&Pattern], scrutinee_type: &Type) -> Result<(), String> {
+        let types = vec![scrutinee_type.clone()];
+        let mut matrix: Vec<Row> = Vec::new();
+
+        for (i, pattern) in patterns.iter().enumerate() {
+            let row = vec![self.lower(pattern)];
+            if !self.useful(&matrix, &row, &types) {
+                return Err(format!(
+                    "Match arm {} is unreachable: earlier arms already match everything `{}` matches",
+                    i + 1,
+                    display_pattern(pattern)
+                ));
+            }
+            matrix.push(row);
+        }
+
+        match self.missing(&matrix, &types) {
+            Some(witness) => Err(format!(
+                "Match is not exhaustive: `{}` is not covered",
+                witness[0]
+            )),
+            None => Ok(()),
+        }
+    }
+
+    fn lower(&self, pattern: &Pattern) -> Pat {
+        match pattern {
+            Pattern::Wildcard | Pattern::Binding(_) => Pat::Wild,
+            Pattern::Literal(literal) => match literal {
+                Expr::BoolLiteral(b) => Pat::Ctor(Ctor::Bool(*b), Vec::new()),
+                Expr::NullLiteral => Pat::Ctor(Ctor::Null, Vec::new()),
+                Expr::IntLiteral(n) => Pat::Ctor(Ctor::Literal(n.to_string()), Vec::new()),
+                Expr::BigIntLiteral(n) => Pat::Ctor(Ctor::Literal(format!("{}n", n)), Vec::new()),
+                // Trailing zeros don't matter, `1.50d` is the same case as `1.5d`
+                Expr::DecimalLiteral(n) => Pat::Ctor(Ctor::Literal(format!("{}d", n.normalize())), Vec::new()),
+                Expr::FloatLiteral(n) => Pat::Ctor(Ctor::Literal(format_float(*n)), Vec::new()),
+                Expr::CharLiteral(c) => Pat::Ctor(Ctor::Literal(format!("{:?}", c)), Vec::new()),
+                Expr::StringLiteral(s) => Pat::Ctor(Ctor::Literal(format!("{:?}", s)), Vec::new()),
+                Expr::BytesLiteral(b) => Pat::Ctor(Ctor::Literal(format!("b\"{}\"", b.escape_ascii())), Vec::new()),
+                other => Pat::Ctor(Ctor::Literal(format!("{:?}", other)), Vec::new()),
+            },
+            Pattern::Variant { enum_name, variant, fields } => Pat::Ctor(
+                Ctor::Variant {
+                    enum_name: enum_name.clone(),
+                    variant: variant.clone(),
+                },
+                fields.iter().map(|p| self.lower(p)).collect(),
+            ),
+            Pattern::Record { name, fields } => {
+                // Sub-patterns in declaration order, unmentioned fields match anything
+                let declared = self.records.get(name).map(|def| def.fields.as_slice()).unwrap_or(&[]);
+                let args = declared
+                    .iter()
+                    .map(|(field, _)| {
+                        fields
+                            .iter()
+                            .find(|(f, _)| f == field)
+                            .map(|(_, p)| self.lower(p))
+                            .unwrap_or(Pat::Wild)
+                    })
+                    .collect();
+                Pat::Ctor(Ctor::Record(name.clone()), args)
+            }
+            Pattern::List { items, rest } => {
+                let mut prefix: Vec<Pat> = items.iter().map(|p| self.lower(p)).collect();
+                match rest.as_deref() {
+                    None => Pat::Ctor(
+                        Ctor::List {
+                            len: prefix.len(),
+                            or_more: false,
+                        },
+                        prefix,
+                    ),
+                    Some(Pattern::Wildcard) | Some(Pattern::Binding(_)) => Pat::ListRest(prefix),
+                    // `[a, ...[b, c]]` is the same as `[a, b, c]`
+                    Some(nested) => match self.lower(nested) {
+                        Pat::Ctor(Ctor::List { len, or_more }, nested_items) => {
+                            prefix.extend(nested_items);
+                            Pat::Ctor(
+                                Ctor::List {
+                                    len: len + items.len(),
+                                    or_more,
+                                },
+                                prefix,
+                            )
+                        }
+                        Pat::ListRest(nested_items) => {
+                            prefix.extend(nested_items);
+                            Pat::ListRest(prefix)
+                        }
+                        _ => Pat::ListRest(prefix),
+                    },
+                }
+            }
+        }
+    }
+
+    /// All constructors of `ty`, or None if there are infinitely many. For
+    /// lists these are the lengths up to the longest pattern in `column`,
+    /// plus one constructor for all longer lists.
+    fn constructors(&self, ty: &Type, column: &[&Pat]) -> Option<Vec<Ctor>> {
+        match ty.resolved() {
+            Type::Bool => Some(vec![Ctor::Bool(true), Ctor::Bool(false)]),
+            Type::Null => Some(vec![Ctor::Null]),
+            Type::Record(name) => Some(vec![Ctor::Record(name.clone())]),
+            Type::Enum(name) => self.enums.get(name).map(|def| {
+                def.variants
+                    .iter()
+                    .map(|(variant, _)| Ctor::Variant {
+                        enum_name: name.clone(),
+                        variant: variant.clone(),
+                    })
+                    .collect()
+            }),
+            Type::List(_) => {
+                let longest = column
+                    .iter()
+                    .map(|pat| match pat {
+                        Pat::Ctor(Ctor::List { len, .. }, _) => len + 1,
+                        Pat::ListRest(prefix) => prefix.len(),
+                        _ => 0,
+                    })
+                    .max()
+                    .unwrap_or(0);
+                let mut ctors: Vec<Ctor> = (0..longest)
+                    .map(|len| Ctor::List { len, or_more: false })
+                    .collect();
+                ctors.push(Ctor::List {
+                    len: longest,
+                    or_more: true,
+                });
+                Some(ctors)
+            }
+            _ => None,
+        }
+    }
+
+    /// Types of the sub-patterns of `ctor` when matching a value of type `ty`
+    fn arg_types(&self, ctor: &Ctor, ty: &Type) -> Vec<Type> {
+        match ctor {
+            Ctor::Variant { enum_name, variant } => self
+                .enums
+                .get(enum_name)
+                .and_then(|def| def.variants.iter().find(|(v, _)| v == variant))
+                .map(|(_, payload)| payload.clone())
+                .unwrap_or_default(),
+            Ctor::Record(name) => self
+                .records
+                .get(name)
+                .map(|def| def.fields.iter().map(|(_, t)| t.clone()).collect())
+                .unwrap_or_default(),
+            Ctor::List { len, .. } => {
+                let elem_type = match ty.resolved() {
+                    Type::List(elem_type) => (**elem_type).clone(),
+                    _ => Type::Any,
+                };
+                vec![elem_type; *len]
+            }
+            Ctor::Bool(_) | Ctor::Null | Ctor::Literal(_) => Vec::new(),
+        }
+    }
+
+    /// Rows of `matrix` that can match `ctor`, with their first pattern
+    /// replaced by its `arity` sub-patterns
+    fn specialize(&self, matrix: &[Row], ctor: &Ctor, arity: usize) -> Vec<Row> {
+        matrix
+            .iter()
+            .filter_map(|row| {
+                let mut head = match &row[0] {
+                    Pat::Wild => vec![Pat::Wild; arity],
+                    Pat::Ctor(row_ctor, args) if same_ctor(row_ctor, ctor) => args.clone(),
+                    Pat::ListRest(prefix) => match ctor {
+                        Ctor::List { len, .. } if *len >= prefix.len() => {
+                            let mut args = prefix.clone();
+                            args.resize(*len, Pat::Wild);
+                            args
+                        }
+                        _ => return None,
+                    },
+                    _ => return None,
+                };
+                head.extend(row[1..].iter().cloned());
+                Some(head)
+            })
+            .collect()
+    }
+
+    /// Rows of `matrix` starting with a catch-all pattern, without it
+    fn default_rows(matrix: &[Row]) -> Vec<Row> {
+        matrix
+            .iter()
+            .filter(|row| matches!(row[0], Pat::Wild))
+            .map(|row| row[1..].to_vec())
+            .collect()
+    }
+
+    /// Whether some value matched by `row` isn't matched by any row of `matrix`
+    fn useful(&self, matrix: &[Row], row: &[Pat], types: &[Type]) -> bool {
+        if row.is_empty() {
+            return matrix.is_empty();
+        }
+
+        let mut column: Vec<&Pat> = matrix.iter().map(|r| &r[0]).collect();
+        column.push(&row[0]);
+
+        let try_ctor = |ctor: &Ctor, head_args: Vec<Pat>| {
+            let sub_types = self.arg_types(ctor, &types[0]);
+            let specialized = self.specialize(matrix, ctor, sub_types.len());
+            let mut sub_row = head_args;
+            sub_row.extend(row[1..].iter().cloned());
+            let mut sub_types_all = sub_types;
+            sub_types_all.extend(types[1..].iter().cloned());
+            self.useful(&specialized, &sub_row, &sub_types_all)
+        };
+
+        match &row[0] {
+            Pat::Ctor(ctor, args) => try_ctor(ctor, args.clone()),
+            Pat::ListRest(prefix) => self
+                .constructors(&Type::List(Box::new(Type::Any)), &column)
+                .unwrap_or_default()
+                .iter()
+                .filter(|ctor| matches!(ctor, Ctor::List { len, .. } if *len >= prefix.len()))
+                .any(|ctor| {
+                    let mut args = prefix.clone();
+                    args.resize(self.arg_types(ctor, &types[0]).len(), Pat::Wild);
+                    try_ctor(ctor, args)
+                }),
+            Pat::Wild => match self.constructors(&types[0], &column) {
+                Some(ctors) => ctors.iter().any(|ctor| {
+                    let arity = self.arg_types(ctor, &types[0]).len();
+                    try_ctor(ctor, vec![Pat::Wild; arity])
+                }),
+                None => self.useful(&Self::default_rows(matrix), &row[1..], &types[1..]),
+            },
+        }
+    }
+
+    /// A list of patterns, one per column, describing values that no row of
+    /// `matrix` matches, or None if the rows are exhaustive
+    fn missing(&self, matrix: &[Row], types: &[Type]) -> Option<Vec<Pat>> {
+        if types.is_empty() {
+            return if matrix.is_empty() { Some(Vec::new()) } else { None };
+        }
+
+        let column: Vec<&Pat> = matrix.iter().map(|r| &r[0]).collect();
+
+        match self.constructors(&types[0], &column) {
+            Some(ctors) => {
+                let (used, unused): (Vec<Ctor>, Vec<Ctor>) =
+                    ctors.into_iter().partition(|ctor| is_used(ctor, &column));
+
+                // Constructors no row mentions are only matched by the
+                // catch-all rows, so checking those once covers all of them
+                if let Some(ctor) = unused.into_iter().next() {
+                    if let Some(mut witness) = self.missing(&Self::default_rows(matrix), &types[1..]) {
+                        let head = if used.is_empty() {
+                            Pat::Wild
+                        } else {
+                            let arity = self.arg_types(&ctor, &types[0]).len();
+                            Pat::Ctor(ctor, vec![Pat::Wild; arity])
+                        };
+                        witness.insert(0, head);
+                        return Some(witness);
+                    }
+                }
+
+                for ctor in used {
+                    let sub_types = self.arg_types(&ctor, &types[0]);
+                    let arity = sub_types.len();
+                    let specialized = self.specialize(matrix, &ctor, arity);
+                    let mut sub_types_all = sub_types;
+                    sub_types_all.extend(types[1..].iter().cloned());
+
+                    if let Some(mut witness) = self.missing(&specialized, &sub_types_all) {
+                        let rest = witness.split_off(arity);
+                        let mut result = vec![Pat::Ctor(ctor, witness)];
+                        result.extend(rest);
+                        return Some(result);
+                    }
+                }
+                None
+            }
+            None => {
+                let mut witness = self.missing(&Self::default_rows(matrix), &types[1..])?;
+                witness.insert(0, Pat::Wild);
+                Some(witness)
+            }
+        }
+    }
+}
+
+/// Whether some pattern in `column` starts with `ctor`
+fn is_used(ctor: &Ctor, column: &[&Pat]) -> bool {
+    column.iter().any(|pat| match pat {
+        Pat::Ctor(head, _) => same_ctor(head, ctor),
+        Pat::ListRest(prefix) => matches!(ctor, Ctor::List { len, .. } if *len >= prefix.len()),
+        Pat::Wild => false,
+    })
+}
+
+/// List constructors are identified by length alone
+fn same_ctor(a: &Ctor, b: &Ctor) -> bool {
+    match (a, b) {
+        (Ctor::List { len: a_len, .. }, Ctor::List { len: b_len, .. }) => a_len == b_len,
+        _ => a == b,
+    }
+}
+
+impl fmt::Display for Pat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Pat::Wild => write!(f, "_"),
+            Pat::ListRest(prefix) => {
+                write!(f, "[")?;
+                for item in prefix {
+                    write!(f, "{}, ", item)?;
+                }
+                write!(f, "...]")
+            }
+            Pat::Ctor(ctor, args) => match ctor {
+                Ctor::Variant { enum_name, variant } => {
+                    write!(f, "{}.{}", enum_name, variant)?;
+                    if !args.is_empty() {
+                        write!(f, "(")?;
+                        write_list(f, args)?;
+                        write!(f, ")")?;
+                    }
+                    Ok(())
+                }
+                Ctor::Record(name) => {
+                    write!(f, "{} {{ ", name)?;
+                    write_list(f, args)?;
+                    write!(f, " }}")
+                }
+                Ctor::Bool(b) => write!(f, "{}", b),
+                Ctor::Null => write!(f, "null"),
+                Ctor::Literal(text) => write!(f, "{}", text),
+                Ctor::List { or_more, .. } => {
+                    write!(f, "[")?;
+                    write_list(f, args)?;
+                    if *or_more {
+                        if !args.is_empty() {
+                            write!(f, ", ")?;
+                        }
+                        write!(f, "...")?;
+                    }
+                    write!(f, "]")
+                }
+            },
+        }
+    }
+}
+
+fn write_list(f: &mut fmt::Formatter<'_>, items: &[Pat]) -> fmt::Result {
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, ", ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    Ok(())
+}
+
+/// Formats a pattern the way it is written in source
+fn display_pattern(pattern: &Pattern) -> String {
+    match pattern {
+        Pattern::Wildcard => "_".to_string(),
+        Pattern::Binding(name) => name.clone(),
+        Pattern::Literal(literal) => match literal {
+            Expr::IntLiteral(n) => n.to_string(),
+            Expr::BigIntLiteral(n) => format!("{}n", n),
+            Expr::DecimalLiteral(n) => format!("{}d", n),
+            Expr::FloatLiteral(n) => format_float(*n),
+            Expr::CharLiteral(c) => format!("{:?}", c),
+            Expr::StringLiteral(s) => format!("{:?}", s),
+            Expr::BytesLiteral(b) => format!("b\"{}\"", b.escape_ascii()),
+            Expr::BoolLiteral(b) => b.to_string(),
+            Expr::NullLiteral => "null".to_string(),
+            other => format!("{:?}", other),
+        },
+        Pattern::Variant { enum_name, variant, fields } => {
+            if fields.is_empty() {
+                format!("{}.{}", enum_name, variant)
+            } else {
+                let fields: Vec<String> = fields.iter().map(display_pattern).collect();
+                format!("{}.{}({})", enum_name, variant, fields.join(", "))
+            }
+        }
+        Pattern::List { items, rest } => {
+            let mut parts: Vec<String> = items.iter().map(display_pattern).collect();
+            if let Some(rest) = rest {
+                parts.push(format!("...{}", display_pattern(rest)));
+            }
+            format!("[{}]", parts.join(", "))
+        }
+        Pattern::Record { name, fields } => {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(field, p)| format!("{}: {}", field, display_pattern(p)))
+                .collect();
+            format!("{} {{ {} }}", name, fields.join(", "))
+        }
+    }
+}
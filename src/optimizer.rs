@@ -0,0 +1,352 @@
+use crate::ast::{BinaryOperator, Expr, Pattern, Program, UnaryOperator};
+use crate::interpreter::{convert_value, evaluate_binary_op, evaluate_unary_op};
+use crate::types::{Type, Value};
+use std::collections::HashMap;
+
+/// Replaces uses of constants with their values and folds operations whose
+/// operands are all known before the program runs, so that e.g.
+/// `60 * 60 * 24` is computed once instead of every time it is reached.
+/// Operations that would fail at runtime, like dividing by zero or
+/// `int("abc")`, are reported here instead.
+pub fn optimize(mut program: Program) -> Result<Program, String> {
+    let mut folder = Folder::default();
+
+    for const_def in &mut program.constants {
+        if folder.constants.contains_key(&const_def.name) {
+            return Err(format!("Constant '{}' is declared more than once", const_def.name));
+        }
+        if program.functions.contains_key(&const_def.name) {
+            return Err(format!("'{}' is declared as both a constant and a function", const_def.name));
+        }
+
+        folder.fold_expr(&mut const_def.value)
+            .map_err(|e| format!("In constant '{}': {}", const_def.name, e))?;
+        if !is_constant(&const_def.value) {
+            return Err(format!(
+                "Constant '{}' on line {} must be a value known before the program runs",
+                const_def.name, const_def.line
+            ));
+        }
+        folder.constants.insert(const_def.name.clone(), const_def.value.clone());
+    }
+
+    let mut functions: Vec<_> = program.functions.values_mut().collect();
+    functions.sort_by_key(|func_def| func_def.line);
+    for func_def in functions {
+        folder.fold_function(&func_def.params, &mut func_def.body)
+            .map_err(|e| format!("In function '{}': {}", func_def.name, e))?;
+    }
+
+    for impl_def in &mut program.impls {
+        for method in &mut impl_def.methods {
+            folder.fold_function(&method.params, &mut method.body)
+                .map_err(|e| format!("In method '{}.{}': {}", impl_def.target, method.name, e))?;
+        }
+    }
+
+    folder.fold_exprs(&mut program.main_block)?;
+
+    Ok(program)
+}
+
+#[derive(Default)]
+struct Folder {
+    /// Values of the constants declared so far
+    constants: HashMap<String, Expr>,
+}
+
+impl Folder {
+    /// Constants can't be redeclared, so every use of their name refers to
+    /// the constant
+    fn declare(&self, name: &str) -> Result<(), String> {
+        if self.constants.contains_key(name) {
+            return Err(format!("Cannot redeclare constant '{}'", name));
+        }
+        Ok(())
+    }
+
+    fn fold_function(&self, params: &[(String, Type)], body: &mut [Expr]) -> Result<(), String> {
+        for (param, _) in params {
+            self.declare(param)?;
+        }
+        self.fold_exprs(body)
+    }
+
+    fn fold_exprs<'a>(&self, exprs: impl IntoIterator<Item = &'a mut Expr>) -> Result<(), String> {
+        for expr in exprs {
+            self.fold_expr(expr)?;
+        }
+        Ok(())
+    }
+
+    fn fold_expr(&self, expr: &mut Expr) -> Result<(), String> {
+        match expr {
+            Expr::IntLiteral(_)
+            | Expr::BigIntLiteral(_)
+            | Expr::DecimalLiteral(_)
+            | Expr::FloatLiteral(_)
+            | Expr::CharLiteral(_)
+            | Expr::StringLiteral(_)
+            | Expr::BytesLiteral(_)
+            | Expr::BoolLiteral(_)
+            | Expr::NullLiteral
+            | Expr::Break { .. }
+            | Expr::Continue { .. } => {}
+
+            Expr::Identifier { name, .. } => {
+                if let Some(value) = self.constants.get(name) {
+                    *expr = value.clone();
+                }
+            }
+
+            Expr::VarDeclaration { name, value, .. } => {
+                if self.constants.contains_key(name) {
+                    return Err(format!("Cannot assign to constant '{}'", name));
+                }
+                self.fold_expr(value)?;
+            }
+
+            Expr::List(items) => self.fold_exprs(items)?,
+            Expr::Map(entries) => {
+                for (key, value) in entries {
+                    self.fold_expr(key)?;
+                    self.fold_expr(value)?;
+                }
+            }
+            Expr::Index { target, index } => {
+                self.fold_expr(target)?;
+                self.fold_expr(index)?;
+            }
+            Expr::Slice { target, start, end } => {
+                self.fold_expr(target)?;
+                self.fold_exprs(start.as_deref_mut())?;
+                self.fold_exprs(end.as_deref_mut())?;
+            }
+            Expr::RecordLiteral { fields, .. } => self.fold_exprs(fields.iter_mut().map(|(_, e)| e))?,
+            Expr::FieldAccess { target, .. } => self.fold_expr(target)?,
+            Expr::FieldAssignment { target, value, .. } => {
+                self.fold_expr(target)?;
+                self.fold_expr(value)?;
+            }
+            Expr::IndexAssignment { target, index, value } => {
+                self.fold_expr(target)?;
+                self.fold_expr(index)?;
+                self.fold_expr(value)?;
+            }
+
+            Expr::BinaryOp { left, operator, right, line } => {
+                self.fold_expr(left)?;
+                self.fold_expr(right)?;
+                let folded = fold_binary_op(left, operator, right).map_err(|e| format!("line {}: {}", line, e))?;
+                if let Some(folded) = folded {
+                    *expr = folded;
+                }
+            }
+            Expr::UnaryOp { operator, expr: operand, line } => {
+                self.fold_expr(operand)?;
+                let foldable = matches!(
+                    (&*operator, &**operand),
+                    (
+                        UnaryOperator::Negate,
+                        Expr::IntLiteral(_) | Expr::BigIntLiteral(_) | Expr::FloatLiteral(_) | Expr::DecimalLiteral(_)
+                    )
+                        | (UnaryOperator::Not, Expr::BoolLiteral(_))
+                );
+                if foldable {
+                    let value = evaluate_unary_op(operator, literal_value(operand).unwrap())
+                        .map_err(|e| format!("line {}: {}", line, e))?;
+                    *expr = value_literal(value);
+                }
+            }
+            Expr::TypeConversion { expr: operand, target_type, line } => {
+                self.fold_expr(operand)?;
+                if let Some(value) = literal_value(operand) {
+                    let always_fails = matches!(
+                        (&value, &*target_type),
+                        (Value::String(_), _)
+                            | (Value::Float(_), Type::Int | Type::BigInt | Type::Decimal)
+                            | (Value::BigInt(_), Type::Int | Type::Decimal)
+                            | (Value::Decimal(_), Type::Int)
+                            | (Value::Int(_), Type::Char)
+                            | (Value::Bytes(_), Type::String)
+                    );
+                    match convert_value(value, target_type) {
+                        Ok(converted) => *expr = value_literal(converted),
+                        // A string that doesn't parse or a number out of the
+                        // range of an int is always an error, other
+                        // unsupported conversions are type errors
+                        Err(e) if always_fails => return Err(format!("line {}: {}", line, e)),
+                        Err(_) => {}
+                    }
+                }
+            }
+
+            Expr::VariantLiteral { args, .. } => self.fold_exprs(args)?,
+            Expr::Match { scrutinee, arms, .. } => {
+                self.fold_expr(scrutinee)?;
+                for arm in arms {
+                    self.fold_pattern(&mut arm.pattern)?;
+                    self.fold_exprs(&mut arm.body)?;
+                }
+            }
+
+            Expr::If { condition, then_branch, else_if_branches, else_branch } => {
+                self.fold_expr(condition)?;
+                self.fold_exprs(then_branch)?;
+                for (cond, branch) in else_if_branches {
+                    self.fold_expr(cond)?;
+                    self.fold_exprs(branch)?;
+                }
+                if let Some(branch) = else_branch {
+                    self.fold_exprs(branch)?;
+                }
+            }
+            Expr::Loop { init, condition, update, body } => {
+                self.fold_exprs(init.as_deref_mut())?;
+                self.fold_exprs(condition.as_deref_mut())?;
+                self.fold_exprs(update.as_deref_mut())?;
+                self.fold_exprs(body)?;
+            }
+            Expr::Return { values, .. } => self.fold_exprs(values)?,
+
+            Expr::FunctionDeclaration { name, params, body, .. } => {
+                self.declare(name)?;
+                self.fold_function(params, body)?;
+            }
+            Expr::Lambda { params, body, .. } => self.fold_function(params, body)?,
+            Expr::FunctionCall { args, .. } => self.fold_exprs(args)?,
+            Expr::Call { callee, args, .. } => {
+                self.fold_expr(callee)?;
+                self.fold_exprs(args)?;
+            }
+            Expr::MethodCall { target, args, .. } => {
+                self.fold_expr(target)?;
+                self.fold_exprs(args)?;
+            }
+
+            Expr::Output(args) => self.fold_exprs(args)?,
+            Expr::OutputFormatted(expr)
+            | Expr::Format { expr, .. }
+            | Expr::OutputAddress(expr)
+            | Expr::Input(expr)
+            | Expr::InputFormatted(expr)
+            | Expr::TypeCheck { expr, .. }
+            | Expr::Cast { expr, .. }
+            | Expr::IntToFloat { expr, .. } => self.fold_expr(expr)?,
+        }
+        Ok(())
+    }
+
+    /// A constant's name in a pattern matches the constant's value
+    fn fold_pattern(&self, pattern: &mut Pattern) -> Result<(), String> {
+        match pattern {
+            Pattern::Wildcard | Pattern::Literal(_) => {}
+            Pattern::Binding(name) => {
+                if let Some(value) = self.constants.get(name) {
+                    if literal_value(value).is_none() {
+                        return Err(format!("Constant '{}' can't be used as a pattern", name));
+                    }
+                    *pattern = Pattern::Literal(value.clone());
+                }
+            }
+            Pattern::Variant { fields, .. } => {
+                for field in fields {
+                    self.fold_pattern(field)?;
+                }
+            }
+            Pattern::List { items, rest } => {
+                for item in items {
+                    self.fold_pattern(item)?;
+                }
+                if let Some(rest) = rest {
+                    if let Pattern::Binding(name) = &**rest {
+                        self.declare(name)?;
+                    }
+                }
+            }
+            Pattern::Record { fields, .. } => {
+                for (_, field) in fields {
+                    self.fold_pattern(field)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Computes `left operator right` when both sides are literals of types the
+/// operator accepts. Everything else is left for the type checker to report.
+fn fold_binary_op(left: &Expr, operator: &BinaryOperator, right: &Expr) -> Result<Option<Expr>, String> {
+    let (Some(left), Some(right)) = (literal_value(left), literal_value(right)) else {
+        return Ok(None);
+    };
+
+    use BinaryOperator::*;
+    let foldable = match (&left, &right) {
+        (Value::Int(_) | Value::Decimal(_), Value::Int(_) | Value::Decimal(_)) => !matches!(operator, And | Or),
+        // Comparing an int with a float is left for the type checker to warn
+        // about
+        (Value::Int(_) | Value::BigInt(_), Value::Float(_)) | (Value::Float(_), Value::Int(_) | Value::BigInt(_))
+            if matches!(operator, Equal | NotEqual) =>
+        {
+            false
+        }
+        (
+            Value::Int(_) | Value::BigInt(_) | Value::Float(_),
+            Value::Int(_) | Value::BigInt(_) | Value::Float(_),
+        ) => !matches!(operator, And | Or),
+        (Value::String(_), Value::String(_)) | (Value::Bytes(_), Value::Bytes(_)) => {
+            !matches!(operator, Subtract | Multiply | Divide | Modulo | And | Or)
+        }
+        (Value::Char(_), Value::Char(_)) => !matches!(operator, Add | Subtract | Multiply | Divide | Modulo | And | Or),
+        (Value::Bool(_), Value::Bool(_)) => matches!(operator, Equal | NotEqual | And | Or),
+        _ => false,
+    };
+    if !foldable {
+        return Ok(None);
+    }
+
+    // What's left to fail is int and decimal arithmetic, by dividing by zero
+    // or overflowing, which is reported before the program runs
+    evaluate_binary_op(left, operator, right).map(|value| Some(value_literal(value)))
+}
+
+fn literal_value(expr: &Expr) -> Option<Value> {
+    match expr {
+        Expr::IntLiteral(n) => Some(Value::Int(*n)),
+        Expr::BigIntLiteral(n) => Some(Value::BigInt(n.clone())),
+        Expr::DecimalLiteral(n) => Some(Value::Decimal(*n)),
+        Expr::FloatLiteral(n) => Some(Value::Float(*n)),
+        Expr::CharLiteral(c) => Some(Value::Char(*c)),
+        Expr::StringLiteral(s) => Some(Value::String(s.clone())),
+        Expr::BytesLiteral(b) => Some(Value::Bytes(b.clone())),
+        Expr::BoolLiteral(b) => Some(Value::Bool(*b)),
+        Expr::NullLiteral => Some(Value::Null),
+        _ => None,
+    }
+}
+
+/// The literal for a value computed from literals
+fn value_literal(value: Value) -> Expr {
+    match value {
+        Value::Int(n) => Expr::IntLiteral(n),
+        Value::BigInt(n) => Expr::BigIntLiteral(n),
+        Value::Decimal(n) => Expr::DecimalLiteral(n),
+        Value::Float(n) => Expr::FloatLiteral(n),
+        Value::Char(c) => Expr::CharLiteral(c),
+        Value::String(s) => Expr::StringLiteral(s),
+        Value::Bytes(b) => Expr::BytesLiteral(b),
+        Value::Bool(b) => Expr::BoolLiteral(b),
+        Value::Null => Expr::NullLiteral,
+        other => unreachable!("folding produced a non-literal value {:?}", other),
+    }
+}
+
+/// Whether an expression is fully known before the program runs
+fn is_constant(expr: &Expr) -> bool {
+    match expr {
+        Expr::List(items) => items.iter().all(is_constant),
+        Expr::Map(entries) => entries.iter().all(|(key, value)| is_constant(key) && is_constant(value)),
+        _ => literal_value(expr).is_some(),
+    }
+}
@@ -1,17 +1,54 @@
-use crate::ast::{BinaryOperator, Expr, FunctionDef, Program};
-use crate::lexer::{Token, TokenWithSpan};
+use crate::ast::{
+    Align, BinaryOperator, ConstDef, EnumDef, Expr, FormatSpec, FunctionDef, ImplDef, InterfaceDef, MatchArm,
+    MatchDispatch, MethodSig, Pattern, Program, RecordDef, UnaryOperator,
+};
+use crate::lexer::{tokenize, Token, TokenWithSpan};
 use crate::types::Type;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+
+/// Type parameter names and the (parameter, interface) constraints on them
+type TypeParams = (Vec<String>, Vec<(String, String)>);
 
 pub struct Parser {
     tokens: Vec<TokenWithSpan>,
     current: usize,
+    /// Type parameters of the generic functions being parsed
+    type_params: Vec<String>,
+    /// What `Self` and an untyped `self` parameter stand for inside an
+    /// interface or impl
+    self_type: Option<Type>,
+    /// Names of all record and enum types in the program, so that types can
+    /// be used before they are declared
+    record_names: HashSet<String>,
+    enum_names: HashSet<String>,
+    alias_names: HashSet<String>,
+    /// Type aliases declared so far with the types they stand for
+    aliases: HashMap<String, Type>,
 }
 
 pub fn parse(tokens: Vec<TokenWithSpan>) -> Result<Program, String> {
+    let declared_names = |keyword: Token| -> HashSet<String> {
+        tokens
+            .windows(2)
+            .filter_map(|pair| match (&pair[0].token, &pair[1].token) {
+                (t, Token::Identifier(name)) if *t == keyword => Some(name.clone()),
+                _ => None,
+            })
+            .collect()
+    };
+    let record_names = declared_names(Token::Type);
+    let enum_names = declared_names(Token::Enum);
+    let alias_names = declared_names(Token::Alias);
+    
     let mut parser = Parser {
         tokens,
         current: 0,
+        type_params: Vec::new(),
+        self_type: None,
+        record_names,
+        enum_names,
+        alias_names,
+        aliases: HashMap::new(),
     };
     
     parser.parse_program()
@@ -20,10 +57,61 @@ pub fn parse(tokens: Vec<TokenWithSpan>) -> Result<Program, String> {
 impl Parser {
     fn parse_program(&mut self) -> Result<Program, String> {
         let mut functions = HashMap::new();
+        let mut records = HashMap::new();
+        let mut enums = HashMap::new();
+        let mut interfaces = HashMap::new();
+        let mut impls = Vec::new();
+        let mut constants = Vec::new();
         let mut main_block = Vec::new();
         
         while !self.is_at_end() {
-            if self.match_token(&Token::Fun) {
+            if self.check(&Token::Type) || self.check(&Token::Enum) {
+                let name = match self.peek_token_type() {
+                    Some(Token::Identifier(name)) => name.clone(),
+                    _ => return Err("Expected type name after 'type' or 'enum' keyword".to_string()),
+                };
+                if records.contains_key(&name) || enums.contains_key(&name) || self.aliases.contains_key(&name) {
+                    return Err(format!("Type '{}' is declared more than once", name));
+                }
+                
+                if self.match_token(&Token::Type) {
+                    let record_def = self.parse_record_declaration()?;
+                    records.insert(name, record_def);
+                } else {
+                    self.advance();
+                    let enum_def = self.parse_enum_declaration()?;
+                    enums.insert(name, enum_def);
+                }
+                continue;
+            }
+            
+            if self.match_token(&Token::Alias) {
+                self.parse_alias_declaration(&records, &enums)?;
+                continue;
+            }
+            
+            if self.match_token(&Token::Const) {
+                constants.push(self.parse_const_declaration()?);
+                continue;
+            }
+            
+            if self.match_token(&Token::Interface) {
+                let interface_def = self.parse_interface_declaration()?;
+                if interfaces.contains_key(&interface_def.name) {
+                    return Err(format!("Interface '{}' is declared more than once", interface_def.name));
+                }
+                interfaces.insert(interface_def.name.clone(), interface_def);
+                continue;
+            }
+            
+            if self.match_token(&Token::Impl) {
+                impls.push(self.parse_impl()?);
+                continue;
+            }
+            
+            // `fun name(...)` declares a function, `fun(...)` is an anonymous function
+            if self.check(&Token::Fun) && matches!(self.peek_token_type(), Some(Token::Identifier(_))) {
+                self.advance();
                 let func_def = self.parse_function_declaration()?;
                 functions.insert(func_def.name.clone(), func_def);
             } else {
@@ -34,11 +122,240 @@ impl Parser {
         
         Ok(Program {
             functions,
+            records,
+            enums,
+            interfaces,
+            impls,
+            constants,
             main_block,
         })
     }
     
+    fn parse_record_declaration(&mut self) -> Result<RecordDef, String> {
+        // The 'type' keyword has already been consumed
+        let name = if let Some(Token::Identifier(name)) = self.current_token_type() {
+            let name_str = name.clone();
+            self.advance();
+            name_str
+        } else {
+            return Err("Expected type name after 'type' keyword".to_string());
+        };
+        
+        self.consume(&Token::LBrace, &format!("Expected '{{' after type name '{}'", name))?;
+        
+        // Fields are `name: type`, optionally separated by commas
+        let mut fields: Vec<(String, Type)> = Vec::new();
+        while !self.check(&Token::RBrace) && !self.is_at_end() {
+            let field_name = if let Some(Token::Identifier(field_name)) = self.current_token_type() {
+                let field_str = field_name.clone();
+                self.advance();
+                field_str
+            } else {
+                return Err(format!("Expected field name in type '{}'", name));
+            };
+            
+            if fields.iter().any(|(existing, _)| *existing == field_name) {
+                return Err(format!("Duplicate field '{}' in type '{}'", field_name, name));
+            }
+            
+            self.consume(&Token::Colon, "Expected ':' after field name")?;
+            let field_type = self.parse_type()?;
+            fields.push((field_name, field_type));
+            
+            self.match_token(&Token::Comma);
+        }
+        
+        self.consume(&Token::RBrace, &format!("Expected '}}' after fields of type '{}'", name))?;
+        
+        Ok(RecordDef { name, fields })
+    }
+    
+    fn parse_enum_declaration(&mut self) -> Result<EnumDef, String> {
+        // The 'enum' keyword has already been consumed
+        let name = if let Some(Token::Identifier(name)) = self.current_token_type() {
+            let name_str = name.clone();
+            self.advance();
+            name_str
+        } else {
+            return Err("Expected enum name after 'enum' keyword".to_string());
+        };
+        
+        self.consume(&Token::LBrace, &format!("Expected '{{' after enum name '{}'", name))?;
+        
+        // Variants are `Name` or `Name(type, ...)`, optionally separated by commas
+        let mut variants: Vec<(String, Vec<Type>)> = Vec::new();
+        while !self.check(&Token::RBrace) && !self.is_at_end() {
+            let variant = if let Some(Token::Identifier(variant)) = self.current_token_type() {
+                let variant_str = variant.clone();
+                self.advance();
+                variant_str
+            } else {
+                return Err(format!("Expected variant name in enum '{}'", name));
+            };
+            
+            if variants.iter().any(|(existing, _)| *existing == variant) {
+                return Err(format!("Duplicate variant '{}' in enum '{}'", variant, name));
+            }
+            
+            let mut payload = Vec::new();
+            if self.match_token(&Token::LParen) {
+                loop {
+                    payload.push(self.parse_type()?);
+                    
+                    if !self.match_token(&Token::Comma) {
+                        break;
+                    }
+                }
+                self.consume(&Token::RParen, "Expected ')' after variant payload types")?;
+            }
+            variants.push((variant, payload));
+            
+            self.match_token(&Token::Comma);
+        }
+        
+        self.consume(&Token::RBrace, &format!("Expected '}}' after variants of enum '{}'", name))?;
+        
+        Ok(EnumDef { name, variants })
+    }
+    
+    fn parse_alias_declaration(
+        &mut self,
+        records: &HashMap<String, RecordDef>,
+        enums: &HashMap<String, EnumDef>,
+    ) -> Result<(), String> {
+        // The 'alias' keyword has already been consumed
+        let name = if let Some(Token::Identifier(name)) = self.current_token_type() {
+            let name_str = name.clone();
+            self.advance();
+            name_str
+        } else {
+            return Err("Expected alias name after 'alias' keyword".to_string());
+        };
+        
+        if records.contains_key(&name) || enums.contains_key(&name) || self.aliases.contains_key(&name) {
+            return Err(format!("Type '{}' is declared more than once", name));
+        }
+        
+        self.consume(&Token::Equals, &format!("Expected '=' after alias name '{}'", name))?;
+        let target = self.parse_type()?;
+        self.aliases.insert(name, target);
+        
+        Ok(())
+    }
+    
+    fn parse_const_declaration(&mut self) -> Result<ConstDef, String> {
+        // The 'const' keyword has already been consumed
+        let line = self.previous_line();
+        let name = if let Some(Token::Identifier(name)) = self.current_token_type() {
+            let name_str = name.clone();
+            self.advance();
+            name_str
+        } else {
+            return Err("Expected constant name after 'const' keyword".to_string());
+        };
+        
+        self.consume(&Token::Equals, &format!("Expected '=' after constant name '{}'", name))?;
+        let value = self.parse_expression()?;
+        
+        Ok(ConstDef { name, value, line })
+    }
+    
+    fn parse_interface_declaration(&mut self) -> Result<InterfaceDef, String> {
+        // The 'interface' keyword has already been consumed
+        let name = if let Some(Token::Identifier(name)) = self.current_token_type() {
+            let name_str = name.clone();
+            self.advance();
+            name_str
+        } else {
+            return Err("Expected interface name after 'interface' keyword".to_string());
+        };
+        
+        self.consume(&Token::LBrace, &format!("Expected '{{' after interface name '{}'", name))?;
+        
+        // Methods are signatures without a body: `fun name(self, ...): type`
+        self.self_type = Some(Type::Param("Self".to_string()));
+        let mut methods: Vec<MethodSig> = Vec::new();
+        while !self.check(&Token::RBrace) && !self.is_at_end() {
+            self.consume(&Token::Fun, &format!("Expected method signature in interface '{}'", name))?;
+            
+            let method = if let Some(Token::Identifier(method)) = self.current_token_type() {
+                let method_str = method.clone();
+                self.advance();
+                method_str
+            } else {
+                return Err(format!("Expected method name in interface '{}'", name));
+            };
+            
+            if methods.iter().any(|existing| existing.name == method) {
+                return Err(format!("Duplicate method '{}' in interface '{}'", method, name));
+            }
+            
+            self.consume(&Token::LParen, "Expected '(' after method name")?;
+            let params = self.parse_params()?;
+            if params.first().map(|(param, _)| param.as_str()) != Some("self") {
+                return Err(format!(
+                    "Method '{}' of interface '{}' must take 'self' as its first parameter",
+                    method, name
+                ));
+            }
+            let return_types = self.parse_return_types()?;
+            
+            methods.push(MethodSig {
+                name: method,
+                params,
+                return_types,
+            });
+        }
+        self.self_type = None;
+        
+        self.consume(&Token::RBrace, &format!("Expected '}}' after methods of interface '{}'", name))?;
+        
+        Ok(InterfaceDef { name, methods })
+    }
+    
+    fn parse_impl(&mut self) -> Result<ImplDef, String> {
+        // The 'impl' keyword has already been consumed
+        let interface = if let Some(Token::Identifier(interface)) = self.current_token_type() {
+            let interface_str = interface.clone();
+            self.advance();
+            interface_str
+        } else {
+            return Err("Expected interface name after 'impl' keyword".to_string());
+        };
+        
+        self.consume(&Token::For, &format!("Expected 'for' after 'impl {}'", interface))?;
+        // Implementing an interface for an alias implements it for the aliased type
+        let target = self.parse_type()?.resolved().clone();
+        
+        self.consume(&Token::LBrace, &format!("Expected '{{' after 'impl {} for {}'", interface, target))?;
+        
+        self.self_type = Some(target.clone());
+        let mut methods: Vec<FunctionDef> = Vec::new();
+        while !self.check(&Token::RBrace) && !self.is_at_end() {
+            self.consume(&Token::Fun, &format!("Expected method in 'impl {} for {}'", interface, target))?;
+            let method = self.parse_function_declaration()?;
+            if methods.iter().any(|existing| existing.name == method.name) {
+                return Err(format!(
+                    "Method '{}' is defined more than once in 'impl {} for {}'",
+                    method.name, interface, target
+                ));
+            }
+            methods.push(method);
+        }
+        self.self_type = None;
+        
+        self.consume(&Token::RBrace, &format!("Expected '}}' after 'impl {} for {}'", interface, target))?;
+        
+        Ok(ImplDef {
+            interface,
+            target,
+            methods,
+        })
+    }
+    
     fn parse_function_declaration(&mut self) -> Result<FunctionDef, String> {
+        let line = self.previous_line();
+        
         // Parse function name
         let name = if let Some(Token::Identifier(name)) = self.current_token_type() {
             let name_str = name.clone();
@@ -48,9 +365,107 @@ impl Parser {
             return Err("Expected function name after 'fun' keyword".to_string());
         };
         
+        // Parse type parameters, which are in scope for the rest of the declaration
+        let (type_params, bounds) = self.parse_type_params()?;
+        let outer_type_params = self.type_params.len();
+        self.type_params.extend(type_params.iter().cloned());
+        
+        let result = self.parse_function_rest(name, type_params, bounds, line);
+        self.type_params.truncate(outer_type_params);
+        result
+    }
+    
+    /// Parses the parameters, return types and body of a function declaration
+    fn parse_function_rest(
+        &mut self,
+        name: String,
+        type_params: Vec<String>,
+        bounds: Vec<(String, String)>,
+        line: usize,
+    ) -> Result<FunctionDef, String> {
         // Parse parameters
         self.consume(&Token::LParen, "Expected '(' after function name")?;
+        let params = self.parse_params()?;
+        
+        // Parse return types
+        let return_types = self.parse_return_types()?;
+        
+        // Parse function body
+        let body = self.parse_block("function body")?;
+        
+        Ok(FunctionDef {
+            name,
+            type_params,
+            bounds,
+            params,
+            return_types,
+            body,
+            line,
+        })
+    }
+    
+    /// Parses an optional `<T, U: Show + Eq>` type parameter list, returning
+    /// the parameters and the interfaces they are constrained by
+    fn parse_type_params(&mut self) -> Result<TypeParams, String> {
+        let mut type_params = Vec::new();
+        let mut bounds = Vec::new();
+        if self.match_token(&Token::LessThan) {
+            loop {
+                let name = match self.current_token_type() {
+                    Some(Token::Identifier(name)) => {
+                        if type_params.contains(name) {
+                            return Err(format!("Duplicate type parameter '{}'", name));
+                        }
+                        name.clone()
+                    }
+                    other => return Err(format!("Expected type parameter name, got {:?}", other)),
+                };
+                self.advance();
+                
+                if self.match_token(&Token::Colon) {
+                    loop {
+                        match self.current_token_type() {
+                            Some(Token::Identifier(interface)) => {
+                                bounds.push((name.clone(), interface.clone()));
+                                self.advance();
+                            }
+                            other => return Err(format!("Expected interface name after '{}:', got {:?}", name, other)),
+                        }
+                        
+                        if !self.match_token(&Token::Plus) {
+                            break;
+                        }
+                    }
+                }
+                type_params.push(name);
+                
+                if !self.match_token(&Token::Comma) {
+                    break;
+                }
+            }
+            self.consume(&Token::GreaterThan, "Expected '>' after type parameters")?;
+        }
+        Ok((type_params, bounds))
+    }
+    
+    fn parse_lambda(&mut self) -> Result<Expr, String> {
+        // The 'fun' keyword has already been consumed
+        let line = self.previous_line();
+        self.consume(&Token::LParen, "Expected '(' after 'fun' in anonymous function")?;
+        let params = self.parse_params()?;
+        let return_types = self.parse_return_types()?;
+        let body = self.parse_block("anonymous function body")?;
         
+        Ok(Expr::Lambda {
+            params,
+            return_types,
+            body,
+            line,
+        })
+    }
+    
+    /// Parses `name: type` pairs up to and including the closing ')'
+    fn parse_params(&mut self) -> Result<Vec<(String, Type)>, String> {
         let mut params = Vec::new();
         if !self.check(&Token::RParen) {
             loop {
@@ -62,6 +477,23 @@ impl Parser {
                     return Err("Expected parameter name".to_string());
                 };
                 
+                // Interface and impl methods take their receiver as `self`
+                if param_name == "self" && !self.check(&Token::Colon) {
+                    let self_type = self
+                        .self_type
+                        .clone()
+                        .ok_or("'self' without a type is only allowed in interface and impl methods")?;
+                    if !params.is_empty() {
+                        return Err("'self' must be the first parameter".to_string());
+                    }
+                    params.push((param_name, self_type));
+                    
+                    if !self.match_token(&Token::Comma) {
+                        break;
+                    }
+                    continue;
+                }
+                
                 self.consume(&Token::Colon, "Expected ':' after parameter name")?;
                 
                 let param_type = self.parse_type()?;
@@ -76,7 +508,10 @@ impl Parser {
         
         self.consume(&Token::RParen, "Expected ')' after parameters")?;
         
-        // Parse return types
+        Ok(params)
+    }
+    
+    fn parse_return_types(&mut self) -> Result<Vec<Type>, String> {
         let mut return_types = Vec::new();
         if self.match_token(&Token::Colon) {
             loop {
@@ -88,9 +523,11 @@ impl Parser {
                 }
             }
         }
-        
-        // Parse function body
-        self.consume(&Token::LBrace, "Expected '{' before function body")?;
+        Ok(return_types)
+    }
+    
+    fn parse_block(&mut self, what: &str) -> Result<Vec<Expr>, String> {
+        self.consume(&Token::LBrace, &format!("Expected '{{' before {}", what))?;
         
         let mut body = Vec::new();
         while !self.check(&Token::RBrace) && !self.is_at_end() {
@@ -98,14 +535,9 @@ impl Parser {
             body.push(expr);
         }
         
-        self.consume(&Token::RBrace, "Expected '}' after function body")?;
+        self.consume(&Token::RBrace, &format!("Expected '}}' after {}", what))?;
         
-        Ok(FunctionDef {
-            name,
-            params,
-            return_types,
-            body,
-        })
+        Ok(body)
     }
     
     fn parse_type(&mut self) -> Result<Type, String> {
@@ -114,18 +546,38 @@ impl Parser {
                 self.advance();
                 Ok(Type::Int)
             }
+            Some(Token::BigIntType) => {
+                self.advance();
+                Ok(Type::BigInt)
+            }
             Some(Token::FloatType) => {
                 self.advance();
                 Ok(Type::Float)
             }
+            Some(Token::DecimalType) => {
+                self.advance();
+                Ok(Type::Decimal)
+            }
+            Some(Token::CharType) => {
+                self.advance();
+                Ok(Type::Char)
+            }
             Some(Token::StringType) => {
                 self.advance();
                 Ok(Type::String)
             }
+            Some(Token::BytesType) => {
+                self.advance();
+                Ok(Type::Bytes)
+            }
             Some(Token::BoolType) => {
                 self.advance();
                 Ok(Type::Bool)
             }
+            Some(Token::AnyType) => {
+                self.advance();
+                Ok(Type::Any)
+            }
             Some(Token::Null) => {
                 self.advance();
                 Ok(Type::Null)
@@ -146,6 +598,51 @@ impl Parser {
                     Ok(Type::List(Box::new(elem_type)))
                 }
             }
+            Some(Token::Identifier(name)) if name == "Self" && self.self_type.is_some() => {
+                self.advance();
+                Ok(self.self_type.clone().unwrap())
+            }
+            Some(Token::Identifier(name)) => {
+                if self.type_params.contains(name) {
+                    let name = name.clone();
+                    self.advance();
+                    Ok(Type::Param(name))
+                } else if self.record_names.contains(name) {
+                    let name = name.clone();
+                    self.advance();
+                    Ok(Type::Record(name))
+                } else if self.enum_names.contains(name) {
+                    let name = name.clone();
+                    self.advance();
+                    Ok(Type::Enum(name))
+                } else if let Some(target) = self.aliases.get(name) {
+                    let alias = Type::Alias(name.clone(), Box::new(target.clone()));
+                    self.advance();
+                    Ok(alias)
+                } else if self.alias_names.contains(name) {
+                    // Aliases can't be used before they are declared, which
+                    // also rules out aliases that refer to themselves
+                    Err(format!("Type alias '{}' is used before its declaration", name))
+                } else {
+                    Err(format!("Unknown type '{}'", name))
+                }
+            }
+            Some(Token::Fun) => {
+                self.advance();
+
+                // Function type fun<T>(ParamType, ...): ReturnType
+                let (type_params, bounds) = self.parse_type_params()?;
+                if !bounds.is_empty() {
+                    return Err("Interface constraints are not allowed in function types".to_string());
+                }
+                let outer_type_params = self.type_params.len();
+                self.type_params.extend(type_params.iter().cloned());
+                let result = self.parse_function_type_rest();
+                self.type_params.truncate(outer_type_params);
+                let (params, returns) = result?;
+
+                Ok(Type::Function { type_params, params, returns })
+            }
             _ => Err(format!(
                 "Expected type, got {:?}",
                 self.current_token_type()
@@ -153,24 +650,679 @@ impl Parser {
         }
     }
     
-    // Simplified parse_expression for now
+    /// Parses a function type after 'fun' and its type parameters
+    fn parse_function_type_rest(&mut self) -> Result<(Vec<Type>, Vec<Type>), String> {
+        self.consume(&Token::LParen, "Expected '(' after 'fun' in function type")?;
+
+        let mut params = Vec::new();
+        if !self.check(&Token::RParen) {
+            loop {
+                params.push(self.parse_type()?);
+
+                if !self.match_token(&Token::Comma) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(&Token::RParen, "Expected ')' after function type parameters")?;
+
+        // A single return type is written bare, several are parenthesized
+        // so they don't swallow the rest of a parameter list
+        let mut returns = Vec::new();
+        if self.match_token(&Token::Colon) {
+            if self.match_token(&Token::LParen) {
+                loop {
+                    returns.push(self.parse_type()?);
+
+                    if !self.match_token(&Token::Comma) {
+                        break;
+                    }
+                }
+                self.consume(&Token::RParen, "Expected ')' after function type return types")?;
+            } else {
+                returns.push(self.parse_type()?);
+            }
+        }
+
+        Ok((params, returns))
+    }
+    
     fn parse_expression(&mut self) -> Result<Expr, String> {
+        match (self.current_token_type(), self.peek_token_type()) {
+            (Some(Token::Identifier(name)), Some(Token::Equals)) => {
+                // Variable declaration or assignment
+                let id = name.clone();
+                let line = self.line();
+                self.advance();
+                self.advance();
+                let value = self.parse_expression()?;
+                Ok(Expr::VarDeclaration {
+                    name: id,
+                    value: Box::new(value),
+                    line,
+                    slot: None,
+                })
+            }
+            (Some(Token::Return), _) => {
+                let line = self.line();
+                self.advance();
+                
+                let mut values = Vec::new();
+                if !self.check(&Token::RBrace) && !self.is_at_end() {
+                    loop {
+                        let value = self.parse_expression()?;
+                        values.push(value);
+                        
+                        if !self.match_token(&Token::Comma) {
+                            break;
+                        }
+                    }
+                }
+                
+                Ok(Expr::Return { values, line })
+            }
+            _ => {
+                let expr = self.parse_or()?;
+                
+                if self.match_token(&Token::Equals) {
+                    let value = self.parse_expression()?;
+                    return match expr {
+                        Expr::FieldAccess { target, field } => Ok(Expr::FieldAssignment {
+                            target,
+                            field,
+                            value: Box::new(value),
+                        }),
+                        Expr::Index { target, index } => Ok(Expr::IndexAssignment {
+                            target,
+                            index,
+                            value: Box::new(value),
+                        }),
+                        other => Err(format!("Invalid assignment target: {:?}", other)),
+                    };
+                }
+                
+                Ok(expr)
+            }
+        }
+    }
+    
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_and()?;
+        while self.match_token(&Token::Or) {
+            let line = self.previous_line();
+            let right = self.parse_and()?;
+            expr = binary(expr, BinaryOperator::Or, right, line);
+        }
+        Ok(expr)
+    }
+    
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_equality()?;
+        while self.match_token(&Token::And) {
+            let line = self.previous_line();
+            let right = self.parse_equality()?;
+            expr = binary(expr, BinaryOperator::And, right, line);
+        }
+        Ok(expr)
+    }
+    
+    fn parse_equality(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_comparison()?;
+        loop {
+            let operator = match self.current_token_type() {
+                Some(Token::DoubleEquals) => BinaryOperator::Equal,
+                Some(Token::NotEquals) => BinaryOperator::NotEqual,
+                _ => break,
+            };
+            self.advance();
+            let line = self.previous_line();
+            let right = self.parse_comparison()?;
+            expr = binary(expr, operator, right, line);
+        }
+        Ok(expr)
+    }
+    
+    fn parse_comparison(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_term()?;
+        loop {
+            // `x is type` and `x is not type`
+            if self.match_token(&Token::Is) {
+                let line = self.previous_line();
+                let is_negated = self.match_token(&Token::NotKeyword);
+                let check_type = self.parse_type()?;
+                expr = Expr::TypeCheck {
+                    expr: Box::new(expr),
+                    check_type,
+                    is_negated,
+                    line,
+                };
+                continue;
+            }
+            
+            let operator = match self.current_token_type() {
+                Some(Token::LessThan) => BinaryOperator::LessThan,
+                Some(Token::LessThanEquals) => BinaryOperator::LessThanOrEqual,
+                Some(Token::GreaterThan) => BinaryOperator::GreaterThan,
+                Some(Token::GreaterThanEquals) => BinaryOperator::GreaterThanOrEqual,
+                _ => break,
+            };
+            self.advance();
+            let line = self.previous_line();
+            let right = self.parse_term()?;
+            expr = binary(expr, operator, right, line);
+        }
+        Ok(expr)
+    }
+    
+    fn parse_term(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_factor()?;
+        loop {
+            let operator = match self.current_token_type() {
+                Some(Token::Plus) => BinaryOperator::Add,
+                Some(Token::Minus) => BinaryOperator::Subtract,
+                _ => break,
+            };
+            self.advance();
+            let line = self.previous_line();
+            let right = self.parse_factor()?;
+            expr = binary(expr, operator, right, line);
+        }
+        Ok(expr)
+    }
+    
+    fn parse_factor(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_cast()?;
+        loop {
+            let operator = match self.current_token_type() {
+                Some(Token::Star) => BinaryOperator::Multiply,
+                Some(Token::Slash) => BinaryOperator::Divide,
+                Some(Token::Percent) => BinaryOperator::Modulo,
+                _ => break,
+            };
+            self.advance();
+            let line = self.previous_line();
+            let right = self.parse_cast()?;
+            expr = binary(expr, operator, right, line);
+        }
+        Ok(expr)
+    }
+    
+    /// `x as T` binds tighter than arithmetic, so `-x as int` casts `-x`
+    fn parse_cast(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_unary()?;
+        while self.match_token(&Token::As) {
+            let target_type = self.parse_type()?;
+            expr = Expr::Cast {
+                expr: Box::new(expr),
+                target_type,
+            };
+        }
+        Ok(expr)
+    }
+    
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        let operator = match self.current_token_type() {
+            Some(Token::Minus) => UnaryOperator::Negate,
+            Some(Token::Not) => UnaryOperator::Not,
+            _ => return self.parse_postfix(),
+        };
+        let line = self.line();
+        self.advance();
+        let expr = self.parse_unary()?;
+        Ok(Expr::UnaryOp {
+            operator,
+            expr: Box::new(expr),
+            line,
+        })
+    }
+    
+    fn parse_postfix(&mut self) -> Result<Expr, String> {
+        let mut expr = self.parse_primary()?;
+        
+        loop {
+            if self.match_token(&Token::LParen) {
+                // Any expression that evaluates to a function can be called
+                let line = self.previous_line();
+                let args = self.parse_args()?;
+                expr = match expr {
+                    Expr::Identifier { name, .. } => Expr::FunctionCall { name, args, slot: None, line },
+                    callee => Expr::Call {
+                        callee: Box::new(callee),
+                        args,
+                        line,
+                    },
+                };
+            } else if self.match_token(&Token::Dot) {
+                let field = match self.current_token_type() {
+                    Some(Token::Identifier(field)) => field.clone(),
+                    other => return Err(format!("Expected field name after '.', got {:?}", other)),
+                };
+                self.advance();
+                expr = if self.match_token(&Token::LParen) {
+                    Expr::MethodCall {
+                        target: Box::new(expr),
+                        method: field,
+                        line: self.previous_line(),
+                        args: self.parse_args()?,
+                        receiver: None,
+                    }
+                } else {
+                    Expr::FieldAccess {
+                        target: Box::new(expr),
+                        field,
+                    }
+                };
+            } else if self.match_token(&Token::LBracket) {
+                // `a[i]`, or a slice `a[i:j]` where either bound can be left out
+                let start = if self.check(&Token::Colon) {
+                    None
+                } else {
+                    Some(Box::new(self.parse_expression()?))
+                };
+                if self.match_token(&Token::Colon) {
+                    let end = if self.check(&Token::RBracket) {
+                        None
+                    } else {
+                        Some(Box::new(self.parse_expression()?))
+                    };
+                    self.consume(&Token::RBracket, "Expected ']' after slice")?;
+                    expr = Expr::Slice {
+                        target: Box::new(expr),
+                        start,
+                        end,
+                    };
+                } else {
+                    self.consume(&Token::RBracket, "Expected ']' after index")?;
+                    expr = Expr::Index {
+                        target: Box::new(expr),
+                        index: start.unwrap(),
+                    };
+                }
+            } else {
+                break;
+            }
+        }
+        
+        Ok(expr)
+    }
+    
+    /// Parses a list `[a, b]` or map `[k: v, ...]` literal after the '['.
+    /// `[]` is an empty list and `[:]` an empty map.
+    fn parse_collection(&mut self) -> Result<Expr, String> {
+        if self.match_token(&Token::RBracket) {
+            return Ok(Expr::List(Vec::new()));
+        }
+        if self.match_token(&Token::Colon) {
+            self.consume(&Token::RBracket, "Expected ']' after ':' in empty map")?;
+            return Ok(Expr::Map(Vec::new()));
+        }
+        
+        let first = self.parse_expression()?;
+        
+        if self.match_token(&Token::Colon) {
+            let value = self.parse_expression()?;
+            let mut entries = vec![(first, value)];
+            while self.match_token(&Token::Comma) {
+                let key = self.parse_expression()?;
+                self.consume(&Token::Colon, "Expected ':' between map key and value")?;
+                let value = self.parse_expression()?;
+                entries.push((key, value));
+            }
+            self.consume(&Token::RBracket, "Expected ']' after map entries")?;
+            Ok(Expr::Map(entries))
+        } else {
+            let mut items = vec![first];
+            while self.match_token(&Token::Comma) {
+                items.push(self.parse_expression()?);
+            }
+            self.consume(&Token::RBracket, "Expected ']' after list items")?;
+            Ok(Expr::List(items))
+        }
+    }
+    
+    /// Parses the `field: value` pairs of a record literal after the '{'
+    fn parse_record_literal(&mut self, name: String) -> Result<Expr, String> {
+        let mut fields = Vec::new();
+        
+        while !self.check(&Token::RBrace) && !self.is_at_end() {
+            let field = match self.current_token_type() {
+                Some(Token::Identifier(field)) => field.clone(),
+                other => return Err(format!("Expected field name in '{}' literal, got {:?}", name, other)),
+            };
+            self.advance();
+            
+            self.consume(&Token::Colon, "Expected ':' after field name")?;
+            let value = self.parse_expression()?;
+            fields.push((field, value));
+            
+            if !self.match_token(&Token::Comma) {
+                break;
+            }
+        }
+        
+        self.consume(&Token::RBrace, &format!("Expected '}}' after '{}' literal", name))?;
+        
+        Ok(Expr::RecordLiteral { name, fields })
+    }
+    
+    fn parse_variant_name(&mut self, enum_name: &str) -> Result<String, String> {
+        match self.current_token_type() {
+            Some(Token::Identifier(variant)) => {
+                let variant = variant.clone();
+                self.advance();
+                Ok(variant)
+            }
+            other => Err(format!("Expected variant name after '{}.', got {:?}", enum_name, other)),
+        }
+    }
+    
+    /// Parses `match value { pattern => body, ... }` after the 'match' keyword.
+    /// An arm's body is either a block or a single expression.
+    fn parse_match(&mut self) -> Result<Expr, String> {
+        let scrutinee = self.parse_expression()?;
+        self.consume(&Token::LBrace, "Expected '{' after match value")?;
+        
+        let mut arms = Vec::new();
+        while !self.check(&Token::RBrace) && !self.is_at_end() {
+            let line = self.line();
+            let pattern = self.parse_pattern()?;
+            self.consume(&Token::FatArrow, "Expected '=>' after match pattern")?;
+            
+            let body = if self.check(&Token::LBrace) {
+                self.parse_block("match arm")?
+            } else {
+                vec![self.parse_expression()?]
+            };
+            arms.push(MatchArm { pattern, body, line });
+            
+            self.match_token(&Token::Comma);
+        }
+        
+        self.consume(&Token::RBrace, "Expected '}' after match arms")?;
+        
+        if arms.is_empty() {
+            return Err("Match must have at least one arm".to_string());
+        }
+        
+        let dispatch = MatchDispatch::new(&arms);
+        Ok(Expr::Match {
+            scrutinee: Box::new(scrutinee),
+            arms,
+            dispatch,
+        })
+    }
+    
+    fn parse_pattern(&mut self) -> Result<Pattern, String> {
+        match self.current_token_type() {
+            Some(Token::Identifier(name)) => {
+                let name = name.clone();
+                self.advance();
+                
+                if name == "_" {
+                    Ok(Pattern::Wildcard)
+                } else if self.enum_names.contains(&name) && self.match_token(&Token::Dot) {
+                    let variant = self.parse_variant_name(&name)?;
+                    let mut fields = Vec::new();
+                    if self.match_token(&Token::LParen) {
+                        if !self.check(&Token::RParen) {
+                            loop {
+                                fields.push(self.parse_pattern()?);
+                                
+                                if !self.match_token(&Token::Comma) {
+                                    break;
+                                }
+                            }
+                        }
+                        self.consume(&Token::RParen, "Expected ')' after variant patterns")?;
+                    }
+                    Ok(Pattern::Variant {
+                        enum_name: name,
+                        variant,
+                        fields,
+                    })
+                } else if self.record_names.contains(&name) && self.match_token(&Token::LBrace) {
+                    // `field: pattern`, or just `field` to bind it to a variable of the same name
+                    let mut fields = Vec::new();
+                    while !self.check(&Token::RBrace) && !self.is_at_end() {
+                        let field = match self.current_token_type() {
+                            Some(Token::Identifier(field)) => field.clone(),
+                            other => return Err(format!("Expected field name in '{}' pattern, got {:?}", name, other)),
+                        };
+                        self.advance();
+                        
+                        let pattern = if self.match_token(&Token::Colon) {
+                            self.parse_pattern()?
+                        } else {
+                            Pattern::Binding(field.clone())
+                        };
+                        fields.push((field, pattern));
+                        
+                        if !self.match_token(&Token::Comma) {
+                            break;
+                        }
+                    }
+                    self.consume(&Token::RBrace, &format!("Expected '}}' after '{}' pattern", name))?;
+                    Ok(Pattern::Record { name, fields })
+                } else {
+                    Ok(Pattern::Binding(name))
+                }
+            }
+            Some(Token::LBracket) => {
+                self.advance();
+                
+                let mut items = Vec::new();
+                let mut rest = None;
+                while !self.check(&Token::RBracket) && !self.is_at_end() {
+                    if self.match_token(&Token::Ellipsis) {
+                        rest = Some(Box::new(self.parse_pattern()?));
+                        break;
+                    }
+                    items.push(self.parse_pattern()?);
+                    
+                    if !self.match_token(&Token::Comma) {
+                        break;
+                    }
+                }
+                self.consume(&Token::RBracket, "Expected ']' after list pattern (a '...' pattern must come last)")?;
+                
+                Ok(Pattern::List { items, rest })
+            }
+            Some(Token::Minus) => {
+                self.advance();
+                match self.current_token_type() {
+                    Some(Token::IntLiteral(n)) => {
+                        let n = -*n;
+                        self.advance();
+                        Ok(Pattern::Literal(Expr::IntLiteral(n)))
+                    }
+                    Some(Token::BigIntLiteral(n)) => {
+                        let n = -n.clone();
+                        self.advance();
+                        Ok(Pattern::Literal(Expr::BigIntLiteral(n)))
+                    }
+                    Some(Token::FloatLiteral(n)) => {
+                        let n = -*n;
+                        self.advance();
+                        Ok(Pattern::Literal(Expr::FloatLiteral(n)))
+                    }
+                    Some(Token::DecimalLiteral(n)) => {
+                        let n = -*n;
+                        self.advance();
+                        Ok(Pattern::Literal(Expr::DecimalLiteral(n)))
+                    }
+                    other => Err(format!("Expected number after '-' in pattern, got {:?}", other)),
+                }
+            }
+            Some(Token::IntLiteral(_)) | Some(Token::BigIntLiteral(_)) | Some(Token::FloatLiteral(_)) |
+            Some(Token::DecimalLiteral(_)) | Some(Token::CharLiteral(_)) | Some(Token::StringLiteral(_)) |
+            Some(Token::BytesLiteral(_)) | Some(Token::True) | Some(Token::False) | Some(Token::Null) => {
+                Ok(Pattern::Literal(self.parse_primary()?))
+            }
+            other => Err(format!("Expected pattern, got {:?}", other)),
+        }
+    }
+    
+    fn parse_if(&mut self) -> Result<Expr, String> {
+        // The 'if' keyword has already been consumed
+        let condition = self.parse_expression()?;
+        let then_branch = self.parse_block("if body")?;
+        
+        let mut else_if_branches = Vec::new();
+        while self.match_token(&Token::ElseIf) {
+            let cond = self.parse_expression()?;
+            let branch = self.parse_block("elseif body")?;
+            else_if_branches.push((cond, branch));
+        }
+        
+        let else_branch = if self.match_token(&Token::Else) {
+            Some(self.parse_block("else body")?)
+        } else {
+            None
+        };
+        
+        Ok(Expr::If {
+            condition: Box::new(condition),
+            then_branch,
+            else_if_branches,
+            else_branch,
+        })
+    }
+    
+    /// Parses the loop forms after the 'loop' keyword:
+    ///
+    /// - `loop { ... }` runs until `break` or `return`
+    /// - `loop cond { ... }` runs while `cond` is true
+    /// - `loop i till cond { ... }` counts `i` up from 0 until `cond` is true
+    /// - `loop i=0, i...n { ... }` counts `i` from 0 up to, but excluding, n
+    /// - `loop init, cond, update { ... }` is a C-style loop
+    fn parse_loop(&mut self) -> Result<Expr, String> {
+        let line = self.previous_line();
+        let mut init = None;
+        let mut condition = None;
+        let mut update = None;
+        
+        if let (Some(Token::Identifier(name)), Some(Token::Till)) =
+            (self.current_token_type(), self.peek_token_type())
+        {
+            let counter = name.clone();
+            self.advance();
+            self.advance();
+            let until = self.parse_expression()?;
+            
+            init = Some(Box::new(Expr::VarDeclaration {
+                name: counter.clone(),
+                value: Box::new(Expr::IntLiteral(0)),
+                line,
+                slot: None,
+            }));
+            condition = Some(Box::new(Expr::UnaryOp {
+                operator: UnaryOperator::Not,
+                expr: Box::new(until),
+                line,
+            }));
+            update = Some(Box::new(increment(counter, line)));
+        } else if !self.check(&Token::LBrace) {
+            let first = self.parse_expression()?;
+            
+            if self.match_token(&Token::Comma) {
+                init = Some(Box::new(first));
+                
+                if let (Some(Token::Identifier(name)), Some(Token::Ellipsis)) =
+                    (self.current_token_type(), self.peek_token_type())
+                {
+                    // Range loop: i...end
+                    let counter = name.clone();
+                    self.advance();
+                    self.advance();
+                    let end = self.parse_expression()?;
+                    
+                    condition = Some(Box::new(binary(
+                        Expr::Identifier { name: counter.clone(), slot: None },
+                        BinaryOperator::LessThan,
+                        end,
+                        line,
+                    )));
+                    update = Some(Box::new(increment(counter, line)));
+                } else {
+                    condition = Some(Box::new(self.parse_expression()?));
+                    
+                    if self.match_token(&Token::Comma) {
+                        update = Some(Box::new(self.parse_expression()?));
+                    }
+                }
+            } else {
+                condition = Some(Box::new(first));
+            }
+        }
+        
+        let body = self.parse_block("loop body")?;
+        
+        Ok(Expr::Loop {
+            init,
+            condition,
+            update,
+            body,
+        })
+    }
+    
+    /// Parses call arguments up to and including the closing ')'
+    fn parse_args(&mut self) -> Result<Vec<Expr>, String> {
+        let mut args = Vec::new();
+        
+        if !self.check(&Token::RParen) {
+            loop {
+                let arg = self.parse_expression()?;
+                args.push(arg);
+                
+                if !self.match_token(&Token::Comma) {
+                    break;
+                }
+            }
+        }
+        
+        self.consume(&Token::RParen, "Expected ')' after function arguments")?;
+        
+        Ok(args)
+    }
+    
+    fn parse_primary(&mut self) -> Result<Expr, String> {
         match self.current_token_type() {
             Some(Token::IntLiteral(n)) => {
                 let value = *n;
                 self.advance();
                 Ok(Expr::IntLiteral(value))
             }
+            Some(Token::BigIntLiteral(n)) => {
+                let value = n.clone();
+                self.advance();
+                Ok(Expr::BigIntLiteral(value))
+            }
             Some(Token::FloatLiteral(n)) => {
                 let value = *n;
                 self.advance();
                 Ok(Expr::FloatLiteral(value))
             }
+            Some(Token::DecimalLiteral(n)) => {
+                let value = *n;
+                self.advance();
+                Ok(Expr::DecimalLiteral(value))
+            }
+            Some(Token::CharLiteral(c)) => {
+                let value = *c;
+                self.advance();
+                Ok(Expr::CharLiteral(value))
+            }
             Some(Token::StringLiteral(s)) => {
                 let value = s.clone();
                 self.advance();
                 Ok(Expr::StringLiteral(value))
             }
+            Some(Token::BytesLiteral(b)) => {
+                let value = b.clone();
+                self.advance();
+                Ok(Expr::BytesLiteral(value))
+            }
             Some(Token::True) => {
                 self.advance();
                 Ok(Expr::BoolLiteral(true))
@@ -183,9 +1335,12 @@ impl Parser {
                 self.advance();
                 Ok(Expr::NullLiteral)
             }
-            Some(Token::IntType) | Some(Token::FloatType) | Some(Token::StringType) | Some(Token::BoolType) => {
-                // Parse type conversion function: int(x), float(x), string(x), bool(x)
+            Some(Token::IntType) | Some(Token::BigIntType) | Some(Token::FloatType) | Some(Token::DecimalType)
+            | Some(Token::CharType) | Some(Token::StringType) | Some(Token::BytesType) | Some(Token::BoolType) => {
+                // Parse type conversion function: int(x), bigint(x), float(x), decimal(x), char(x), string(x),
+                // bytes(x), bool(x)
                 let type_token = self.current_token_type().unwrap().clone();
+                let line = self.line();
                 self.advance();
                 
                 self.consume(&Token::LParen, &format!("Expected '(' after type name"))?;
@@ -196,8 +1351,12 @@ impl Parser {
                 
                 let target_type = match type_token {
                     Token::IntType => Type::Int,
+                    Token::BigIntType => Type::BigInt,
                     Token::FloatType => Type::Float,
+                    Token::DecimalType => Type::Decimal,
+                    Token::CharType => Type::Char,
                     Token::StringType => Type::String,
+                    Token::BytesType => Type::Bytes,
                     Token::BoolType => Type::Bool,
                     _ => unreachable!(),
                 };
@@ -205,39 +1364,85 @@ impl Parser {
                 Ok(Expr::TypeConversion {
                     expr: Box::new(expr),
                     target_type,
+                    line,
                 })
             }
             Some(Token::Identifier(name)) => {
                 let id = name.clone();
                 self.advance();
                 
-                // Check if it's a variable declaration
-                if self.match_token(&Token::Equals) {
-                    let value = self.parse_expression()?;
-                    Ok(Expr::VarDeclaration(id, Box::new(value)))
-                } else if self.match_token(&Token::LParen) {
-                    // Function call
-                    let mut args = Vec::new();
-                    
-                    if !self.check(&Token::RParen) {
-                        loop {
-                            let arg = self.parse_expression()?;
-                            args.push(arg);
-                            
-                            if !self.match_token(&Token::Comma) {
-                                break;
-                            }
-                        }
+                if self.record_names.contains(&id) && self.match_token(&Token::LBrace) {
+                    self.parse_record_literal(id)
+                } else if self.enum_names.contains(&id) && self.match_token(&Token::Dot) {
+                    let variant = self.parse_variant_name(&id)?;
+                    let args = if self.match_token(&Token::LParen) {
+                        self.parse_args()?
+                    } else {
+                        Vec::new()
+                    };
+                    Ok(Expr::VariantLiteral {
+                        enum_name: id,
+                        variant,
+                        args,
+                    })
+                } else {
+                    Ok(Expr::Identifier { name: id, slot: None })
+                }
+            }
+            Some(Token::Fun) => {
+                self.advance();
+                if let Some(Token::Identifier(_)) = self.current_token_type() {
+                    // Nested function declaration
+                    let func_def = self.parse_function_declaration()?;
+                    if !func_def.bounds.is_empty() {
+                        return Err(format!(
+                            "Interface constraints are only allowed on top-level functions, not on '{}'",
+                            func_def.name
+                        ));
                     }
-                    
-                    self.consume(&Token::RParen, "Expected ')' after function arguments")?;
-                    
-                    Ok(Expr::FunctionCall { name: id, args })
+                    Ok(Expr::FunctionDeclaration {
+                        name: func_def.name,
+                        type_params: func_def.type_params,
+                        params: func_def.params,
+                        return_types: func_def.return_types,
+                        body: func_def.body,
+                        line: func_def.line,
+                        slot: None,
+                    })
                 } else {
-                    // Variable reference
-                    Ok(Expr::Identifier(id))
+                    self.parse_lambda()
                 }
             }
+            Some(Token::LBracket) => {
+                self.advance();
+                self.parse_collection()
+            }
+            Some(Token::Match) => {
+                self.advance();
+                self.parse_match()
+            }
+            Some(Token::If) => {
+                self.advance();
+                self.parse_if()
+            }
+            Some(Token::Loop) => {
+                self.advance();
+                self.parse_loop()
+            }
+            Some(Token::Break) => {
+                self.advance();
+                Ok(Expr::Break { line: self.previous_line() })
+            }
+            Some(Token::Continue) => {
+                self.advance();
+                Ok(Expr::Continue { line: self.previous_line() })
+            }
+            Some(Token::LParen) => {
+                self.advance();
+                let expr = self.parse_expression()?;
+                self.consume(&Token::RParen, "Expected ')' after expression")?;
+                Ok(expr)
+            }
             Some(Token::Output) => {
                 self.advance();
                 self.consume(&Token::LParen, "Expected '(' after 'output'")?;
@@ -262,34 +1467,123 @@ impl Parser {
                 self.advance();
                 self.consume(&Token::LParen, "Expected '(' after 'outputf'")?;
                 
-                let format_string = self.parse_expression()?;
+                // A literal format string has its `{value}`s filled in
+                let format_string = match (self.current_token_type(), self.peek_token_type()) {
+                    (Some(Token::StringLiteral(text)), Some(Token::RParen)) => {
+                        let text = text.clone();
+                        let line = self.line();
+                        self.advance();
+                        self.parse_format_string(&text, line)?
+                    }
+                    _ => self.parse_expression()?,
+                };
                 
                 self.consume(&Token::RParen, "Expected ')' after outputf argument")?;
                 
                 Ok(Expr::OutputFormatted(Box::new(format_string)))
             }
-            Some(Token::Return) => {
-                self.advance();
-                
-                let mut values = Vec::new();
-                if !self.check(&Token::RBrace) && !self.is_at_end() {
-                    loop {
-                        let value = self.parse_expression()?;
-                        values.push(value);
-                        
-                        if !self.match_token(&Token::Comma) {
+            _ => Err(format!(
+                "Unexpected token: {:?}",
+                self.current_token_type()
+            )),
+        }
+    }
+    
+    /// Turns the text of `outputf("total: {price:>10.2}")` into the
+    /// concatenation of its literal parts and formatted values. `{{` and
+    /// `}}` stand for braces.
+    fn parse_format_string(&mut self, text: &str, line: usize) -> Result<Expr, String> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = text.char_indices().peekable();
+        
+        while let Some((i, c)) = chars.next() {
+            let doubled = chars.peek().map(|&(_, next)| next) == Some(c);
+            match c {
+                '{' | '}' if doubled => {
+                    chars.next();
+                    literal.push(c);
+                }
+                '}' => return Err(format!("Unmatched '}}' in outputf string on line {}", line)),
+                '{' => {
+                    // The value ends at the matching brace, so that it can
+                    // be a record literal
+                    let mut depth = 1;
+                    let mut end = None;
+                    for (j, c) in chars.by_ref() {
+                        match c {
+                            '{' => depth += 1,
+                            '}' => depth -= 1,
+                            _ => {}
+                        }
+                        if depth == 0 {
+                            end = Some(j);
                             break;
                         }
                     }
+                    let end = end.ok_or_else(|| format!("Unclosed '{{' in outputf string on line {}", line))?;
+                    
+                    if !literal.is_empty() {
+                        parts.push(Expr::StringLiteral(std::mem::take(&mut literal)));
+                    }
+                    parts.push(self.parse_format_field(&text[i + 1..end], line)?);
                 }
-                
-                Ok(Expr::Return(values))
+                c => literal.push(c),
             }
-            _ => Err(format!(
-                "Unexpected token: {:?}",
-                self.current_token_type()
-            )),
         }
+        if !literal.is_empty() || parts.is_empty() {
+            parts.push(Expr::StringLiteral(literal));
+        }
+        
+        let mut parts = parts.into_iter();
+        let first = parts.next().unwrap();
+        Ok(parts.fold(first, |text, part| binary(text, BinaryOperator::Add, part, line)))
+    }
+    
+    /// Parses the `price:>10.2` inside the braces of a format string
+    fn parse_format_field(&mut self, field: &str, line: usize) -> Result<Expr, String> {
+        // The spec follows the last `:` outside brackets, so the colons of a
+        // map literal stay with the value
+        let mut depth = 0;
+        let mut colon = None;
+        for (i, c) in field.char_indices() {
+            match c {
+                '(' | '[' | '{' => depth += 1,
+                ')' | ']' | '}' => depth -= 1,
+                ':' if depth == 0 => colon = Some(i),
+                _ => {}
+            }
+        }
+        let (source, spec) = match colon {
+            Some(i) => (&field[..i], Some(&field[i + 1..])),
+            None => (field, None),
+        };
+        
+        let mut tokens = tokenize(source).map_err(|e| format!("In outputf string on line {}: {}", line, e))?;
+        if tokens.is_empty() {
+            return Err(format!("Missing value in '{{{}}}' in outputf string on line {}", field, line));
+        }
+        for token in &mut tokens {
+            token.line = line;
+        }
+        
+        // The value is parsed in place of the program's tokens
+        let program_tokens = std::mem::replace(&mut self.tokens, tokens);
+        let program_current = std::mem::replace(&mut self.current, 0);
+        let expr = self.parse_expression().and_then(|expr| match self.current_token_type() {
+            None => Ok(expr),
+            Some(token) => Err(format!("Unexpected {} after the value", token)),
+        });
+        self.tokens = program_tokens;
+        self.current = program_current;
+        let expr = expr.map_err(|e| format!("In '{{{}}}' in outputf string on line {}: {}", field, line, e))?;
+        
+        let spec = match spec {
+            Some(spec) => parse_format_spec(spec)
+                .map_err(|e| format!("Invalid format '{}' in outputf string on line {}: {}", spec, line, e))?,
+            None => FormatSpec::default(),
+        };
+        Ok(Expr::Format { expr: Box::new(expr), spec })
     }
     
     // Helper methods for the parser
@@ -297,10 +1591,29 @@ impl Parser {
         self.current >= self.tokens.len()
     }
     
+    /// Line of the current token, or of the last one at the end of input
+    fn line(&self) -> usize {
+        self.tokens
+            .get(self.current)
+            .or(self.tokens.last())
+            .map_or(1, |token| token.line)
+    }
+    
+    /// Line of the token that was just consumed
+    fn previous_line(&self) -> usize {
+        self.tokens
+            .get(self.current.saturating_sub(1))
+            .map_or(1, |token| token.line)
+    }
+    
     fn current_token_type(&self) -> Option<&Token> {
         self.tokens.get(self.current).map(|t| &t.token)
     }
     
+    fn peek_token_type(&self) -> Option<&Token> {
+        self.tokens.get(self.current + 1).map(|t| &t.token)
+    }
+    
     fn advance(&mut self) -> Option<&Token> {
         if !self.is_at_end() {
             self.current += 1;
@@ -344,3 +1657,63 @@ impl Parser {
         }
     }
 }
+
+fn binary(left: Expr, operator: BinaryOperator, right: Expr, line: usize) -> Expr {
+    Expr::BinaryOp {
+        left: Box::new(left),
+        operator,
+        right: Box::new(right),
+        line,
+    }
+}
+
+/// The widest a formatted value can be padded to
+const MAX_FORMAT_WIDTH: usize = 10_000;
+/// The most digits a formatted number can show after the point
+const MAX_FORMAT_PRECISION: u32 = 1_000;
+
+/// Parses a format spec like `>10.2`, each part of which can be left out
+fn parse_format_spec(spec: &str) -> Result<FormatSpec, String> {
+    let invalid = || "expected an alignment (<, > or ^), a width and a precision like .2".to_string();
+    let (align, rest) = match spec.chars().next() {
+        Some('<') => (Some(Align::Left), &spec[1..]),
+        Some('>') => (Some(Align::Right), &spec[1..]),
+        Some('^') => (Some(Align::Center), &spec[1..]),
+        _ => (None, spec),
+    };
+    let (width, precision) = match rest.split_once('.') {
+        Some((width, precision)) => (width, Some(precision)),
+        None => (rest, None),
+    };
+    let is_number = |digits: &str| digits.bytes().all(|b| b.is_ascii_digit());
+    if !is_number(width) || precision.is_some_and(|p| p.is_empty() || !is_number(p)) {
+        return Err(invalid());
+    }
+    // A number with too many digits to parse is too large as well
+    let too_large = |what: &str, max: usize| format!("the {} can be at most {}", what, max);
+    let width = match width {
+        "" => None,
+        width => match width.parse() {
+            Ok(width) if width <= MAX_FORMAT_WIDTH => Some(width),
+            _ => return Err(too_large("width", MAX_FORMAT_WIDTH)),
+        },
+    };
+    let precision = match precision.map(str::parse) {
+        None => None,
+        Some(Ok(precision)) if precision <= MAX_FORMAT_PRECISION => Some(precision),
+        Some(_) => return Err(too_large("precision", MAX_FORMAT_PRECISION as usize)),
+    };
+    Ok(FormatSpec { align, width, precision })
+}
+
+/// Builds `name = name + 1`
+fn increment(name: String, line: usize) -> Expr {
+    let identifier = Expr::Identifier { name: name.clone(), slot: None };
+    let value = binary(identifier, BinaryOperator::Add, Expr::IntLiteral(1), line);
+    Expr::VarDeclaration {
+        name,
+        value: Box::new(value),
+        line,
+        slot: None,
+    }
+}
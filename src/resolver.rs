@@ -0,0 +1,246 @@
+use crate::ast::{Expr, Program, VarSlot};
+use crate::builtins;
+use crate::types::Type;
+use std::collections::HashSet;
+
+/// Works out which variable every name refers to, so the interpreter can
+/// find variables by position instead of searching its scopes by name. The
+/// scopes here mirror the ones the interpreter creates: one per function
+/// call, block, match arm and loop iteration. Names that aren't a visible
+/// variable or a top-level function are reported before the program runs.
+pub fn resolve(mut program: Program) -> Result<Program, String> {
+    let mut resolver = Resolver {
+        scopes: Vec::new(),
+        functions: program.functions.keys().cloned().collect(),
+    };
+
+    let mut functions: Vec<_> = program.functions.values_mut().collect();
+    functions.sort_by_key(|func_def| func_def.line);
+    for func_def in functions {
+        resolver.resolve_top_level(&func_def.params, &mut func_def.body)
+            .map_err(|e| format!("In function '{}': {}", func_def.name, e))?;
+    }
+
+    for impl_def in &mut program.impls {
+        for method in &mut impl_def.methods {
+            resolver.resolve_top_level(&method.params, &mut method.body)
+                .map_err(|e| format!("In method '{}.{}': {}", impl_def.target, method.name, e))?;
+        }
+    }
+
+    // The statements outside functions only run when there is no main
+    if !resolver.functions.contains("main") {
+        resolver.resolve_top_level(&[], &mut program.main_block)?;
+    }
+
+    Ok(program)
+}
+
+struct Resolver {
+    /// The names declared in each enclosing scope, innermost last, in the
+    /// order of their indices
+    scopes: Vec<Vec<String>>,
+    functions: HashSet<String>,
+}
+
+impl Resolver {
+    /// The variable a name refers to. A name declared twice in one scope,
+    /// like a function declared again, refers to the later declaration.
+    fn lookup(&self, name: &str) -> Option<VarSlot> {
+        self.scopes.iter().rev().enumerate().find_map(|(depth, scope)| {
+            let index = scope.iter().rposition(|declared| declared == name)?;
+            Some(VarSlot { depth, index })
+        })
+    }
+
+    fn declare(&mut self, name: &str) -> VarSlot {
+        let scope = self.scopes.last_mut().expect("declaration outside of any scope");
+        scope.push(name.to_string());
+        VarSlot { depth: 0, index: scope.len() - 1 }
+    }
+
+    fn resolve_block(&mut self, body: &mut [Expr]) -> Result<(), String> {
+        self.scopes.push(Vec::new());
+        let result = self.resolve_exprs(body);
+        self.scopes.pop();
+        result
+    }
+
+    /// Top-level functions don't see any variables but their own
+    fn resolve_top_level(&mut self, params: &[(String, Type)], body: &mut [Expr]) -> Result<(), String> {
+        let outer = std::mem::take(&mut self.scopes);
+        let result = self.resolve_function(params, body);
+        self.scopes = outer;
+        result
+    }
+
+    /// Nested functions and lambdas see the scopes they are created in
+    fn resolve_function(&mut self, params: &[(String, Type)], body: &mut [Expr]) -> Result<(), String> {
+        self.scopes.push(params.iter().map(|(name, _)| name.clone()).collect());
+        let result = self.resolve_exprs(body);
+        self.scopes.pop();
+        result
+    }
+
+    fn resolve_exprs<'a>(&mut self, exprs: impl IntoIterator<Item = &'a mut Expr>) -> Result<(), String> {
+        for expr in exprs {
+            self.resolve_expr(expr)?;
+        }
+        Ok(())
+    }
+
+    fn resolve_expr(&mut self, expr: &mut Expr) -> Result<(), String> {
+        match expr {
+            Expr::IntLiteral(_)
+            | Expr::BigIntLiteral(_)
+            | Expr::DecimalLiteral(_)
+            | Expr::FloatLiteral(_)
+            | Expr::CharLiteral(_)
+            | Expr::StringLiteral(_)
+            | Expr::BytesLiteral(_)
+            | Expr::BoolLiteral(_)
+            | Expr::NullLiteral
+            | Expr::Break { .. }
+            | Expr::Continue { .. } => {}
+
+            Expr::Identifier { name, slot } => {
+                *slot = self.lookup(name);
+                // Named functions can be used as values
+                if slot.is_none() && !self.functions.contains(name.as_str()) {
+                    return Err(format!("Undefined variable: {}", name));
+                }
+            }
+            Expr::VarDeclaration { name, value, slot, .. } => {
+                self.resolve_expr(value)?;
+                *slot = Some(self.lookup(name).unwrap_or_else(|| self.declare(name)));
+            }
+
+            Expr::List(items) => self.resolve_exprs(items)?,
+            Expr::Map(entries) => {
+                for (key, value) in entries {
+                    self.resolve_expr(key)?;
+                    self.resolve_expr(value)?;
+                }
+            }
+            Expr::Index { target, index } => {
+                self.resolve_expr(target)?;
+                self.resolve_expr(index)?;
+            }
+            Expr::Slice { target, start, end } => {
+                self.resolve_expr(target)?;
+                self.resolve_exprs(start.as_deref_mut())?;
+                self.resolve_exprs(end.as_deref_mut())?;
+            }
+            Expr::RecordLiteral { fields, .. } => self.resolve_exprs(fields.iter_mut().map(|(_, e)| e))?,
+            Expr::FieldAssignment { target, value, .. } => {
+                self.resolve_expr(value)?;
+                self.resolve_expr(target)?;
+            }
+            Expr::IndexAssignment { target, index, value } => {
+                self.resolve_expr(value)?;
+                self.resolve_expr(target)?;
+                self.resolve_expr(index)?;
+            }
+            Expr::BinaryOp { left, right, .. } => {
+                self.resolve_expr(left)?;
+                self.resolve_expr(right)?;
+            }
+            Expr::VariantLiteral { args, .. } => self.resolve_exprs(args)?,
+
+            Expr::Match { scrutinee, arms, .. } => {
+                self.resolve_expr(scrutinee)?;
+                for arm in arms {
+                    let bindings = arm.pattern.bindings().into_iter().map(String::from).collect();
+                    self.scopes.push(bindings);
+                    let result = self.resolve_exprs(&mut arm.body);
+                    self.scopes.pop();
+                    result?;
+                }
+            }
+            Expr::If { condition, then_branch, else_if_branches, else_branch } => {
+                self.resolve_expr(condition)?;
+                self.resolve_block(then_branch)?;
+                for (cond, branch) in else_if_branches {
+                    self.resolve_expr(cond)?;
+                    self.resolve_block(branch)?;
+                }
+                if let Some(branch) = else_branch {
+                    self.resolve_block(branch)?;
+                }
+            }
+            Expr::Loop { init, condition, update, body } => {
+                self.scopes.push(Vec::new());
+                let result = self.resolve_loop(init.as_deref_mut(), condition, update, body);
+                self.scopes.pop();
+                result?;
+            }
+            Expr::Return { values, .. } => self.resolve_exprs(values)?,
+
+            Expr::FunctionDeclaration { name, params, body, slot, .. } => {
+                // Declared first, so the function can call itself
+                *slot = Some(self.declare(name));
+                self.resolve_function(params, body)?;
+            }
+            Expr::Lambda { params, body, .. } => self.resolve_function(params, body)?,
+            Expr::FunctionCall { name, args, slot, .. } => {
+                *slot = self.lookup(name);
+                let is_function = self.functions.contains(name.as_str()) || builtins::lookup(name).is_some();
+                if slot.is_none() && !is_function {
+                    return Err(format!("Undefined function: {}", name));
+                }
+                self.resolve_exprs(args)?;
+            }
+            Expr::Call { callee, args, .. } => {
+                self.resolve_expr(callee)?;
+                self.resolve_exprs(args)?;
+            }
+            Expr::MethodCall { target, args, .. } => {
+                self.resolve_expr(target)?;
+                self.resolve_exprs(args)?;
+            }
+
+            Expr::Output(args) => self.resolve_exprs(args)?,
+            Expr::FieldAccess { target: expr, .. }
+            | Expr::UnaryOp { expr, .. }
+            | Expr::OutputFormatted(expr)
+            | Expr::Format { expr, .. }
+            | Expr::OutputAddress(expr)
+            | Expr::Input(expr)
+            | Expr::InputFormatted(expr)
+            | Expr::TypeConversion { expr, .. }
+            | Expr::TypeCheck { expr, .. }
+            | Expr::Cast { expr, .. }
+            | Expr::IntToFloat { expr, .. } => self.resolve_expr(expr)?,
+        }
+        Ok(())
+    }
+
+    /// The initializer runs in the loop's scope. Each iteration gets a scope
+    /// of its own, starting with the loop variable, for the condition, the
+    /// update and the body's block.
+    fn resolve_loop(
+        &mut self,
+        init: Option<&mut Expr>,
+        condition: &mut Option<Box<Expr>>,
+        update: &mut Option<Box<Expr>>,
+        body: &mut [Expr],
+    ) -> Result<(), String> {
+        let mut iteration = Vec::new();
+        match init {
+            Some(Expr::VarDeclaration { name, value, slot, .. }) => {
+                self.resolve_expr(value)?;
+                iteration.push(name.clone());
+                *slot = Some(VarSlot { depth: 0, index: 0 });
+            }
+            Some(init_expr) => self.resolve_expr(init_expr)?,
+            None => {}
+        }
+
+        self.scopes.push(iteration);
+        let result = self.resolve_exprs(condition.as_deref_mut())
+            .and_then(|_| self.resolve_exprs(update.as_deref_mut()))
+            .and_then(|_| self.resolve_block(body));
+        self.scopes.pop();
+        result
+    }
+}
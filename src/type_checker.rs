@@ -1,31 +1,112 @@
-use crate::ast::{BinaryOperator, Expr, Program, UnaryOperator};
+use crate::ast::{
+    BinaryOperator, EnumDef, Expr, ImplDef, InterfaceDef, MethodSig, Pattern, Program, RecordDef, UnaryOperator,
+};
+use crate::builtins;
+use crate::interpreter::format_float;
+use crate::match_checker::MatchChecker;
 use crate::types::Type;
-use std::collections::HashMap;
+use crate::warnings::{Warning, WarningKind};
+use std::collections::{HashMap, HashSet};
 
 pub struct TypeChecker {
     variables: HashMap<String, Type>,
     functions: HashMap<String, FunctionType>,
+    records: HashMap<String, RecordDef>,
+    enums: HashMap<String, EnumDef>,
+    interfaces: HashMap<String, InterfaceDef>,
+    /// (interface, type) pairs that have an implementation
+    impls: HashSet<(String, String)>,
+    /// Interfaces required of the type parameters in scope
+    bounds: Vec<(String, String)>,
+    /// Return types of the function being checked, outside any function
+    /// `None`
+    return_types: Option<Vec<Type>>,
+    /// In strict mode `any` is never introduced implicitly: empty collections
+    /// need a cast to give them a type, and `any` values need a cast to be
+    /// used as anything else
+    strict: bool,
+    warnings: Vec<Warning>,
 }
 
 #[derive(Clone)]
 struct FunctionType {
+    type_params: Vec<String>,
+    bounds: Vec<(String, String)>,
     param_types: Vec<(String, Type)>,
     return_types: Vec<Type>,
 }
 
-pub fn check_types(program: &Program) -> Vec<String> {
+/// Type checks the program, returning the errors and the warnings that
+/// need type information
+pub fn check_types(program: &mut Program, strict: bool) -> (Vec<String>, Vec<Warning>) {
     let mut checker = TypeChecker {
         variables: HashMap::new(),
         functions: HashMap::new(),
+        records: program.records.clone(),
+        enums: program.enums.clone(),
+        interfaces: HashMap::new(),
+        impls: HashSet::new(),
+        bounds: Vec::new(),
+        return_types: None,
+        strict,
+        warnings: Vec::new(),
     };
 
     let mut errors = Vec::new();
 
+    // Register interfaces, starting with the built-in ones
+    for interface in builtin_interfaces() {
+        checker.interfaces.insert(interface.name.clone(), interface);
+    }
+    for &(ty, interface) in BUILTIN_IMPLS {
+        checker.impls.insert((interface.to_string(), ty.to_string()));
+    }
+    for (name, interface) in &program.interfaces {
+        if checker.interfaces.contains_key(name) {
+            errors.push(format!("Interface '{}' is built in and can't be redeclared", name));
+            continue;
+        }
+        checker.interfaces.insert(name.clone(), interface.clone());
+    }
+
+    for record in program.records.values() {
+        for (field, ty) in &record.fields {
+            if let Err(e) = check_map_keys(ty) {
+                errors.push(format!("In field '{}' of type '{}': {}", field, record.name, e));
+            }
+        }
+    }
+    for enum_def in program.enums.values() {
+        for (variant, payload) in &enum_def.variants {
+            if let Some(e) = payload.iter().find_map(|ty| check_map_keys(ty).err()) {
+                errors.push(format!("In variant '{}.{}': {}", enum_def.name, variant, e));
+            }
+        }
+    }
+
+    // Register implementations before checking any code, so that methods can
+    // be used anywhere
+    for impl_def in &program.impls {
+        if let Err(e) = checker.register_impl(impl_def) {
+            errors.push(e);
+        }
+    }
+
     // Register function signatures
     for (name, func_def) in &program.functions {
+        for (type_param, interface) in &func_def.bounds {
+            if !checker.interfaces.contains_key(interface) {
+                errors.push(format!(
+                    "Undefined interface '{}' in constraint on '{}' of function '{}'",
+                    interface, type_param, name
+                ));
+            }
+        }
         checker.functions.insert(
             name.clone(),
             FunctionType {
+                type_params: func_def.type_params.clone(),
+                bounds: func_def.bounds.clone(),
                 param_types: func_def.params.clone(),
                 return_types: func_def.return_types.clone(),
             },
@@ -33,183 +114,663 @@ pub fn check_types(program: &Program) -> Vec<String> {
     }
 
     // Check function bodies
-    for (name, func_def) in &program.functions {
+    for (name, func_def) in &mut program.functions {
+        errors.extend(checker.check_function(
+            name,
+            &func_def.bounds,
+            &func_def.params,
+            &func_def.return_types,
+            &mut func_def.body,
+        ));
+    }
+
+    // Check method bodies
+    for impl_def in &mut program.impls {
+        for method in &mut impl_def.methods {
+            errors.extend(checker.check_function(
+                &format!("{}.{}", impl_def.target, method.name),
+                &method.bounds,
+                &method.params,
+                &method.return_types,
+                &mut method.body,
+            ));
+        }
+    }
+
+    // Check main block
+    for expr in &mut program.main_block {
+        if let Err(e) = checker.check_expr(expr) {
+            errors.push(e);
+        }
+    }
+
+    (errors, checker.warnings)
+}
+
+impl TypeChecker {
+    fn compatible(&self, actual: &Type, expected: &Type) -> bool {
+        types_compatible(actual, expected, self.strict)
+    }
+
+    /// The type of a collection holding values of types `a` and `b`, which
+    /// is the more general of the two, e.g. float for an int and a float
+    fn join(&self, a: &Type, b: &Type) -> Option<Type> {
+        if self.compatible(b, a) {
+            Some(a.clone())
+        } else if self.compatible(a, b) {
+            Some(b.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Checks that `impl_def` implements exactly the methods of its interface
+    /// with the right signatures, and records the implementation
+    fn register_impl(&mut self, impl_def: &ImplDef) -> Result<(), String> {
+        let ImplDef { interface, target, methods } = impl_def;
+        let interface_def = self
+            .interfaces
+            .get(interface)
+            .ok_or_else(|| format!("Undefined interface: {}", interface))?;
+
+        if !matches!(
+            target,
+            Type::Int
+                | Type::BigInt
+                | Type::Float
+                | Type::Decimal
+                | Type::Char
+                | Type::String
+                | Type::Bytes
+                | Type::Bool
+                | Type::Record(_)
+                | Type::Enum(_)
+        ) {
+            return Err(format!(
+                "Cannot implement '{}' for {}: interfaces can only be implemented for int, bigint, float, decimal, char, string, bytes, bool, records and enums",
+                interface, target
+            ));
+        }
+
+        let key = (interface.clone(), target.to_string());
+        if self.impls.contains(&key) {
+            return Err(format!("'{}' is already implemented for {}", interface, target));
+        }
+
+        let self_binding = HashMap::from([("Self".to_string(), target.clone())]);
+        for required in &interface_def.methods {
+            let method = methods.iter().find(|m| m.name == required.name).ok_or_else(|| {
+                format!(
+                    "Missing method '{}' in 'impl {} for {}'",
+                    required.name, interface, target
+                )
+            })?;
+
+            let expected = substitute(&sig_type(required), &self_binding);
+            let actual = Type::Function {
+                type_params: method.type_params.clone(),
+                params: method.params.iter().map(|(_, t)| t.clone()).collect(),
+                returns: method.return_types.clone(),
+            };
+            if actual != expected {
+                return Err(format!(
+                    "Method '{}' in 'impl {} for {}' has type {}, but '{}' requires {}",
+                    method.name, interface, target, actual, interface, expected
+                ));
+            }
+        }
+
+        for method in methods {
+            if !interface_def.methods.iter().any(|m| m.name == method.name) {
+                return Err(format!(
+                    "Method '{}' in 'impl {} for {}' is not part of interface '{}'",
+                    method.name, interface, target, interface
+                ));
+            }
+        }
+
+        self.impls.insert(key);
+        Ok(())
+    }
+
+    /// Whether values of type `ty` implement `interface`
+    fn implements(&self, ty: &Type, interface: &str) -> bool {
+        let ty = ty.resolved();
+        match ty {
+            Type::Param(name) => self.bounds.iter().any(|(p, i)| p == name && i == interface),
+            Type::Any => true,
+            _ => self.impls.contains(&(interface.to_string(), ty.to_string())),
+        }
+    }
+
+    /// Finds the interface method `method` of `receiver`, with `Self`
+    /// replaced by the receiver's type
+    fn resolve_method(&self, receiver: &Type, method: &str) -> Result<MethodSig, String> {
+        let mut providers: Vec<&InterfaceDef> = self
+            .interfaces
+            .values()
+            .filter(|interface| interface.methods.iter().any(|m| m.name == method))
+            .collect();
+        providers.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let implemented: Vec<&InterfaceDef> = providers
+            .iter()
+            .copied()
+            .filter(|interface| self.implements(receiver, &interface.name))
+            .collect();
+
+        match implemented.as_slice() {
+            [interface] => {
+                let sig = interface.methods.iter().find(|m| m.name == method).unwrap();
+                let self_binding = HashMap::from([("Self".to_string(), receiver.clone())]);
+                Ok(MethodSig {
+                    name: sig.name.clone(),
+                    params: sig
+                        .params
+                        .iter()
+                        .map(|(p, t)| (p.clone(), substitute(t, &self_binding)))
+                        .collect(),
+                    return_types: sig.return_types.iter().map(|t| substitute(t, &self_binding)).collect(),
+                })
+            }
+            [] if providers.is_empty() => Err(format!("Type {} has no method '{}'", receiver, method)),
+            [] => {
+                let names: Vec<&str> = providers.iter().map(|i| i.name.as_str()).collect();
+                match receiver.resolved() {
+                    Type::Param(name) => Err(format!(
+                        "Type parameter '{}' has no method '{}'; constrain it with '{}: {}'",
+                        name, method, name, names[0]
+                    )),
+                    _ => Err(format!(
+                        "Type {} has no method '{}': it does not implement {}",
+                        receiver,
+                        method,
+                        names.join(" or ")
+                    )),
+                }
+            }
+            _ => {
+                let names: Vec<&str> = implemented.iter().map(|i| i.name.as_str()).collect();
+                Err(format!(
+                    "Method '{}' of type {} is ambiguous: it is provided by {}",
+                    method,
+                    receiver,
+                    names.join(" and ")
+                ))
+            }
+        }
+    }
+
+    /// Checks a function body in its own scope and returns every error found
+    fn check_function(
+        &mut self,
+        name: &str,
+        bounds: &[(String, String)],
+        params: &[(String, Type)],
+        return_types: &[Type],
+        body: &mut [Expr],
+    ) -> Vec<String> {
+        let mut errors = Vec::new();
+        // Functions see the variables of the scope they are declared in
         let mut local_checker = TypeChecker {
-            variables: HashMap::new(),
-            functions: checker.functions.clone(),
+            variables: self.variables.clone(),
+            functions: self.functions.clone(),
+            records: self.records.clone(),
+            enums: self.enums.clone(),
+            interfaces: self.interfaces.clone(),
+            impls: self.impls.clone(),
+            bounds: self.bounds.iter().chain(bounds).cloned().collect(),
+            return_types: Some(return_types.to_vec()),
+            strict: self.strict,
+            warnings: Vec::new(),
         };
 
+        for ty in params.iter().map(|(_, t)| t).chain(return_types) {
+            if let Err(e) = check_map_keys(ty) {
+                errors.push(format!("In function '{}': {}", name, e));
+            }
+        }
+
         // Add parameters to local scope
-        for (param_name, param_type) in &func_def.params {
+        for (param_name, param_type) in params {
             local_checker.variables.insert(param_name.clone(), param_type.clone());
         }
 
         // Check function body
-        for expr in &func_def.body {
+        for expr in body.iter_mut() {
             if let Err(e) = local_checker.check_expr(expr) {
                 errors.push(format!("In function '{}': {}", name, e));
             }
         }
 
-        // Check return type
-        if let Some(last_expr) = func_def.body.last() {
-            if let Expr::Return(values) = last_expr {
-                if values.len() != func_def.return_types.len() {
+        // Check the function ends with a return of every value. The types of
+        // the values are checked with each `return`
+        if let Some(last_expr) = body.last() {
+            if let Expr::Return { values, .. } = last_expr {
+                if values.len() != return_types.len() {
                     errors.push(format!(
                         "Function '{}' returns {} values, but declared to return {} values",
                         name,
                         values.len(),
-                        func_def.return_types.len()
+                        return_types.len()
                     ));
-                } else {
-                    for (i, (value, expected_type)) in values.iter().zip(&func_def.return_types).enumerate() {
-                        if let Ok(actual_type) = local_checker.infer_type(value) {
-                            if !types_compatible(&actual_type, expected_type) {
-                                errors.push(format!(
-                                    "Function '{}' return value {} has type {:?}, expected {:?}",
-                                    name, i, actual_type, expected_type
-                                ));
-                            }
-                        }
-                    }
                 }
-            } else if !func_def.return_types.is_empty() && func_def.return_types != vec![Type::Null] {
+            } else if !return_types.is_empty() && return_types != [Type::Null] {
                 errors.push(format!(
                     "Function '{}' is missing return statement",
                     name
                 ));
             }
         }
-    }
 
-    // Check main block
-    for expr in &program.main_block {
-        if let Err(e) = checker.check_expr(expr) {
-            errors.push(e);
-        }
+        self.warnings.append(&mut local_checker.warnings);
+        errors
     }
 
-    errors
-}
-
-impl TypeChecker {
-    fn check_expr(&mut self, expr: &Expr) -> Result<Type, String> {
+    fn check_expr(&mut self, expr: &mut Expr) -> Result<Type, String> {
         match expr {
             Expr::IntLiteral(_) => Ok(Type::Int),
+            Expr::BigIntLiteral(_) => Ok(Type::BigInt),
             Expr::FloatLiteral(_) => Ok(Type::Float),
+            Expr::DecimalLiteral(_) => Ok(Type::Decimal),
+            Expr::CharLiteral(_) => Ok(Type::Char),
             Expr::StringLiteral(_) => Ok(Type::String),
+            Expr::BytesLiteral(_) => Ok(Type::Bytes),
             Expr::BoolLiteral(_) => Ok(Type::Bool),
             Expr::NullLiteral => Ok(Type::Null),
 
             Expr::List(items) => {
                 if items.is_empty() {
+                    if self.strict {
+                        return Err("Empty list has type [any] in strict mode; give it a type with `[] as [T]`".to_string());
+                    }
                     return Ok(Type::List(Box::new(Type::Any)));
                 }
 
-                let first_type = self.infer_type(&items[0])?;
+                let mut item_types = Vec::with_capacity(items.len());
+                for item in items.iter_mut() {
+                    item_types.push(self.infer_type(item)?);
+                }
                 
-                for (i, item) in items.iter().enumerate().skip(1) {
-                    let item_type = self.infer_type(item)?;
-                    if !types_compatible(&item_type, &first_type) {
-                        return Err(format!(
-                            "List contains mixed types: item {} has type {:?}, expected {:?}",
-                            i, item_type, first_type
-                        ));
-                    }
+                let mut elem_type = item_types[0].clone();
+                for (i, item_type) in item_types.iter().enumerate().skip(1) {
+                    elem_type = self.join(&elem_type, item_type).ok_or_else(|| {
+                        format!(
+                            "List contains mixed types: item {} has type {}, expected {}",
+                            i, item_type, elem_type
+                        )
+                    })?;
+                }
+                for (item, item_type) in items.iter_mut().zip(&item_types) {
+                    convert_ints(item, item_type, &elem_type);
                 }
 
-                Ok(Type::List(Box::new(first_type)))
+                Ok(Type::List(Box::new(elem_type)))
             }
 
             Expr::Map(entries) => {
                 if entries.is_empty() {
+                    if self.strict {
+                        return Err("Empty map has type [any:any] in strict mode; give it a type with `[:] as [K:V]`".to_string());
+                    }
                     return Ok(Type::Map(Box::new(Type::Any), Box::new(Type::Any)));
                 }
 
-                let first_key_type = self.infer_type(&entries[0].0)?;
-                let first_val_type = self.infer_type(&entries[0].1)?;
+                let mut entry_types = Vec::with_capacity(entries.len());
+                let mut key_type = self.infer_type(&mut entries[0].0)?;
+                let mut val_type = self.infer_type(&mut entries[0].1)?;
+                entry_types.push((key_type.clone(), val_type.clone()));
+                if !is_hashable(&key_type) {
+                    return Err(format!("Map keys must be int, bigint, float, decimal, char, string, bytes or bool, not {}", key_type));
+                }
                 
-                for (i, (key, val)) in entries.iter().enumerate().skip(1) {
-                    let key_type = self.infer_type(key)?;
-                    if !types_compatible(&key_type, &first_key_type) {
-                        return Err(format!(
-                            "Map contains mixed key types: entry {} has key type {:?}, expected {:?}",
-                            i, key_type, first_key_type
-                        ));
+                for i in 1..entries.len() {
+                    let key = &entries[i].0;
+                    if is_literal(key) && entries[..i].iter().any(|(k, _)| k == key) {
+                        return Err(format!("Map key {} is given more than once", literal_text(key)));
                     }
+                    
+                    let (key, val) = &mut entries[i];
+                    let entry_key_type = self.infer_type(key)?;
+                    key_type = self.join(&key_type, &entry_key_type).ok_or_else(|| {
+                        format!(
+                            "Map contains mixed key types: entry {} has key type {}, expected {}",
+                            i, entry_key_type, key_type
+                        )
+                    })?;
 
-                    let val_type = self.infer_type(val)?;
-                    if !types_compatible(&val_type, &first_val_type) {
-                        return Err(format!(
-                            "Map contains mixed value types: entry {} has value type {:?}, expected {:?}",
-                            i, val_type, first_val_type
-                        ));
-                    }
+                    let entry_val_type = self.infer_type(val)?;
+                    val_type = self.join(&val_type, &entry_val_type).ok_or_else(|| {
+                        format!(
+                            "Map contains mixed value types: entry {} has value type {}, expected {}",
+                            i, entry_val_type, val_type
+                        )
+                    })?;
+                    entry_types.push((entry_key_type, entry_val_type));
+                }
+                for ((key, val), (entry_key_type, entry_val_type)) in entries.iter_mut().zip(&entry_types) {
+                    convert_ints(key, entry_key_type, &key_type);
+                    convert_ints(val, entry_val_type, &val_type);
                 }
 
-                Ok(Type::Map(Box::new(first_key_type), Box::new(first_val_type)))
+                Ok(Type::Map(Box::new(key_type), Box::new(val_type)))
             }
 
-            Expr::Identifier(name) => {
+            Expr::Identifier { name, .. } => {
                 if let Some(var_type) = self.variables.get(name) {
                     Ok(var_type.clone())
+                } else if let Some(func_type) = self.functions.get(name) {
+                    // Named functions can be used as values, except when
+                    // their constraints have to be checked at each call
+                    if !func_type.bounds.is_empty() {
+                        return Err(format!(
+                            "Function '{}' has interface constraints and can only be called directly",
+                            name
+                        ));
+                    }
+                    Ok(func_type.to_type())
                 } else {
                     Err(format!("Undefined variable: {}", name))
                 }
             }
 
-            Expr::VarDeclaration(name, value) => {
+            Expr::VarDeclaration { name, value, .. } => {
                 let value_type = self.infer_type(value)?;
+                // Assigning to an existing variable keeps its type, which
+                // closures sharing the variable rely on
+                if let Some(existing_type) = self.variables.get(name) {
+                    if !self.compatible(&value_type, existing_type) {
+                        return Err(format!(
+                            "Cannot assign value of type {} to variable '{}' of type {}",
+                            value_type, name, existing_type
+                        ));
+                    }
+                    convert_ints(value, &value_type, existing_type);
+                    return Ok(existing_type.clone());
+                }
                 self.variables.insert(name.clone(), value_type.clone());
                 Ok(value_type)
             }
 
-            Expr::BinaryOp { left, operator, right } => {
+            Expr::Index { target, index } => {
+                let target_type = self.infer_type(target)?;
+                let index_type = self.infer_type(index)?;
+
+                match target_type.resolved() {
+                    Type::List(elem_type) => {
+                        if index_type != Type::Int {
+                            return Err(format!("List index must be int, got {}", index_type));
+                        }
+                        Ok((**elem_type).clone())
+                    }
+                    Type::Map(key_type, val_type) => {
+                        if !self.compatible(&index_type, key_type) {
+                            return Err(format!("Map key must be {}, got {}", key_type, index_type));
+                        }
+                        Ok((**val_type).clone())
+                    }
+                    Type::String => {
+                        if index_type != Type::Int {
+                            return Err(format!("String index must be int, got {}", index_type));
+                        }
+                        Ok(Type::Char)
+                    }
+                    Type::Bytes => {
+                        if index_type != Type::Int {
+                            return Err(format!("Bytes index must be int, got {}", index_type));
+                        }
+                        Ok(Type::Int)
+                    }
+                    Type::Any => Ok(Type::Any),
+                    _ => Err(format!("Cannot index into value of type {}", target_type)),
+                }
+            }
+
+            Expr::Slice { target, start, end } => {
+                let target_type = self.infer_type(target)?;
+                for bound in [start, end].into_iter().flatten() {
+                    let bound_type = self.infer_type(bound)?;
+                    if bound_type != Type::Int {
+                        return Err(format!("Slice bounds must be int, got {}", bound_type));
+                    }
+                }
+
+                match target_type.resolved() {
+                    Type::List(_) | Type::String | Type::Bytes | Type::Any => Ok(target_type),
+                    _ => Err(format!("Cannot slice value of type {}", target_type)),
+                }
+            }
+
+            Expr::RecordLiteral { name, fields } => {
+                let record_def = self
+                    .records
+                    .get(name)
+                    .cloned()
+                    .ok_or_else(|| format!("Undefined type: {}", name))?;
+
+                for i in 0..fields.len() {
+                    let field = &fields[i].0;
+                    if fields[..i].iter().any(|(f, _)| f == field) {
+                        return Err(format!("Field '{}' is given more than once in '{}' literal", field, name));
+                    }
+
+                    let (field, value) = &mut fields[i];
+                    let expected_type = record_def
+                        .fields
+                        .iter()
+                        .find(|(f, _)| f == field)
+                        .map(|(_, t)| t)
+                        .ok_or_else(|| format!("Type '{}' has no field '{}'", name, field))?;
+
+                    let value_type = self.infer_type(value)?;
+                    if !self.compatible(&value_type, expected_type) {
+                        return Err(format!(
+                            "Field '{}' of '{}' has type {}, got {}",
+                            field, name, expected_type, value_type
+                        ));
+                    }
+                    convert_ints(value, &value_type, expected_type);
+                }
+
+                for (field, _) in &record_def.fields {
+                    if !fields.iter().any(|(f, _)| f == field) {
+                        return Err(format!("Missing field '{}' in '{}' literal", field, name));
+                    }
+                }
+
+                Ok(Type::Record(name.clone()))
+            }
+
+            Expr::FieldAccess { target, field } => {
+                let target_type = self.infer_type(target)?;
+                self.field_type(&target_type, field)
+            }
+
+            Expr::FieldAssignment { target, field, value } => {
+                let target_type = self.infer_type(target)?;
+                let field_type = self.field_type(&target_type, field)?;
+
+                let value_type = self.infer_type(value)?;
+                if !self.compatible(&value_type, &field_type) {
+                    return Err(format!(
+                        "Cannot assign value of type {} to field '{}' of type {}",
+                        value_type, field, field_type
+                    ));
+                }
+                convert_ints(value, &value_type, &field_type);
+                Ok(field_type)
+            }
+
+            Expr::IndexAssignment { target, index, value } => {
+                let target_type = self.infer_type(target)?;
+                let index_type = self.infer_type(index)?;
+
+                let element_type = match target_type.resolved() {
+                    Type::List(elem_type) => {
+                        if index_type != Type::Int {
+                            return Err(format!("List index must be int, got {}", index_type));
+                        }
+                        (**elem_type).clone()
+                    }
+                    Type::Map(key_type, val_type) => {
+                        if !self.compatible(&index_type, key_type) {
+                            return Err(format!("Map key must be {}, got {}", key_type, index_type));
+                        }
+                        convert_ints(index, &index_type, key_type);
+                        (**val_type).clone()
+                    }
+                    Type::Bytes => {
+                        if index_type != Type::Int {
+                            return Err(format!("Bytes index must be int, got {}", index_type));
+                        }
+                        Type::Int
+                    }
+                    Type::Any => Type::Any,
+                    _ => return Err(format!("Cannot assign to an index of value of type {}", target_type)),
+                };
+
+                let value_type = self.infer_type(value)?;
+                if !self.compatible(&value_type, &element_type) {
+                    return Err(format!(
+                        "Cannot assign value of type {} to an element of {}",
+                        value_type, target_type
+                    ));
+                }
+                convert_ints(value, &value_type, &element_type);
+                Ok(element_type)
+            }
+
+            Expr::VariantLiteral { enum_name, variant, args } => {
+                let payload = self.variant_payload(enum_name, variant)?;
+
+                if args.len() != payload.len() {
+                    return Err(format!(
+                        "Variant '{}.{}' expects {} values, got {}",
+                        enum_name,
+                        variant,
+                        payload.len(),
+                        args.len()
+                    ));
+                }
+
+                for (i, (arg, expected_type)) in args.iter_mut().zip(&payload).enumerate() {
+                    let arg_type = self.infer_type(arg)?;
+                    if !self.compatible(&arg_type, expected_type) {
+                        return Err(format!(
+                            "Variant '{}.{}' value {} has type {}, expected {}",
+                            enum_name, variant, i, arg_type, expected_type
+                        ));
+                    }
+                    convert_ints(arg, &arg_type, expected_type);
+                }
+
+                Ok(Type::Enum(enum_name.clone()))
+            }
+
+            Expr::Match { scrutinee, arms, .. } => {
+                let scrutinee_type = self.infer_type(scrutinee)?;
+
+                let mut arm_types = Vec::new();
+                for arm in arms.iter_mut() {
+                    let mut bindings = Vec::new();
+                    self.check_pattern(&arm.pattern, &scrutinee_type, &mut bindings)?;
+
+                    // Each arm is its own scope holding the pattern's bindings
+                    let outer_variables = self.variables.clone();
+                    self.variables.extend(bindings);
+                    let mut arm_type = Ok(Type::Null);
+                    for expr in &mut arm.body {
+                        arm_type = self.check_expr(expr);
+                        if arm_type.is_err() {
+                            break;
+                        }
+                    }
+                    self.variables = outer_variables;
+                    arm_types.push(arm_type?);
+                }
+
+                let patterns: Vec<&Pattern> = arms.iter().map(|arm| &arm.pattern).collect();
+                MatchChecker::new(&self.records, &self.enums).check(&patterns, &scrutinee_type)?;
+
+                // A match has a value if all arms agree on its type; otherwise
+                // it can only be used as a statement
+                let mut match_type = Some(arm_types[0].clone());
+                for arm_type in &arm_types[1..] {
+                    match_type = match_type.and_then(|match_type| self.join(&match_type, arm_type));
+                }
+                let Some(match_type) = match_type else {
+                    return Ok(Type::Null);
+                };
+                for (arm, arm_type) in arms.iter_mut().zip(&arm_types) {
+                    if let Some(value) = arm.body.last_mut() {
+                        convert_ints(value, arm_type, &match_type);
+                    }
+                }
+                Ok(match_type)
+            }
+
+            Expr::BinaryOp { left, operator, right, line } => {
                 let left_type = self.infer_type(left)?;
                 let right_type = self.infer_type(right)?;
 
                 match operator {
                     BinaryOperator::Add => {
-                        match (&left_type, &right_type) {
-                            (Type::Int, Type::Int) => Ok(Type::Int),
-                            (Type::Float, Type::Float) => Ok(Type::Float),
-                            (Type::Int, Type::Float) | (Type::Float, Type::Int) => Ok(Type::Float),
+                        if let Some(result_type) = numeric_result(&left_type, &right_type) {
+                            return Ok(result_type);
+                        }
+                        match (left_type.resolved(), right_type.resolved()) {
                             (Type::String, Type::String) => Ok(Type::String),
+                            (Type::Bytes, Type::Bytes) => Ok(Type::Bytes),
                             _ => Err(format!(
-                                "Cannot add values of types {:?} and {:?}",
-                                left_type, right_type
+                                "Cannot add values of types {} and {}{}",
+                                left_type, right_type, decimal_hint(&left_type, &right_type)
                             )),
                         }
                     }
                     BinaryOperator::Subtract | BinaryOperator::Multiply | BinaryOperator::Divide | BinaryOperator::Modulo => {
-                        match (&left_type, &right_type) {
-                            (Type::Int, Type::Int) => Ok(Type::Int),
-                            (Type::Float, Type::Float) => Ok(Type::Float),
-                            (Type::Int, Type::Float) | (Type::Float, Type::Int) => Ok(Type::Float),
-                            _ => Err(format!(
-                                "Cannot perform arithmetic on types {:?} and {:?}",
-                                left_type, right_type
-                            )),
-                        }
+                        numeric_result(&left_type, &right_type).ok_or_else(|| {
+                            format!(
+                                "Cannot perform arithmetic on types {} and {}{}",
+                                left_type, right_type, decimal_hint(&left_type, &right_type)
+                            )
+                        })
                     }
                     BinaryOperator::Equal | BinaryOperator::NotEqual => {
-                        if types_compatible(&left_type, &right_type) {
+                        if let (integer @ (Type::Int | Type::BigInt), Type::Float) | (Type::Float, integer @ (Type::Int | Type::BigInt)) =
+                            (left_type.resolved(), right_type.resolved())
+                        {
+                            let symbol = if *operator == BinaryOperator::Equal { "==" } else { "!=" };
+                            self.warnings.push(Warning::new(
+                                *line,
+                                WarningKind::MixedComparison,
+                                format!(
+                                    "Comparing {} with {} using '{}' converts the {} to float first",
+                                    left_type, right_type, symbol, integer
+                                ),
+                            ));
+                        }
+
+                        if self.compatible(&left_type, &right_type) || numeric_result(&left_type, &right_type).is_some() {
                             Ok(Type::Bool)
                         } else {
                             Err(format!(
-                                "Cannot compare values of incompatible types {:?} and {:?}",
+                                "Cannot compare values of incompatible types {} and {}",
                                 left_type, right_type
                             ))
                         }
                     }
                     BinaryOperator::LessThan | BinaryOperator::LessThanOrEqual |
                     BinaryOperator::GreaterThan | BinaryOperator::GreaterThanOrEqual => {
-                        match (&left_type, &right_type) {
-                            (Type::Int, Type::Int) | (Type::Float, Type::Float) |
-                            (Type::Int, Type::Float) | (Type::Float, Type::Int) => Ok(Type::Bool),
-                            (Type::String, Type::String) => Ok(Type::Bool),
+                        match (left_type.resolved(), right_type.resolved()) {
+                            _ if numeric_result(&left_type, &right_type).is_some() => Ok(Type::Bool),
+                            (Type::Char, Type::Char) | (Type::String, Type::String) | (Type::Bytes, Type::Bytes) => Ok(Type::Bool),
+                            _ if left_type == right_type && self.implements(&left_type, "Ord") => Ok(Type::Bool),
+                            (Type::Record(_) | Type::Enum(_) | Type::Param(_), _) if left_type == right_type => Err(format!(
+                                "Cannot order values of type {}: it does not implement Ord",
+                                left_type
+                            )),
                             _ => Err(format!(
-                                "Cannot compare values of types {:?} and {:?}",
+                                "Cannot compare values of types {} and {}",
                                 left_type, right_type
                             )),
                         }
@@ -219,7 +780,7 @@ impl TypeChecker {
                             Ok(Type::Bool)
                         } else {
                             Err(format!(
-                                "Logical operators require boolean operands, got {:?} and {:?}",
+                                "Logical operators require boolean operands, got {} and {}",
                                 left_type, right_type
                             ))
                         }
@@ -227,22 +788,24 @@ impl TypeChecker {
                 }
             }
 
-            Expr::UnaryOp { operator, expr } => {
+            Expr::UnaryOp { operator, expr, .. } => {
                 let expr_type = self.infer_type(expr)?;
 
                 match operator {
                     UnaryOperator::Negate => {
-                        match expr_type {
+                        match expr_type.resolved() {
                             Type::Int => Ok(Type::Int),
+                            Type::BigInt => Ok(Type::BigInt),
                             Type::Float => Ok(Type::Float),
-                            _ => Err(format!("Cannot negate value of type {:?}", expr_type)),
+                            Type::Decimal => Ok(Type::Decimal),
+                            _ => Err(format!("Cannot negate value of type {}", expr_type)),
                         }
                     }
                     UnaryOperator::Not => {
                         if expr_type == Type::Bool {
                             Ok(Type::Bool)
                         } else {
-                            Err(format!("Cannot apply logical NOT to type {:?}", expr_type))
+                            Err(format!("Cannot apply logical NOT to type {}", expr_type))
                         }
                     }
                     UnaryOperator::AddressOf => {
@@ -256,31 +819,25 @@ impl TypeChecker {
             Expr::If { condition, then_branch, else_if_branches, else_branch } => {
                 let cond_type = self.infer_type(condition)?;
                 if cond_type != Type::Bool {
-                    return Err(format!("If condition must be boolean, got {:?}", cond_type));
+                    return Err(format!("If condition must be boolean, got {}", cond_type));
                 }
 
                 // Check then branch
-                for expr in then_branch {
-                    self.check_expr(expr)?;
-                }
+                self.check_block(then_branch)?;
 
                 // Check else-if branches
                 for (cond, branch) in else_if_branches {
                     let cond_type = self.infer_type(cond)?;
                     if cond_type != Type::Bool {
-                        return Err(format!("Else-if condition must be boolean, got {:?}", cond_type));
+                        return Err(format!("Else-if condition must be boolean, got {}", cond_type));
                     }
 
-                    for expr in branch {
-                        self.check_expr(expr)?;
-                    }
+                    self.check_block(branch)?;
                 }
 
                 // Check else branch
                 if let Some(branch) = else_branch {
-                    for expr in branch {
-                        self.check_expr(expr)?;
-                    }
+                    self.check_block(branch)?;
                 }
 
                 // If expressions don't have a specific return type in this language
@@ -288,74 +845,120 @@ impl TypeChecker {
             }
 
             Expr::Loop { init, condition, update, body } => {
-                // Check initialization
-                if let Some(init_expr) = init {
-                    self.check_expr(init_expr)?;
-                }
+                // Variables declared by the loop are scoped to it
+                let outer_variables = self.variables.clone();
+                let result = self.check_loop(init.as_deref_mut(), condition.as_deref_mut(), update.as_deref_mut(), body);
+                self.variables = outer_variables;
+                result
+            }
 
-                // Check condition
-                if let Some(cond_expr) = condition {
-                    let cond_type = self.infer_type(cond_expr)?;
-                    if cond_type != Type::Bool {
-                        return Err(format!("Loop condition must be boolean, got {:?}", cond_type));
+            Expr::Continue { .. } | Expr::Break { .. } => Ok(Type::Null),
+
+            Expr::Return { values, .. } => {
+                let return_types = self.return_types.clone().unwrap_or_default();
+                for (i, value) in values.iter_mut().enumerate() {
+                    let value_type = self.infer_type(value)?;
+                    if let Some(expected_type) = return_types.get(i) {
+                        if !self.compatible(&value_type, expected_type) {
+                            return Err(format!(
+                                "Return value {} has type {}, expected {}",
+                                i, value_type, expected_type
+                            ));
+                        }
+                        convert_ints(value, &value_type, expected_type);
                     }
                 }
+                Ok(Type::Null)
+            }
 
-                // Check update
-                if let Some(update_expr) = update {
-                    self.check_expr(update_expr)?;
-                }
+            Expr::FunctionCall { name, args, .. } => {
+                // A variable holding a function shadows a named function
+                let (func_type, bounds) = match self.variables.get(name) {
+                    Some(var_type) => (var_type.clone(), Vec::new()),
+                    None => match (self.functions.get(name), builtins::lookup(name)) {
+                        (Some(func_type), _) => (func_type.to_type(), func_type.bounds.clone()),
+                        (None, Some((_, builtin))) => (builtin.to_type(), Vec::new()),
+                        (None, None) => return Err(format!("Undefined function: {}", name)),
+                    },
+                };
 
-                // Check body
-                for expr in body {
-                    self.check_expr(expr)?;
-                }
+                self.check_call(name, &func_type, &bounds, args)
+            }
 
-                Ok(Type::Null)
+            Expr::Call { callee, args, .. } => {
+                let callee_type = self.infer_type(callee)?;
+                self.check_call("anonymous", &callee_type, &[], args)
             }
 
-            Expr::Continue | Expr::Break => Ok(Type::Null),
+            Expr::MethodCall { target, method, args, receiver, .. } => {
+                let target_type = self.infer_type(target)?;
 
-            Expr::Return(values) => {
-                for value in values {
-                    self.check_expr(value)?;
+                // A record field holding a function is called like a method
+                if let Type::Record(name) = target_type.resolved() {
+                    let has_field = self
+                        .records
+                        .get(name)
+                        .is_some_and(|def| def.fields.iter().any(|(f, _)| f == method));
+                    if has_field {
+                        let field_type = self.field_type(&target_type, method)?;
+                        return self.check_call(method, &field_type, &[], args);
+                    }
                 }
-                Ok(Type::Null)
-            }
 
-            Expr::FunctionCall { name, args } => {
-                if let Some(func_type) = self.functions.get(name) {
-                    if args.len() != func_type.param_types.len() {
-                        return Err(format!(
-                            "Function '{}' expects {} arguments, got {}",
-                            name,
-                            func_type.param_types.len(),
-                            args.len()
-                        ));
+                if target_type == Type::Any {
+                    for arg in args {
+                        self.check_expr(arg)?;
                     }
+                    return Ok(Type::Any);
+                }
 
-                    for (i, (arg, (_, expected_type))) in args.iter().zip(&func_type.param_types).enumerate() {
-                        let arg_type = self.infer_type(arg)?;
-                        if !types_compatible(&arg_type, expected_type) {
-                            return Err(format!(
-                                "Function '{}' argument {} has type {:?}, expected {:?}",
-                                name, i, arg_type, expected_type
-                            ));
-                        }
-                    }
+                let sig = self.resolve_method(&target_type, method)?;
+                if !matches!(target_type.resolved(), Type::Param(_)) {
+                    *receiver = Some(target_type.resolved().to_string());
+                }
+                let method_type = Type::Function {
+                    type_params: Vec::new(),
+                    params: sig.params[1..].iter().map(|(_, t)| t.clone()).collect(),
+                    returns: sig.return_types,
+                };
+                self.check_call(&format!("{}.{}", target_type, method), &method_type, &[], args)
+            }
 
-                    if func_type.return_types.is_empty() {
-                        Ok(Type::Null)
-                    } else if func_type.return_types.len() == 1 {
-                        Ok(func_type.return_types[0].clone())
-                    } else {
-                        // For multiple return values, we'd need a tuple type
-                        // For simplicity, we'll just return the first value's type
-                        Ok(func_type.return_types[0].clone())
-                    }
-                } else {
-                    Err(format!("Undefined function: {}", name))
+            Expr::FunctionDeclaration { name, type_params, params, return_types, body, .. } => {
+                let func_type = Type::Function {
+                    type_params: type_params.clone(),
+                    params: params.iter().map(|(_, t)| t.clone()).collect(),
+                    returns: return_types.clone(),
+                };
+
+                // Declare the function first so that it can call itself
+                self.variables.insert(name.clone(), func_type.clone());
+
+                if let Some(error) = self
+                    .check_function(name, &[], params, return_types, body)
+                    .into_iter()
+                    .next()
+                {
+                    return Err(error);
+                }
+
+                Ok(func_type)
+            }
+
+            Expr::Lambda { params, return_types, body, .. } => {
+                if let Some(error) = self
+                    .check_function("anonymous", &[], params, return_types, body)
+                    .into_iter()
+                    .next()
+                {
+                    return Err(error);
                 }
+
+                Ok(Type::Function {
+                    type_params: Vec::new(),
+                    params: params.iter().map(|(_, t)| t.clone()).collect(),
+                    returns: return_types.clone(),
+                })
             }
 
             Expr::Output(args) => {
@@ -369,11 +972,24 @@ impl TypeChecker {
             Expr::OutputFormatted(expr) => {
                 let expr_type = self.check_expr(expr)?;
                 if expr_type != Type::String {
-                    return Err(format!("outputf requires a string argument, got {:?}", expr_type));
+                    return Err(format!("outputf requires a string argument, got {}", expr_type));
                 }
                 Ok(Type::Null)
             }
 
+            Expr::Format { expr, spec } => {
+                let expr_type = self.check_expr(expr)?;
+                if let Some(precision) = spec.precision {
+                    if !matches!(expr_type.resolved(), Type::Float | Type::Decimal | Type::Any) {
+                        return Err(format!(
+                            "Precision '.{}' in outputf needs a float or decimal, got {}",
+                            precision, expr_type
+                        ));
+                    }
+                }
+                Ok(Type::String)
+            }
+
             Expr::OutputAddress(expr) => {
                 self.check_expr(expr)?;
                 Ok(Type::Null)
@@ -382,7 +998,7 @@ impl TypeChecker {
             Expr::Input(expr) => {
                 let expr_type = self.check_expr(expr)?;
                 if expr_type != Type::String {
-                    return Err(format!("input requires a string prompt, got {:?}", expr_type));
+                    return Err(format!("input requires a string prompt, got {}", expr_type));
                 }
                 Ok(Type::String)
             }
@@ -390,70 +1006,682 @@ impl TypeChecker {
             Expr::InputFormatted(expr) => {
                 let expr_type = self.check_expr(expr)?;
                 if expr_type != Type::String {
-                    return Err(format!("inputf requires a string argument, got {:?}", expr_type));
+                    return Err(format!("inputf requires a string argument, got {}", expr_type));
                 }
                 Ok(Type::String)
             }
 
-            Expr::TypeConversion { expr, target_type } => {
+            Expr::TypeConversion { expr, target_type, .. } => {
                 let expr_type = self.infer_type(expr)?;
                 
-                match (&expr_type, target_type) {
-                    (Type::Int, Type::Float) => Ok(Type::Float),
-                    (Type::Float, Type::Int) => Ok(Type::Int),
-                    (Type::Int, Type::String) => Ok(Type::String),
-                    (Type::Float, Type::String) => Ok(Type::String),
-                    (Type::Bool, Type::String) => Ok(Type::String),
-                    (Type::String, Type::Int) => Ok(Type::Int),
-                    (Type::String, Type::Float) => Ok(Type::Float),
-                    (Type::String, Type::Bool) => Ok(Type::Bool),
-                    _ => Err(format!("Cannot convert from {:?} to {:?}", expr_type, target_type)),
+                if converts(&expr_type, target_type) {
+                    Ok(target_type.clone())
+                } else {
+                    Err(format!("Cannot convert from {} to {}", expr_type, target_type))
                 }
             }
 
-            Expr::TypeCheck { expr, check_type, .. } => {
-                // Type check expressions always return a boolean
-                self.check_expr(expr)?;
+            // Only put in by the type checker, around an expression it has
+            // checked
+            Expr::IntToFloat { target_type, .. } => Ok(target_type.clone()),
+
+            Expr::Cast { expr, target_type } => {
+                check_map_keys(target_type)?;
+                // Casting is how empty collections get a type in strict mode
+                let source_type = match &**expr {
+                    Expr::List(items) if items.is_empty() => Type::List(Box::new(Type::Any)),
+                    Expr::Map(entries) if entries.is_empty() => Type::Map(Box::new(Type::Any), Box::new(Type::Any)),
+                    _ => self.infer_type(expr)?,
+                };
+
+                // A cast doesn't convert values, it only moves between a type
+                // and a more or less precise version of it
+                if types_compatible(&source_type, target_type, false) {
+                    convert_ints(expr, &source_type, target_type);
+                    Ok(target_type.clone())
+                } else if types_compatible(target_type, &source_type, false) {
+                    Ok(target_type.clone())
+                } else if converts(&source_type, target_type) {
+                    Err(format!(
+                        "Cannot cast {} to {}; use {}(x) to convert the value",
+                        source_type, target_type, target_type.resolved()
+                    ))
+                } else {
+                    Err(format!("Cannot cast {} to {}", source_type, target_type))
+                }
+            }
+
+            Expr::TypeCheck { expr, check_type, is_negated, line } => {
+                check_map_keys(check_type)?;
+                let expr_type = self.infer_type(expr)?;
+                if always_has_type(&expr_type, check_type) {
+                    self.warnings.push(Warning::new(
+                        *line,
+                        WarningKind::RedundantIs,
+                        format!(
+                            "'is {}{}' is always {}: the value has type {}",
+                            if *is_negated { "not " } else { "" },
+                            check_type,
+                            !*is_negated,
+                            expr_type
+                        ),
+                    ));
+                }
                 Ok(Type::Bool)
             }
+        }
+    }
+
+    fn variant_payload(&self, enum_name: &str, variant: &str) -> Result<Vec<Type>, String> {
+        let enum_def = self
+            .enums
+            .get(enum_name)
+            .ok_or_else(|| format!("Undefined type: {}", enum_name))?;
+        enum_def
+            .variants
+            .iter()
+            .find(|(v, _)| v == variant)
+            .map(|(_, payload)| payload.clone())
+            .ok_or_else(|| format!("Enum '{}' has no variant '{}'", enum_name, variant))
+    }
+
+    /// Checks that `pattern` can match values of type `expected`, collecting
+    /// the variables it binds
+    fn check_pattern(
+        &self,
+        pattern: &Pattern,
+        expected: &Type,
+        bindings: &mut Vec<(String, Type)>,
+    ) -> Result<(), String> {
+        match pattern {
+            Pattern::Wildcard => Ok(()),
+            Pattern::Binding(name) => {
+                if bindings.iter().any(|(bound, _)| bound == name) {
+                    return Err(format!("Variable '{}' is bound more than once in the same pattern", name));
+                }
+                bindings.push((name.clone(), expected.clone()));
+                Ok(())
+            }
+            Pattern::Literal(literal) => {
+                let literal_type = match literal {
+                    Expr::IntLiteral(_) => Type::Int,
+                    Expr::BigIntLiteral(_) => Type::BigInt,
+                    Expr::FloatLiteral(_) => Type::Float,
+                    Expr::DecimalLiteral(_) => Type::Decimal,
+                    Expr::CharLiteral(_) => Type::Char,
+                    Expr::StringLiteral(_) => Type::String,
+                    Expr::BytesLiteral(_) => Type::Bytes,
+                    Expr::BoolLiteral(_) => Type::Bool,
+                    _ => Type::Null,
+                };
+                if self.compatible(&literal_type, expected) {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "Pattern of type {} cannot match a value of type {}",
+                        literal_type, expected
+                    ))
+                }
+            }
+            Pattern::Variant { enum_name, variant, fields } => {
+                match expected.resolved() {
+                    Type::Enum(name) if name == enum_name => {}
+                    Type::Any => {}
+                    other => {
+                        return Err(format!(
+                            "Pattern '{}.{}' cannot match a value of type {}",
+                            enum_name, variant, other
+                        ))
+                    }
+                }
+
+                let payload = self.variant_payload(enum_name, variant)?;
+                if fields.len() != payload.len() {
+                    return Err(format!(
+                        "Pattern for variant '{}.{}' has {} fields, expected {}",
+                        enum_name,
+                        variant,
+                        fields.len(),
+                        payload.len()
+                    ));
+                }
+
+                for (field, field_type) in fields.iter().zip(&payload) {
+                    self.check_pattern(field, field_type, bindings)?;
+                }
+                Ok(())
+            }
+            Pattern::List { items, rest } => {
+                let elem_type = match expected.resolved() {
+                    Type::List(elem_type) => (**elem_type).clone(),
+                    Type::Any => Type::Any,
+                    other => return Err(format!("List pattern cannot match a value of type {}", other)),
+                };
+
+                for item in items {
+                    self.check_pattern(item, &elem_type, bindings)?;
+                }
+                if let Some(rest) = rest {
+                    self.check_pattern(rest, &Type::List(Box::new(elem_type)), bindings)?;
+                }
+                Ok(())
+            }
+            Pattern::Record { name, fields } => {
+                match expected.resolved() {
+                    Type::Record(expected_name) if expected_name == name => {}
+                    Type::Any => {}
+                    other => return Err(format!("Pattern '{}' cannot match a value of type {}", name, other)),
+                }
+
+                for (field, field_pattern) in fields {
+                    let field_type = self.field_type(&Type::Record(name.clone()), field)?;
+                    self.check_pattern(field_pattern, &field_type, bindings)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn field_type(&self, target_type: &Type, field: &str) -> Result<Type, String> {
+        match target_type.resolved() {
+            Type::Record(name) => {
+                let record_def = self
+                    .records
+                    .get(name)
+                    .ok_or_else(|| format!("Undefined type: {}", name))?;
+                record_def
+                    .fields
+                    .iter()
+                    .find(|(f, _)| f == field)
+                    .map(|(_, t)| t.clone())
+                    .ok_or_else(|| format!("Type '{}' has no field '{}'", name, field))
+            }
+            Type::Any => Ok(Type::Any),
+            other => Err(format!("Cannot access field '{}' of non-record type {}", field, other)),
+        }
+    }
+
+    /// Checks a block in its own scope: variables declared inside it are not
+    /// visible afterwards
+    fn check_block(&mut self, body: &mut [Expr]) -> Result<(), String> {
+        let outer_variables = self.variables.clone();
+        let result = body.iter_mut().try_for_each(|expr| self.check_expr(expr).map(|_| ()));
+        self.variables = outer_variables;
+        result
+    }
+
+    fn check_loop(
+        &mut self,
+        init: Option<&mut Expr>,
+        condition: Option<&mut Expr>,
+        update: Option<&mut Expr>,
+        body: &mut [Expr],
+    ) -> Result<Type, String> {
+        // Check initialization. The loop variable is always a new variable,
+        // even if it shadows an outer one
+        if let Some(init_expr) = init {
+            if let Expr::VarDeclaration { name, value, .. } = init_expr {
+                let value_type = self.infer_type(value)?;
+                self.variables.insert(name.clone(), value_type);
+            } else {
+                self.check_expr(init_expr)?;
+            }
+        }
+
+        // Check condition
+        if let Some(cond_expr) = condition {
+            let cond_type = self.infer_type(cond_expr)?;
+            if cond_type != Type::Bool {
+                return Err(format!("Loop condition must be boolean, got {}", cond_type));
+            }
+        }
 
-            _ => Err(format!("Type checking not implemented for {:?}", expr)),
+        // Check update
+        if let Some(update_expr) = update {
+            self.check_expr(update_expr)?;
         }
+
+        // Check body
+        self.check_block(body)?;
+
+        Ok(Type::Null)
     }
 
-    fn infer_type(&self, expr: &Expr) -> Result<Type, String> {
+    /// Checks a call of a value with type `callee_type` and returns the call's
+    /// type. Type parameters of a generic callee are inferred from the
+    /// arguments and must implement the interfaces in `bounds`.
+    fn check_call(
+        &mut self,
+        name: &str,
+        callee_type: &Type,
+        bounds: &[(String, String)],
+        args: &mut [Expr],
+    ) -> Result<Type, String> {
+        let (type_params, param_types, return_types) = match callee_type.resolved() {
+            Type::Function { type_params, params, returns } => (type_params, params, returns),
+            Type::Any => {
+                for arg in args {
+                    self.check_expr(arg)?;
+                }
+                return Ok(Type::Any);
+            }
+            other => return Err(format!("Cannot call '{}' of non-function type {}", name, other)),
+        };
+
+        if args.len() != param_types.len() {
+            return Err(format!(
+                "Function '{}' expects {} arguments, got {}",
+                name,
+                param_types.len(),
+                args.len()
+            ));
+        }
+
+        let mut arg_types = Vec::new();
+        for arg in args.iter_mut() {
+            arg_types.push(self.infer_type(arg)?);
+        }
+
+        // Instantiate the type parameters
+        let mut bindings = HashMap::new();
+        for (arg_type, param_type) in arg_types.iter().zip(param_types) {
+            unify(param_type, arg_type, type_params, &mut bindings)
+                .map_err(|e| format!("In call to '{}': {}", name, e))?;
+        }
+        // An argument that doesn't have the shape of its parameter leaves the
+        // parameter's type parameters unbound, and is the error to report
+        for (i, ((arg, arg_type), param_type)) in args.iter_mut().zip(&arg_types).zip(param_types).enumerate() {
+            let expected_type = substitute(param_type, &bindings);
+            if !self.compatible(arg_type, &expected_type) {
+                return Err(format!(
+                    "Function '{}' argument {} has type {}, expected {}",
+                    name, i, arg_type, expected_type
+                ));
+            }
+            convert_ints(arg, arg_type, &expected_type);
+        }
+        for type_param in type_params {
+            if !bindings.contains_key(type_param) {
+                return Err(format!(
+                    "Cannot infer type parameter '{}' of function '{}' from its arguments",
+                    type_param, name
+                ));
+            }
+        }
+        for (type_param, interface) in bounds {
+            let bound_type = &bindings[type_param];
+            if !self.implements(bound_type, interface) {
+                return Err(format!(
+                    "Type {} does not implement {}, which function '{}' requires of '{}'",
+                    bound_type, interface, name, type_param
+                ));
+            }
+        }
+
+        if return_types.is_empty() {
+            Ok(Type::Null)
+        } else {
+            // For multiple return values, we'd need a tuple type
+            // For simplicity, we'll just return the first value's type
+            Ok(substitute(&return_types[0], &bindings))
+        }
+    }
+
+    fn infer_type(&mut self, expr: &mut Expr) -> Result<Type, String> {
         match expr {
             Expr::IntLiteral(_) => Ok(Type::Int),
+            Expr::BigIntLiteral(_) => Ok(Type::BigInt),
             Expr::FloatLiteral(_) => Ok(Type::Float),
+            Expr::DecimalLiteral(_) => Ok(Type::Decimal),
+            Expr::CharLiteral(_) => Ok(Type::Char),
             Expr::StringLiteral(_) => Ok(Type::String),
+            Expr::BytesLiteral(_) => Ok(Type::Bytes),
             Expr::BoolLiteral(_) => Ok(Type::Bool),
             Expr::NullLiteral => Ok(Type::Null),
             
-            Expr::Identifier(name) => {
-                if let Some(var_type) = self.variables.get(name) {
-                    Ok(var_type.clone())
-                } else {
-                    Err(format!("Undefined variable: {}", name))
-                }
-            },
-            
-            Expr::TypeConversion { expr, target_type } => {
+            Expr::TypeConversion { target_type, .. } => {
                 // For type inference, we just return the target type
                 Ok(target_type.clone())
             },
             
-            // For other expressions, we need to evaluate them
-            _ => Err(format!("Cannot infer type of complex expression: {:?}", expr)),
+            // For other expressions, we need to check them
+            _ => self.check_expr(expr),
+        }
+    }
+}
+
+impl FunctionType {
+    fn to_type(&self) -> Type {
+        Type::Function {
+            type_params: self.type_params.clone(),
+            params: self.param_types.iter().map(|(_, t)| t.clone()).collect(),
+            returns: self.return_types.clone(),
         }
     }
 }
 
-fn types_compatible(actual: &Type, expected: &Type) -> bool {
-    match (actual, expected) {
+/// Interfaces every program can use. The interpreter implements them for the
+/// types listed in `BUILTIN_IMPLS`.
+fn builtin_interfaces() -> Vec<InterfaceDef> {
+    let self_type = Type::Param("Self".to_string());
+    let method = |name: &str, takes_other: bool, returns: Type| {
+        let mut params = vec![("self".to_string(), self_type.clone())];
+        if takes_other {
+            params.push(("other".to_string(), self_type.clone()));
+        }
+        MethodSig {
+            name: name.to_string(),
+            params,
+            return_types: vec![returns],
+        }
+    };
+
+    vec![
+        InterfaceDef {
+            name: "Show".to_string(),
+            methods: vec![method("show", false, Type::String)],
+        },
+        InterfaceDef {
+            name: "Eq".to_string(),
+            methods: vec![method("eq", true, Type::Bool)],
+        },
+        // `cmp` returns a negative number, zero or a positive number
+        InterfaceDef {
+            name: "Ord".to_string(),
+            methods: vec![method("cmp", true, Type::Int)],
+        },
+    ]
+}
+
+const BUILTIN_IMPLS: &[(&str, &str)] = &[
+    ("int", "Show"),
+    ("bigint", "Show"),
+    ("float", "Show"),
+    ("decimal", "Show"),
+    ("char", "Show"),
+    ("string", "Show"),
+    ("bytes", "Show"),
+    ("bool", "Show"),
+    ("int", "Eq"),
+    ("bigint", "Eq"),
+    ("float", "Eq"),
+    ("decimal", "Eq"),
+    ("char", "Eq"),
+    ("string", "Eq"),
+    ("bytes", "Eq"),
+    ("bool", "Eq"),
+    ("int", "Ord"),
+    ("bigint", "Ord"),
+    ("float", "Ord"),
+    ("decimal", "Ord"),
+    ("char", "Ord"),
+    ("string", "Ord"),
+    ("bytes", "Ord"),
+];
+
+/// Whether every value of type `actual` passes an `is checked` test at
+/// runtime, so that the test tells nothing
+/// Whether values of a type can be map keys. Values of `any` and type
+/// parameters are checked when they are used.
+fn is_hashable(ty: &Type) -> bool {
+    matches!(
+        ty.resolved(),
+        Type::Int
+            | Type::BigInt
+            | Type::Float
+            | Type::Decimal
+            | Type::Char
+            | Type::String
+            | Type::Bytes
+            | Type::Bool
+            | Type::Any
+            | Type::Param(_)
+    )
+}
+
+/// Rejects map types with keys that can't be hashed anywhere in a written type
+fn check_map_keys(ty: &Type) -> Result<(), String> {
+    match ty.resolved() {
+        Type::Map(key, value) => {
+            if !is_hashable(key) {
+                return Err(format!("Map keys must be int, bigint, float, decimal, char, string, bytes or bool, not {}", key));
+            }
+            check_map_keys(value)
+        }
+        Type::List(elem) => check_map_keys(elem),
+        Type::Function { params, returns, .. } => params.iter().chain(returns).try_for_each(check_map_keys),
+        _ => Ok(()),
+    }
+}
+
+fn is_literal(expr: &Expr) -> bool {
+    matches!(
+        expr,
+        Expr::IntLiteral(_)
+            | Expr::BigIntLiteral(_)
+            | Expr::FloatLiteral(_)
+            | Expr::DecimalLiteral(_)
+            | Expr::CharLiteral(_)
+            | Expr::StringLiteral(_)
+            | Expr::BytesLiteral(_)
+            | Expr::BoolLiteral(_)
+    )
+}
+
+fn literal_text(expr: &Expr) -> String {
+    match expr {
+        Expr::IntLiteral(n) => n.to_string(),
+        Expr::BigIntLiteral(n) => format!("{}n", n),
+        Expr::FloatLiteral(n) => format_float(*n),
+        Expr::DecimalLiteral(n) => format!("{}d", n),
+        Expr::CharLiteral(c) => format!("'{}'", c.escape_debug()),
+        Expr::StringLiteral(s) => format!("\"{}\"", s),
+        Expr::BytesLiteral(b) => format!("b\"{}\"", b.escape_ascii()),
+        Expr::BoolLiteral(b) => b.to_string(),
+        other => format!("{:?}", other),
+    }
+}
+
+/// The type of arithmetic on two numbers. Mixing an int with a bigint gives
+/// a bigint, and mixing either with a float gives a float. Decimals only mix
+/// with ints, since a float or a bigint can't be turned into a decimal
+/// without losing something.
+fn numeric_result(left: &Type, right: &Type) -> Option<Type> {
+    match (left.resolved(), right.resolved()) {
+        (Type::Int, Type::Int) => Some(Type::Int),
+        (Type::Int | Type::Decimal, Type::Int | Type::Decimal) => Some(Type::Decimal),
+        (Type::Int | Type::BigInt, Type::Int | Type::BigInt) => Some(Type::BigInt),
+        (Type::Int | Type::BigInt | Type::Float, Type::Int | Type::BigInt | Type::Float) => Some(Type::Float),
+        _ => None,
+    }
+}
+
+/// Decimals don't mix with floats or bigints, which the error for mixing
+/// them explains
+fn decimal_hint(left: &Type, right: &Type) -> String {
+    match (left.resolved(), right.resolved()) {
+        (Type::Decimal, other @ (Type::Float | Type::BigInt)) | (other @ (Type::Float | Type::BigInt), Type::Decimal) => {
+            format!("; convert with decimal(x) or {}(x) first", other)
+        }
+        _ => String::new(),
+    }
+}
+
+fn always_has_type(actual: &Type, checked: &Type) -> bool {
+    match (actual.resolved(), checked.resolved()) {
+        (_, Type::Any) => true,
+        (Type::Any, _) | (Type::Param(_), _) => false,
+        (Type::Int, Type::Float) => true,
+        (Type::List(actual_elem), Type::List(checked_elem)) => always_has_type(actual_elem, checked_elem),
+        (Type::Map(actual_key, actual_val), Type::Map(checked_key, checked_val)) => {
+            always_has_type(actual_key, checked_key) && always_has_type(actual_val, checked_val)
+        }
+        (a, c) => a == c,
+    }
+}
+
+fn sig_type(sig: &MethodSig) -> Type {
+    Type::Function {
+        type_params: Vec::new(),
+        params: sig.params.iter().map(|(_, t)| t.clone()).collect(),
+        returns: sig.return_types.clone(),
+    }
+}
+
+/// Whether a conversion like `float(x)` can turn a value of type `from`
+/// into one of type `to`
+fn converts(from: &Type, to: &Type) -> bool {
+    matches!(
+        (from.resolved(), to.resolved()),
+        (Type::Int, Type::Float)
+            | (Type::Float, Type::Int)
+            | (Type::Int | Type::Float, Type::BigInt)
+            | (Type::BigInt, Type::Int | Type::Float)
+            | (Type::Int | Type::BigInt | Type::Float | Type::String, Type::Decimal)
+            | (Type::Decimal, Type::Int | Type::BigInt | Type::Float)
+            | (Type::Int | Type::BigInt | Type::Float | Type::Decimal | Type::Bool, Type::String)
+            | (Type::String, Type::Int | Type::BigInt | Type::Float | Type::Bool)
+            | (Type::Char, Type::Int | Type::String)
+            | (Type::Int | Type::String, Type::Char)
+            | (Type::String, Type::Bytes)
+            | (Type::Bytes, Type::String)
+    )
+}
+
+/// Makes `expr`, of type `actual`, a value of type `expected`, which it is
+/// compatible with: where `expected` has floats and `actual` ints, the ints
+/// become floats
+fn convert_ints(expr: &mut Expr, actual: &Type, expected: &Type) {
+    if !has_ints_for_floats(actual, expected) {
+        return;
+    }
+    *expr = match std::mem::replace(expr, Expr::NullLiteral) {
+        Expr::IntLiteral(n) => Expr::FloatLiteral(n as f64),
+        other => Expr::IntToFloat {
+            expr: Box::new(other),
+            target_type: expected.clone(),
+        },
+    };
+}
+
+fn has_ints_for_floats(actual: &Type, expected: &Type) -> bool {
+    match (actual.resolved(), expected.resolved()) {
+        (Type::Int, Type::Float) => true,
+        (Type::List(actual_elem), Type::List(expected_elem)) => has_ints_for_floats(actual_elem, expected_elem),
+        (Type::Map(actual_key, actual_val), Type::Map(expected_key, expected_val)) => {
+            has_ints_for_floats(actual_key, expected_key) || has_ints_for_floats(actual_val, expected_val)
+        }
+        _ => false,
+    }
+}
+
+/// Whether a value of type `actual` can be used where `expected` is required.
+/// Outside strict mode `any` values can be used as any type.
+fn types_compatible(actual: &Type, expected: &Type, strict: bool) -> bool {
+    match (actual.resolved(), expected.resolved()) {
         (a, b) if a == b => true,
         (_, Type::Any) => true,
+        (Type::Any, _) => !strict,
         (Type::Int, Type::Float) => true,
-        (Type::Float, Type::Int) => true,
+        (Type::List(actual_elem), Type::List(expected_elem)) => types_compatible(actual_elem, expected_elem, strict),
+        (Type::Map(actual_key, actual_val), Type::Map(expected_key, expected_val)) => {
+            types_compatible(actual_key, expected_key, strict) && types_compatible(actual_val, expected_val, strict)
+        }
+        (
+            Type::Function { type_params: actual_vars, params: actual_params, returns: actual_returns },
+            Type::Function { type_params: expected_vars, params: expected_params, returns: expected_returns },
+        ) => {
+            if actual_params.len() != expected_params.len() || actual_returns.len() != expected_returns.len() {
+                return false;
+            }
+
+            // A generic function can be used where one of its instantiations
+            // is expected; two generic functions must agree up to renaming
+            let mut bindings = HashMap::new();
+            if expected_vars.is_empty() {
+                for (a, e) in actual_params.iter().zip(expected_params).chain(actual_returns.iter().zip(expected_returns)) {
+                    if unify(a, e, actual_vars, &mut bindings).is_err() {
+                        return false;
+                    }
+                }
+            } else if actual_vars.len() == expected_vars.len() {
+                for (a, e) in actual_vars.iter().zip(expected_vars) {
+                    bindings.insert(a.clone(), Type::Param(e.clone()));
+                }
+            } else {
+                return false;
+            }
+
+            actual_params.iter().zip(expected_params).all(|(a, e)| types_compatible(e, &substitute(a, &bindings), strict))
+                && actual_returns.iter().zip(expected_returns).all(|(a, e)| types_compatible(&substitute(a, &bindings), e, strict))
+        }
         _ => false,
     }
 }
+
+/// Binds the type parameters `vars` occurring in `pattern` to the
+/// corresponding parts of `actual`
+fn unify(pattern: &Type, actual: &Type, vars: &[String], bindings: &mut HashMap<String, Type>) -> Result<(), String> {
+    match (pattern.resolved(), actual.resolved()) {
+        (Type::Param(name), _) if vars.contains(name) => {
+            match bindings.get(name) {
+                // Empty collections don't pin down a type parameter
+                None | Some(Type::Any) => {
+                    bindings.insert(name.clone(), actual.clone());
+                }
+                Some(bound) if *actual == Type::Any || types_compatible(actual, bound, false) => {}
+                // An int and a float make the parameter a float
+                Some(bound) if types_compatible(bound, actual, false) => {
+                    bindings.insert(name.clone(), actual.clone());
+                }
+                Some(bound) => {
+                    return Err(format!(
+                        "Conflicting types for type parameter '{}': {} and {}",
+                        name, bound, actual
+                    ));
+                }
+            }
+            Ok(())
+        }
+        (Type::List(pattern_elem), Type::List(actual_elem)) => unify(pattern_elem, actual_elem, vars, bindings),
+        (Type::Map(pattern_key, pattern_val), Type::Map(actual_key, actual_val)) => {
+            unify(pattern_key, actual_key, vars, bindings)?;
+            unify(pattern_val, actual_val, vars, bindings)
+        }
+        (
+            Type::Function { params: pattern_params, returns: pattern_returns, .. },
+            Type::Function { type_params: actual_vars, params: actual_params, returns: actual_returns },
+        ) => {
+            // A generic argument takes its instantiation from the other
+            // arguments instead, see types_compatible
+            if !actual_vars.is_empty() {
+                return Ok(());
+            }
+
+            for (p, a) in pattern_params.iter().zip(actual_params) {
+                unify(p, a, vars, bindings)?;
+            }
+            for (p, a) in pattern_returns.iter().zip(actual_returns) {
+                unify(p, a, vars, bindings)?;
+            }
+            Ok(())
+        }
+        // Anything else is left to the compatibility check
+        _ => Ok(()),
+    }
+}
+
+/// Replaces bound type parameters in `ty`
+fn substitute(ty: &Type, bindings: &HashMap<String, Type>) -> Type {
+    match ty {
+        Type::Param(name) => bindings.get(name).cloned().unwrap_or_else(|| ty.clone()),
+        Type::List(elem) => Type::List(Box::new(substitute(elem, bindings))),
+        Type::Map(key, val) => Type::Map(Box::new(substitute(key, bindings)), Box::new(substitute(val, bindings))),
+        Type::Function { type_params, params, returns } => Type::Function {
+            type_params: type_params.clone(),
+            params: params.iter().map(|p| substitute(p, bindings)).collect(),
+            returns: returns.iter().map(|r| substitute(r, bindings)).collect(),
+        },
+        _ => ty.clone(),
+    }
+}
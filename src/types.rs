@@ -1,77 +1,187 @@
+use crate::bytecode::Closure;
+use crate::interpreter::Scope;
+use im::{HashMap, Vector};
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, ToPrimitive};
+use rust_decimal::Decimal;
 use std::fmt;
+use std::rc::Rc;
 
 /// Type system for Boba language
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub enum Type {
     Int,
+    /// An integer of any size, e.g. `12345678901234567890n`
+    BigInt,
     Float,
+    /// An exact decimal number, e.g. `12.50d`
+    Decimal,
+    /// A Unicode character, e.g. `'a'`
+    Char,
     String,
+    /// A sequence of bytes, e.g. `b"\x89PNG"`
+    Bytes,
     Bool,
     Null,
     List(Box<Type>),
     Map(Box<Type>, Box<Type>),
     Function {
+        type_params: Vec<String>,
         params: Vec<Type>,
         returns: Vec<Type>,
     },
+    /// A user-defined record type, e.g. `Point` after `type Point { x: float, y: float }`
+    Record(String),
+    /// A user-defined enum type, e.g. `Shape` after `enum Shape { Circle(float), Empty }`
+    Enum(String),
+    /// A type parameter of a generic function, e.g. `T` in `fun first<T>(xs: [T]): T`
+    Param(String),
+    /// A type written with an alias name, e.g. `Scores` after
+    /// `alias Scores = [string:[int]]`. It is the same type as its target
+    /// but displays as the name.
+    Alias(String, Box<Type>),
     Any,
 }
 
+impl Type {
+    /// Looks through aliases to the type they stand for
+    pub fn resolved(&self) -> &Type {
+        match self {
+            Type::Alias(_, target) => target.resolved(),
+            _ => self,
+        }
+    }
+}
+
+// Aliases are equal to the types they stand for
+impl PartialEq for Type {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.resolved(), other.resolved()) {
+            (Type::Int, Type::Int)
+            | (Type::BigInt, Type::BigInt)
+            | (Type::Float, Type::Float)
+            | (Type::Decimal, Type::Decimal)
+            | (Type::Char, Type::Char)
+            | (Type::Bytes, Type::Bytes)
+            | (Type::String, Type::String)
+            | (Type::Bool, Type::Bool)
+            | (Type::Null, Type::Null)
+            | (Type::Any, Type::Any) => true,
+            (Type::List(a), Type::List(b)) => a == b,
+            (Type::Map(a_key, a_val), Type::Map(b_key, b_val)) => a_key == b_key && a_val == b_val,
+            (
+                Type::Function { type_params: a_vars, params: a_params, returns: a_returns },
+                Type::Function { type_params: b_vars, params: b_params, returns: b_returns },
+            ) => a_vars == b_vars && a_params == b_params && a_returns == b_returns,
+            (Type::Record(a), Type::Record(b)) | (Type::Enum(a), Type::Enum(b)) | (Type::Param(a), Type::Param(b)) => {
+                a == b
+            }
+            _ => false,
+        }
+    }
+}
+
 impl fmt::Display for Type {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             Type::Int => write!(f, "int"),
+            Type::BigInt => write!(f, "bigint"),
             Type::Float => write!(f, "float"),
+            Type::Decimal => write!(f, "decimal"),
+            Type::Char => write!(f, "char"),
+            Type::Bytes => write!(f, "bytes"),
             Type::String => write!(f, "string"),
             Type::Bool => write!(f, "bool"),
             Type::Null => write!(f, "null"),
             Type::List(elem_type) => write!(f, "[{}]", elem_type),
             Type::Map(key_type, val_type) => write!(f, "[{}:{}]", key_type, val_type),
-            Type::Function { params, returns } => {
-                write!(f, "fun(")?;
+            Type::Function { type_params, params, returns } => {
+                write!(f, "fun")?;
+                if !type_params.is_empty() {
+                    write!(f, "<{}>", type_params.join(", "))?;
+                }
+                write!(f, "(")?;
                 for (i, param) in params.iter().enumerate() {
                     if i > 0 {
                         write!(f, ", ")?;
                     }
                     write!(f, "{}", param)?;
                 }
-                write!(f, "): ")?;
-                for (i, ret) in returns.iter().enumerate() {
-                    if i > 0 {
-                        write!(f, ", ")?;
+                write!(f, ")")?;
+                // Multiple return types are parenthesized so that a function
+                // type can appear inside a comma-separated list
+                match returns.len() {
+                    0 => Ok(()),
+                    1 => write!(f, ": {}", returns[0]),
+                    _ => {
+                        write!(f, ": (")?;
+                        for (i, ret) in returns.iter().enumerate() {
+                            if i > 0 {
+                                write!(f, ", ")?;
+                            }
+                            write!(f, "{}", ret)?;
+                        }
+                        write!(f, ")")
                     }
-                    write!(f, "{}", ret)?;
                 }
-                Ok(())
             }
+            Type::Record(name) => write!(f, "{}", name),
+            Type::Enum(name) => write!(f, "{}", name),
+            Type::Param(name) => write!(f, "{}", name),
+            Type::Alias(name, _) => write!(f, "{}", name),
             Type::Any => write!(f, "any"),
         }
     }
 }
 
-/// Runtime values in the Boba language
+/// Runtime values in the Boba language. Values behave as if they were
+/// copied on every assignment and call: changing a list, map or record
+/// through one variable never changes it in another. Collections are
+/// persistent, so a copy shares its structure with the original and
+/// changing an element only copies the path to it.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Value {
     Int(i64),
+    BigInt(BigInt),
     Float(f64),
+    Decimal(Decimal),
+    Char(char),
     String(String),
+    Bytes(Vec<u8>),
     Bool(bool),
     Null,
-    List(Vec<Value>),
-    Map(Vec<(Value, Value)>),
+    List(Vector<Value>),
+    Map(MapValue),
+    /// Record fields are kept in declaration order
+    Record {
+        name: String,
+        fields: Vec<(String, Value)>,
+    },
+    Variant {
+        enum_name: String,
+        variant: String,
+        fields: Vec<Value>,
+    },
     Function {
         name: String,
         params: Vec<(String, Type)>,
         return_types: Vec<Type>,
-        body: Vec<crate::ast::Expr>,
+        body: Rc<Vec<crate::ast::Expr>>,
+        closure: Option<Rc<Scope>>,
     },
+    /// A function compiled for the VM
+    Closure(Rc<Closure>),
 }
 
 impl Value {
     pub fn get_type(&self) -> Type {
         match self {
             Value::Int(_) => Type::Int,
+            Value::BigInt(_) => Type::BigInt,
             Value::Float(_) => Type::Float,
+            Value::Decimal(_) => Type::Decimal,
+            Value::Char(_) => Type::Char,
+            Value::Bytes(_) => Type::Bytes,
             Value::String(_) => Type::String,
             Value::Bool(_) => Type::Bool,
             Value::Null => Type::Null,
@@ -83,23 +193,127 @@ impl Value {
                     Type::List(Box::new(values[0].get_type()))
                 }
             }
-            Value::Map(entries) => {
-                if entries.is_empty() {
-                    Type::Map(Box::new(Type::Any), Box::new(Type::Any))
-                } else {
-                    // For simplicity, assume all keys and values have the same types as the first entry
-                    Type::Map(
-                        Box::new(entries[0].0.get_type()),
-                        Box::new(entries[0].1.get_type()),
-                    )
-                }
-            }
+            Value::Map(map) => match map.iter().next() {
+                None => Type::Map(Box::new(Type::Any), Box::new(Type::Any)),
+                // For simplicity, assume all keys and values have the same types as the first entry
+                Some((key, value)) => Type::Map(Box::new(key.get_type()), Box::new(value.get_type())),
+            },
+            Value::Record { name, .. } => Type::Record(name.clone()),
+            Value::Variant { enum_name, .. } => Type::Enum(enum_name.clone()),
             Value::Function {
                 params, return_types, ..
             } => Type::Function {
+                type_params: Vec::new(),
                 params: params.iter().map(|(_, t)| t.clone()).collect(),
                 returns: return_types.clone(),
             },
+            Value::Closure(closure) => Type::Function {
+                type_params: Vec::new(),
+                params: closure.proto.params.iter().map(|(_, t)| t.clone()).collect(),
+                returns: closure.proto.return_types.clone(),
+            },
+        }
+    }
+}
+
+/// A map key. Numbers are keys by value, like they compare with `==`, so
+/// `1`, `1n` and `1.0` are the same key; every NaN is the same key too.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Int(i64),
+    /// A bigint too big to be an int
+    BigInt(BigInt),
+    /// The bits of a float that isn't a whole number
+    Float(u64),
+    /// A decimal that isn't a whole number. Decimals that only differ in
+    /// trailing zeros, like `0.5d` and `0.50d`, are the same key.
+    Decimal(Decimal),
+    Char(char),
+    String(String),
+    Bytes(Vec<u8>),
+    Bool(bool),
+}
+
+impl Key {
+    fn new(value: &Value) -> Result<Key, String> {
+        match value {
+            Value::Int(n) => Ok(Key::Int(*n)),
+            Value::BigInt(n) => Ok(Key::integer(n.clone())),
+            Value::Float(n) if n.fract() == 0.0 && n.abs() < i64::MAX as f64 => Ok(Key::Int(*n as i64)),
+            Value::Float(n) if n.fract() == 0.0 => Ok(Key::integer(BigInt::from_f64(*n).unwrap())),
+            Value::Float(n) if n.is_nan() => Ok(Key::Float(f64::NAN.to_bits())),
+            Value::Float(n) => Ok(Key::Float(n.to_bits())),
+            Value::Decimal(n) if n.fract().is_zero() => Ok(Key::integer(BigInt::from(n.trunc().to_i128().unwrap()))),
+            Value::Decimal(n) => Ok(Key::Decimal(n.normalize())),
+            Value::Char(c) => Ok(Key::Char(*c)),
+            Value::String(s) => Ok(Key::String(s.clone())),
+            Value::Bytes(b) => Ok(Key::Bytes(b.clone())),
+            Value::Bool(b) => Ok(Key::Bool(*b)),
+            other => Err(format!(
+                "Map keys must be int, bigint, float, decimal, char, string, bytes or bool, got {}",
+                other.get_type()
+            )),
+        }
+    }
+
+    fn integer(n: BigInt) -> Key {
+        match n.to_i64() {
+            Some(n) => Key::Int(n),
+            None => Key::BigInt(n),
+        }
+    }
+}
+
+/// A map value. Entries are kept in the order their keys were first added,
+/// which is the order a map prints in.
+#[derive(Clone, Default)]
+pub struct MapValue {
+    entries: Vector<(Value, Value)>,
+    positions: HashMap<Key, usize>,
+}
+
+impl MapValue {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn get(&self, key: &Value) -> Result<Option<&Value>, String> {
+        let position = self.positions.get(&Key::new(key)?);
+        Ok(position.map(|&i| &self.entries[i].1))
+    }
+
+    /// Sets the value for a key, adding the key at the end if it is new
+    pub fn insert(&mut self, key: Value, value: Value) -> Result<(), String> {
+        let hashed = Key::new(&key)?;
+        match self.positions.get(&hashed).copied() {
+            Some(i) => self.entries[i].1 = value,
+            None => {
+                self.positions.insert(hashed, self.entries.len());
+                self.entries.push_back((key, value));
+            }
         }
+        Ok(())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &(Value, Value)> {
+        self.entries.iter()
+    }
+}
+
+// Maps are equal when they have the same entries, in any order
+impl PartialEq for MapValue {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self.iter().all(|(key, value)| matches!(other.get(key), Ok(Some(v)) if v == value))
+    }
+}
+
+impl fmt::Debug for MapValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter().map(|(key, value)| (key, value))).finish()
     }
 }
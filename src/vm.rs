@@ -0,0 +1,446 @@
+use crate::ast::BinaryOperator;
+use crate::builtins::BUILTINS;
+use crate::bytecode::{Capture, Closure, Module, Op, PathStep, Slot};
+use crate::interpreter::{
+    call_depth_error, call_method, check_argument, check_returns, with_traceback, cast_value, compare_with_methods, convert_value, describe_value, display_value, evaluate_binary_op,
+    evaluate_unary_op, format_value, get_field, index_value, is_truthy, match_pattern, output_formatted, promote_ints, slice_value, update_path,
+    value_has_type, Accessor, Budget, CallFrame, Limits, Methods, STACK_RED_ZONE, STACK_SEGMENT_SIZE,
+};
+use crate::types::{MapValue, Value};
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+/// A call in progress
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// Where the call's operands start on the stack
+    base: usize,
+    locals: Vec<Value>,
+    cells: Vec<Rc<RefCell<Value>>>,
+    /// The line of the call, for tracebacks
+    line: Option<usize>,
+    /// Whether the return value is checked against the signature. The
+    /// entry point isn't called, so nothing is checked for it.
+    checked: bool,
+}
+
+impl Frame {
+    fn declare(&mut self, slot: Slot, value: Value) {
+        match slot {
+            Slot::Local(i) => self.locals[i as usize] = value,
+            Slot::Cell(i) => self.cells[i as usize] = Rc::new(RefCell::new(value)),
+        }
+    }
+}
+
+/// Runs compiled programs. Calls push frames instead of recursing, except
+/// for the interface methods that output and comparisons call.
+pub struct Vm {
+    module: Module,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    budget: Budget,
+}
+
+pub fn run(module: Module, limits: &Limits) -> Result<(), String> {
+    let entry = Rc::new(Closure { proto: module.entry.clone(), upvalues: Vec::new() });
+    let mut vm = Vm {
+        module,
+        stack: Vec::new(),
+        frames: Vec::new(),
+        budget: Budget::new(limits),
+    };
+    vm.push_frame(entry, Vec::new(), None, false)?;
+    // Frames stay on the stack when a call fails, so they are the calls
+    // the error happened in
+    vm.execute(0).map(|_| ()).map_err(|e| {
+        let calls: Vec<_> = vm.frames[1..]
+            .iter()
+            .map(|frame| CallFrame { name: frame.closure.proto.name.clone(), line: frame.line })
+            .collect();
+        with_traceback(e, &calls)
+    })
+}
+
+impl Methods for Vm {
+    fn has_method(&self, type_name: &str, method: &str) -> bool {
+        self.module.methods.contains_key(&(type_name.to_string(), method.to_string()))
+    }
+
+    fn call_impl(&mut self, type_name: &str, method: &str, args: Vec<Value>, line: Option<usize>) -> Result<Value, String> {
+        let func = self.module.methods[&(type_name.to_string(), method.to_string())].clone();
+        let depth = self.frames.len();
+        self.call_value(func, args, line)?;
+        // Methods that output and comparisons call run nested on the native stack
+        stacker::maybe_grow(STACK_RED_ZONE, STACK_SEGMENT_SIZE, || self.execute(depth))
+    }
+
+    fn budget(&self) -> &Budget {
+        &self.budget
+    }
+}
+
+impl Vm {
+    /// Starts a call of `func`, which runs when execution continues
+    fn call_value(&mut self, func: Value, args: Vec<Value>, line: Option<usize>) -> Result<(), String> {
+        match func {
+            Value::Closure(closure) => self.push_frame(closure, args, line, true),
+            other => Err(format!("Cannot call a value of type {}", other.get_type())),
+        }
+    }
+
+    fn push_frame(
+        &mut self,
+        closure: Rc<Closure>,
+        args: Vec<Value>,
+        line: Option<usize>,
+        checked: bool,
+    ) -> Result<(), String> {
+        let proto = &closure.proto;
+        // The entry point is the first frame, and isn't a call
+        let limit = self.budget.limits.max_call_depth;
+        if checked && self.frames.len() > limit {
+            return Err(call_depth_error(limit, &proto.name));
+        }
+        if checked {
+            self.budget.step()?;
+        }
+        // The arguments are checked once the callee is on the call stack, so
+        // that an error's traceback shows the call
+        let args = if !checked {
+            Ok(args)
+        } else if args.len() != proto.params.len() {
+            Err(format!(
+                "Function '{}' expects {} arguments, got {}",
+                proto.name,
+                proto.params.len(),
+                args.len()
+            ))
+        } else {
+            proto
+                .params
+                .iter()
+                .zip(args)
+                .map(|((param_name, param_type), arg_value)| {
+                    check_argument(&proto.name, param_name, param_type, arg_value)
+                })
+                .collect()
+        };
+
+        let mut frame = Frame {
+            ip: 0,
+            base: self.stack.len(),
+            locals: vec![Value::Null; proto.locals],
+            cells: (0..proto.cells).map(|_| Rc::new(RefCell::new(Value::Null))).collect(),
+            line,
+            checked,
+            closure: closure.clone(),
+        };
+        let result = args.map(|args| {
+            for (&slot, arg_value) in closure.proto.param_slots.iter().zip(args) {
+                frame.declare(slot, arg_value);
+            }
+        });
+        self.frames.push(frame);
+        result
+    }
+
+    fn pop_values(&mut self, count: usize) -> Vec<Value> {
+        self.stack.split_off(self.stack.len() - count)
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap()
+    }
+
+    /// Runs until the call that leaves `stop` frames returns, and gives its
+    /// value
+    fn execute(&mut self, stop: usize) -> Result<Value, String> {
+        loop {
+            let frame = self.frames.last_mut().unwrap();
+            let op = frame.closure.proto.code[frame.ip];
+            frame.ip += 1;
+
+            match op {
+                Op::Constant(i) => {
+                    let value = frame.closure.proto.constants[i as usize].clone();
+                    self.stack.push(value);
+                }
+                Op::Pop => {
+                    self.stack.pop();
+                }
+                Op::Dup => {
+                    let value = self.stack.last().unwrap().clone();
+                    self.stack.push(value);
+                }
+                Op::Truncate(n) => self.stack.truncate(frame.base + n as usize),
+
+                Op::GetLocal(i) => self.stack.push(frame.locals[i as usize].clone()),
+                Op::SetLocal(i) => frame.locals[i as usize] = self.stack.last().unwrap().clone(),
+                Op::GetCell(i) => self.stack.push(frame.cells[i as usize].borrow().clone()),
+                Op::SetCell(i) => *frame.cells[i as usize].borrow_mut() = self.stack.last().unwrap().clone(),
+                Op::DeclareCell(i) => frame.declare(Slot::Cell(i), self.stack.last().unwrap().clone()),
+                Op::RenewCell(i) => {
+                    let value = frame.cells[i as usize].borrow().clone();
+                    frame.declare(Slot::Cell(i), value);
+                }
+                Op::GetUpvalue(i) => self.stack.push(frame.closure.upvalues[i as usize].borrow().clone()),
+                Op::SetUpvalue(i) => {
+                    *frame.closure.upvalues[i as usize].borrow_mut() = self.stack.last().unwrap().clone();
+                }
+                Op::GetFunction(i) => self.stack.push(self.module.functions[i as usize].clone()),
+
+                Op::List(n) => {
+                    let list = Value::List(self.pop_values(n as usize).into());
+                    self.budget.check_size(&list)?;
+                    self.stack.push(list);
+                }
+                Op::Map(n) => {
+                    let mut values = self.pop_values(2 * n as usize).into_iter();
+                    let mut map = MapValue::new();
+                    while let (Some(key), Some(value)) = (values.next(), values.next()) {
+                        map.insert(key, value)?;
+                    }
+                    let map = Value::Map(map);
+                    self.budget.check_size(&map)?;
+                    self.stack.push(map);
+                }
+                Op::Index => {
+                    let index = self.pop();
+                    let target = self.pop();
+                    self.stack.push(index_value(target, index)?);
+                }
+                Op::Slice => {
+                    let end = self.pop();
+                    let start = self.pop();
+                    let target = self.pop();
+                    self.stack.push(slice_value(target, start, end)?);
+                }
+                Op::Record(i) => {
+                    let (name, field_names) = &self.module.records[i as usize];
+                    let values = self.stack.split_off(self.stack.len() - field_names.len());
+                    let fields = field_names.iter().cloned().zip(values).collect();
+                    self.stack.push(Value::Record { name: name.clone(), fields });
+                }
+                Op::Variant(i) => {
+                    let (enum_name, variant, arity) = &self.module.variants[i as usize];
+                    let fields = self.stack.split_off(self.stack.len() - arity);
+                    self.stack.push(Value::Variant {
+                        enum_name: enum_name.clone(),
+                        variant: variant.clone(),
+                        fields,
+                    });
+                }
+                Op::GetField(i) => {
+                    let record = self.stack.pop().unwrap();
+                    let value = get_field(&record, &frame.closure.proto.names[i as usize])?;
+                    self.stack.push(value);
+                }
+                Op::SetPath(i) => {
+                    let proto = &frame.closure.proto;
+                    let steps = &proto.paths[i as usize];
+                    let count = steps.iter().filter(|step| **step == PathStep::Index).count();
+                    let mut indices = self.stack.split_off(self.stack.len() - count).into_iter();
+                    let target = self.stack.pop().unwrap();
+                    let value = self.stack.pop().unwrap();
+                    let path: Vec<_> = steps
+                        .iter()
+                        .map(|step| match step {
+                            PathStep::Field(f) => Accessor::Field(&proto.names[*f as usize]),
+                            PathStep::Index => Accessor::Index(indices.next().unwrap()),
+                        })
+                        .collect();
+                    self.stack.push(update_path(target, &path, value, &self.budget)?);
+                }
+
+                Op::Binary(operator) => {
+                    let right = self.pop();
+                    let left = self.pop();
+                    let result = match compare_with_methods(&left, &operator, &right, self)? {
+                        Some(result) => result,
+                        None => {
+                            let result = evaluate_binary_op(left, &operator, right)?;
+                            self.budget.check_size(&result)?;
+                            result
+                        }
+                    };
+                    self.stack.push(result);
+                }
+                Op::Unary(operator) => {
+                    let value = self.pop();
+                    self.stack.push(evaluate_unary_op(&operator, value)?);
+                }
+                Op::ShortCircuit(operator, target) => {
+                    let decided = matches!(
+                        (operator, self.stack.last()),
+                        (BinaryOperator::And, Some(Value::Bool(false))) | (BinaryOperator::Or, Some(Value::Bool(true)))
+                    );
+                    if decided {
+                        frame.ip = target as usize;
+                    }
+                }
+                Op::Convert(i) => {
+                    let value = self.stack.pop().unwrap();
+                    let result = convert_value(value, &frame.closure.proto.types[i as usize])?;
+                    self.budget.check_size(&result)?;
+                    self.stack.push(result);
+                }
+                Op::Cast(i) => {
+                    let value = self.stack.pop().unwrap();
+                    self.stack.push(cast_value(value, &frame.closure.proto.types[i as usize])?);
+                }
+                Op::IntToFloat(i) => {
+                    let value = self.stack.pop().unwrap();
+                    self.stack.push(promote_ints(value, &frame.closure.proto.types[i as usize])?);
+                }
+                Op::TypeCheck(i, is_negated) => {
+                    let value = self.stack.pop().unwrap();
+                    let has_type = value_has_type(&value, &frame.closure.proto.types[i as usize]);
+                    self.stack.push(Value::Bool(has_type != is_negated));
+                }
+
+                Op::Jump(target) => {
+                    // Only loops jump backwards, to repeat
+                    if (target as usize) < frame.ip {
+                        self.budget.step()?;
+                    }
+                    frame.ip = target as usize;
+                }
+                Op::JumpIfFalse(target, context) => {
+                    let condition = self.stack.pop().unwrap();
+                    if !is_truthy(&condition, context)? {
+                        frame.ip = target as usize;
+                    }
+                }
+                Op::Match(i) => {
+                    let value = self.stack.pop().unwrap();
+                    let proto = frame.closure.proto.clone();
+                    let table = &proto.matches[i as usize];
+
+                    let mut matched = false;
+                    for &arm_index in table.dispatch.candidates(&value) {
+                        let arm = &table.arms[arm_index];
+                        let mut bindings = Vec::new();
+                        if match_pattern(&arm.pattern, &value, &mut bindings) {
+                            for (&slot, (_, bound)) in arm.bindings.iter().zip(bindings) {
+                                frame.declare(slot, bound);
+                            }
+                            frame.ip = arm.target as usize;
+                            matched = true;
+                            break;
+                        }
+                    }
+                    if !matched {
+                        return Err(format!("No match arm matches value {}", describe_value(&value)));
+                    }
+                }
+
+                Op::Closure(i) => {
+                    let proto = self.module.protos[i as usize].clone();
+                    let upvalues = proto
+                        .captures
+                        .iter()
+                        .map(|capture| match *capture {
+                            Capture::Cell(i) => frame.cells[i as usize].clone(),
+                            Capture::Upvalue(i) => frame.closure.upvalues[i as usize].clone(),
+                        })
+                        .collect();
+                    self.stack.push(Value::Closure(Rc::new(Closure { proto, upvalues })));
+                }
+                Op::CheckFunction(name) => {
+                    let callee = self.stack.last().unwrap();
+                    if !matches!(callee, Value::Closure(_)) {
+                        return Err(match name {
+                            Some(i) => format!("'{}' is not a function: it holds a {}", frame.closure.proto.names[i as usize], callee.get_type()),
+                            None => format!("Cannot call a value of type {}", callee.get_type()),
+                        });
+                    }
+                }
+                Op::Call(argc, line) => {
+                    let args = self.pop_values(argc as usize);
+                    let callee = self.pop();
+                    self.call_value(callee, args, Some(line as usize))?;
+                }
+                Op::CallFunction(i, argc, line) => {
+                    let args = self.pop_values(argc as usize);
+                    let func = self.module.functions[i as usize].clone();
+                    self.call_value(func, args, Some(line as usize))?;
+                }
+                Op::CallMethod(i, argc, line, receiver_type) => {
+                    let line = Some(line as usize);
+                    let method = frame.closure.proto.names[i as usize].clone();
+                    let receiver_type = receiver_type.map(|i| frame.closure.proto.names[i as usize].clone());
+                    let mut args = self.pop_values(argc as usize);
+                    let receiver = self.pop();
+
+                    // A record field holding a function is called like a method
+                    if let Value::Record { fields, .. } = &receiver {
+                        if let Some((_, func)) = fields.iter().find(|(f, _)| *f == method) {
+                            self.call_value(func.clone(), args, line)?;
+                            continue;
+                        }
+                    }
+
+                    let type_name = receiver_type.clone().unwrap_or_else(|| receiver.get_type().to_string());
+                    args.insert(0, receiver);
+                    match self.module.methods.get(&(type_name, method.clone())) {
+                        Some(func) => self.call_value(func.clone(), args, line)?,
+                        None => {
+                            let result = call_method(&method, receiver_type.as_deref(), args, line, self)?;
+                            self.stack.push(result);
+                        }
+                    }
+                }
+                Op::CallBuiltin(i, argc) => {
+                    let args = self.pop_values(argc as usize);
+                    let result = (BUILTINS[i as usize].call)(&args)?;
+                    self.budget.check_size(&result)?;
+                    self.stack.push(result);
+                }
+                Op::Return(n, explicit) => {
+                    let mut values = self.pop_values(n as usize);
+                    // Checked before the frame is popped, so that an error's
+                    // traceback shows the function
+                    let frame = self.frames.last().unwrap();
+                    let value = if explicit && frame.checked {
+                        let proto = &frame.closure.proto;
+                        check_returns(&proto.name, values, &proto.return_types)?
+                    } else {
+                        values.swap_remove(0)
+                    };
+                    let frame = self.frames.pop().unwrap();
+
+                    self.stack.truncate(frame.base);
+                    if self.frames.len() == stop {
+                        return Ok(value);
+                    }
+                    self.stack.push(value);
+                }
+
+                Op::Output(n) => {
+                    let values = self.pop_values(n as usize);
+                    let mut line = Vec::new();
+                    for value in &values {
+                        line.push(display_value(value, self)?);
+                    }
+                    println!("{}", line.join(" "));
+                    io::stdout().flush().map_err(|e| e.to_string())?;
+                    self.stack.push(Value::Null);
+                }
+                Op::OutputFormatted => {
+                    let format_value = self.pop();
+                    self.stack.push(output_formatted(format_value)?);
+                }
+                Op::Format(i) => {
+                    let spec = frame.closure.proto.formats[i as usize];
+                    let value = self.pop();
+                    let text = format_value(&value, &spec, self)?;
+                    self.stack.push(Value::String(text));
+                }
+                Op::Fail(i) => return Err(frame.closure.proto.names[i as usize].clone()),
+            }
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use crate::ast::{Expr, Program};
+use crate::lexer::Trivia;
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+/// What a warning is about. The name is what a `# allow(...)` comment on
+/// the warning's line uses to suppress it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WarningKind {
+    Unused,
+    Unreachable,
+    Shadowing,
+    RedundantIs,
+    MixedComparison,
+}
+
+impl WarningKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            WarningKind::Unused => "unused",
+            WarningKind::Unreachable => "unreachable",
+            WarningKind::Shadowing => "shadowing",
+            WarningKind::RedundantIs => "redundant_is",
+            WarningKind::MixedComparison => "mixed_comparison",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Warning {
+    pub line: usize,
+    pub kind: WarningKind,
+    pub message: String,
+}
+
+impl Warning {
+    pub fn new(line: usize, kind: WarningKind, message: String) -> Self {
+        Self { line, kind, message }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {} [{}]", self.line, self.message, self.kind.name())
+    }
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Binding {
+    Variable,
+    Parameter,
+    Function,
+}
+
+struct Variable {
+    line: usize,
+    binding: Binding,
+    used: bool,
+}
+
+/// Walks the program with the interpreter's scoping rules: assigning to a
+/// name updates the nearest variable with that name and only declares a new
+/// one if there is none, while parameters, loop variables, pattern bindings
+/// and nested functions always declare a new one.
+#[derive(Default)]
+struct ScopeChecker {
+    scopes: Vec<HashMap<String, Variable>>,
+    /// Top-level functions referenced from outside their own body
+    called_functions: HashSet<String>,
+    current_function: Option<String>,
+    warnings: Vec<Warning>,
+}
+
+/// Reports unused variables, parameters and functions, code after `return`,
+/// `break` or `continue`, and variables that shadow another one
+pub fn check_program(program: &Program) -> Vec<Warning> {
+    let mut checker = ScopeChecker::default();
+
+    let mut functions: Vec<_> = program.functions.values().collect();
+    functions.sort_by_key(|func_def| func_def.line);
+
+    for func_def in &functions {
+        // Top-level functions don't capture anything
+        let outer_scopes = std::mem::take(&mut checker.scopes);
+        checker.current_function = Some(func_def.name.clone());
+        checker.check_function(&func_def.params, &func_def.body, func_def.line);
+        checker.current_function = None;
+        checker.scopes = outer_scopes;
+    }
+
+    for impl_def in &program.impls {
+        for method in &impl_def.methods {
+            checker.check_function(&method.params, &method.body, method.line);
+        }
+    }
+
+    checker.scopes.push(HashMap::new());
+    checker.check_block(&program.main_block);
+    checker.pop_scope();
+
+    for func_def in &functions {
+        if func_def.name != "main" && !checker.called_functions.contains(&func_def.name) {
+            checker.warnings.push(Warning::new(
+                func_def.line,
+                WarningKind::Unused,
+                format!("Function '{}' is never used", func_def.name),
+            ));
+        }
+    }
+
+    checker.warnings.sort_by_key(|warning| warning.line);
+    checker.warnings
+}
+
+impl ScopeChecker {
+    fn check_function(&mut self, params: &[(String, crate::types::Type)], body: &[Expr], line: usize) {
+        self.scopes.push(HashMap::new());
+        for (param, _) in params {
+            self.declare(param, line, Binding::Parameter);
+        }
+        self.check_block(body);
+        self.pop_scope();
+    }
+
+    /// Declares a new variable in the innermost scope
+    fn declare(&mut self, name: &str, line: usize, binding: Binding) {
+        if let Some(outer) = self.lookup(name) {
+            let outer_line = outer.line;
+            self.warnings.push(Warning::new(
+                line,
+                WarningKind::Shadowing,
+                format!("'{}' shadows the variable declared on line {}", name, outer_line),
+            ));
+        }
+        self.scopes.last_mut().unwrap().insert(
+            name.to_string(),
+            Variable {
+                line,
+                binding,
+                used: false,
+            },
+        );
+    }
+
+    fn lookup(&mut self, name: &str) -> Option<&mut Variable> {
+        self.scopes.iter_mut().rev().find_map(|scope| scope.get_mut(name))
+    }
+
+    /// Marks a read of `name`, which is either a variable or a function
+    fn use_name(&mut self, name: &str) {
+        match self.lookup(name) {
+            Some(variable) => variable.used = true,
+            None => {
+                if self.current_function.as_deref() != Some(name) {
+                    self.called_functions.insert(name.to_string());
+                }
+            }
+        }
+    }
+
+    fn pop_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap_or_default();
+        for (name, variable) in scope {
+            // `_name` marks a variable as intentionally unused
+            if variable.used || name.starts_with('_') || name == "self" {
+                continue;
+            }
+            let message = match variable.binding {
+                Binding::Variable => format!("Variable '{}' is never used", name),
+                Binding::Parameter => format!("Parameter '{}' is never used", name),
+                Binding::Function => format!("Function '{}' is never used", name),
+            };
+            self.warnings.push(Warning::new(variable.line, WarningKind::Unused, message));
+        }
+    }
+
+    /// Checks statements, reporting any that follow a `return`, `break` or
+    /// `continue`
+    fn check_block(&mut self, body: &[Expr]) {
+        let mut reported = false;
+        for (i, expr) in body.iter().enumerate() {
+            self.check_expr(expr);
+
+            let exit = match expr {
+                Expr::Return { line, .. } => Some(("return", *line)),
+                Expr::Break { line } => Some(("break", *line)),
+                Expr::Continue { line } => Some(("continue", *line)),
+                _ => None,
+            };
+            if let Some((keyword, line)) = exit {
+                if !reported && i + 1 < body.len() {
+                    self.warnings.push(Warning::new(
+                        line,
+                        WarningKind::Unreachable,
+                        format!("Code after this '{}' is never run", keyword),
+                    ));
+                    reported = true;
+                }
+            }
+        }
+    }
+
+    fn check_scoped_block(&mut self, body: &[Expr]) {
+        self.scopes.push(HashMap::new());
+        self.check_block(body);
+        self.pop_scope();
+    }
+
+    fn check_exprs<'a>(&mut self, exprs: impl IntoIterator<Item = &'a Expr>) {
+        for expr in exprs {
+            self.check_expr(expr);
+        }
+    }
+
+    fn check_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::IntLiteral(_)
+            | Expr::BigIntLiteral(_)
+            | Expr::DecimalLiteral(_)
+            | Expr::FloatLiteral(_)
+            | Expr::CharLiteral(_)
+            | Expr::StringLiteral(_)
+            | Expr::BytesLiteral(_)
+            | Expr::BoolLiteral(_)
+            | Expr::NullLiteral
+            | Expr::Break { .. }
+            | Expr::Continue { .. } => {}
+
+            Expr::Identifier { name, .. } => self.use_name(name),
+
+            Expr::VarDeclaration { name, value, line, .. } => {
+                self.check_expr(value);
+                if self.lookup(name).is_none() {
+                    self.declare(name, *line, Binding::Variable);
+                }
+            }
+
+            Expr::List(items) => self.check_exprs(items),
+            Expr::Map(entries) => {
+                for (key, value) in entries {
+                    self.check_expr(key);
+                    self.check_expr(value);
+                }
+            }
+            Expr::Index { target, index } => {
+                self.check_expr(target);
+                self.check_expr(index);
+            }
+            Expr::Slice { target, start, end } => {
+                self.check_expr(target);
+                self.check_exprs(start.as_deref());
+                self.check_exprs(end.as_deref());
+            }
+            Expr::RecordLiteral { fields, .. } => self.check_exprs(fields.iter().map(|(_, e)| e)),
+            Expr::FieldAccess { target, .. } => self.check_expr(target),
+            Expr::FieldAssignment { target, value, .. } => {
+                self.check_expr(value);
+                self.check_expr(target);
+            }
+            Expr::IndexAssignment { target, index, value } => {
+                self.check_expr(value);
+                self.check_expr(target);
+                self.check_expr(index);
+            }
+            Expr::BinaryOp { left, right, .. } => {
+                self.check_expr(left);
+                self.check_expr(right);
+            }
+            Expr::UnaryOp { expr, .. } => self.check_expr(expr),
+            Expr::VariantLiteral { args, .. } => self.check_exprs(args),
+
+            Expr::Match { scrutinee, arms, .. } => {
+                self.check_expr(scrutinee);
+                for arm in arms {
+                    self.scopes.push(HashMap::new());
+                    for name in arm.pattern.bindings() {
+                        self.declare(name, arm.line, Binding::Variable);
+                    }
+                    self.check_block(&arm.body);
+                    self.pop_scope();
+                }
+            }
+
+            Expr::If { condition, then_branch, else_if_branches, else_branch } => {
+                self.check_expr(condition);
+                self.check_scoped_block(then_branch);
+                for (cond, branch) in else_if_branches {
+                    self.check_expr(cond);
+                    self.check_scoped_block(branch);
+                }
+                if let Some(branch) = else_branch {
+                    self.check_scoped_block(branch);
+                }
+            }
+
+            Expr::Loop { init, condition, update, body } => {
+                self.scopes.push(HashMap::new());
+                match init.as_deref() {
+                    // The loop variable is always a new variable
+                    Some(Expr::VarDeclaration { name, value, line, .. }) => {
+                        self.check_expr(value);
+                        self.declare(name, *line, Binding::Variable);
+                    }
+                    Some(other) => self.check_expr(other),
+                    None => {}
+                }
+                self.check_exprs(condition.as_deref());
+                self.check_exprs(update.as_deref());
+                self.check_scoped_block(body);
+                self.pop_scope();
+            }
+
+            Expr::Return { values, .. } => self.check_exprs(values),
+
+            Expr::FunctionDeclaration { name, params, body, line, .. } => {
+                // Declared first so that it can call itself
+                self.declare(name, *line, Binding::Function);
+                self.check_function(params, body, *line);
+            }
+            Expr::Lambda { params, body, line, .. } => self.check_function(params, body, *line),
+
+            Expr::FunctionCall { name, args, .. } => {
+                self.use_name(name);
+                self.check_exprs(args);
+            }
+            Expr::Call { callee, args, .. } => {
+                self.check_expr(callee);
+                self.check_exprs(args);
+            }
+            Expr::MethodCall { target, args, .. } => {
+                self.check_expr(target);
+                self.check_exprs(args);
+            }
+
+            Expr::Output(args) => self.check_exprs(args),
+            Expr::OutputFormatted(expr)
+            | Expr::Format { expr, .. }
+            | Expr::OutputAddress(expr)
+            | Expr::Input(expr)
+            | Expr::InputFormatted(expr)
+            | Expr::TypeConversion { expr, .. }
+            | Expr::TypeCheck { expr, .. }
+            | Expr::Cast { expr, .. }
+            | Expr::IntToFloat { expr, .. } => self.check_expr(expr),
+        }
+    }
+}
+
+/// Drops the warnings whose line has a comment like `# allow(unused)` or
+/// `# allow(unused, shadowing)` naming their kind
+pub fn suppress(warnings: Vec<Warning>, comments: &[Trivia]) -> Vec<Warning> {
+    warnings
+        .into_iter()
+        .filter(|warning| {
+            !comments
+                .iter()
+                .filter(|comment| comment.line == warning.line)
+                .any(|comment| allowed_kinds(&comment.text).contains(&warning.kind.name()))
+        })
+        .collect()
+}
+
+/// Kinds listed in an `allow(...)` annotation in a comment
+fn allowed_kinds(comment: &str) -> Vec<&str> {
+    let Some(start) = comment.find("allow(") else {
+        return Vec::new();
+    };
+    let list = &comment[start + "allow(".len()..];
+    let list = &list[..list.find(')').unwrap_or(list.len())];
+    list.split(',').map(str::trim).collect()
+}
@@ -0,0 +1,28 @@
+//! A cast only moves between a type and a more or less precise version of
+//! it; casting to an unrelated type points to the conversion to use instead
+
+mod common;
+
+use common::error_of;
+
+#[test]
+fn a_cast_that_needs_a_conversion_names_it() {
+    let source = r#"
+fun main(): null {
+    output(42 as string)
+    return null
+}
+"#;
+    assert_eq!(error_of(source), "Type error: In function 'main': Cannot cast int to string; use string(x) to convert the value");
+}
+
+#[test]
+fn a_cast_with_no_conversion_has_no_hint() {
+    let source = r#"
+fun main(): null {
+    output([1, 2] as bool)
+    return null
+}
+"#;
+    assert_eq!(error_of(source), "Type error: In function 'main': Cannot cast [int] to bool");
+}
@@ -0,0 +1,132 @@
+//! Closures capture variables by reference, so a counter or generator
+//! built from one keeps its state between calls and after the function
+//! that created it returns
+
+mod common;
+
+use common::output_of;
+
+#[test]
+fn counters_keep_their_own_count() {
+    let source = r#"
+fun make_counter(): fun(): int {
+    count = 0
+    return fun(): int {
+        count = count + 1
+        return count
+    }
+}
+
+fun main(): null {
+    first = make_counter()
+    second = make_counter()
+    output(first(), first(), first())
+    output(second())
+    output(first())
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["1 2 3", "1", "4"]);
+}
+
+#[test]
+fn generators_outlive_the_scope_that_made_them() {
+    let source = r#"
+fun fibonacci(): fun(): int {
+    a = 0
+    b = 1
+    fun next(): int {
+        result = a
+        a = b
+        b = result + b
+        return result
+    }
+    return next
+}
+
+fun main(): null {
+    fib = fibonacci()
+    loop i = 0, i...8 {
+        output(fib())
+    }
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["0", "1", "1", "2", "3", "5", "8", "13"]);
+}
+
+#[test]
+fn changes_after_capture_are_seen_by_the_closure() {
+    let source = r#"
+fun main(): null {
+    message = "before"
+    show = fun(): string {
+        return message
+    }
+    message = "after"
+    output(show())
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["after"]);
+}
+
+#[test]
+fn changes_made_by_the_closure_are_seen_outside() {
+    let source = r#"
+fun main(): null {
+    total = 0
+    add = fun(n: int): null {
+        total = total + n
+    }
+    add(5)
+    add(10)
+    output(total)
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["15"]);
+}
+
+#[test]
+fn closures_sharing_a_variable_see_each_others_changes() {
+    let source = r#"
+fun make_pair(): [fun(): int] {
+    n = 0
+    increment = fun(): int {
+        n = n + 1
+        return n
+    }
+    read = fun(): int {
+        return n
+    }
+    return [increment, read]
+}
+
+fun main(): null {
+    pair = make_pair()
+    increment = pair[0]
+    read = pair[1]
+    increment()
+    increment()
+    output(read())
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["2"]);
+}
+
+#[test]
+fn each_loop_iteration_has_its_own_variable() {
+    let source = r#"
+fun main(): null {
+    getters = [:] as [int: fun(): int]
+    loop i = 0, i...3 {
+        getters[i] = fun(): int { return i }
+    }
+    output(getters[0](), getters[1](), getters[2]())
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["0 1 2"]);
+}
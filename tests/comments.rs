@@ -0,0 +1,87 @@
+//! Block comments open at a fence of three or more '#'s at the start of a
+//! line and close at the next line ending in just as many, so text can sit
+//! next to the fences while banners and '#'s in line comments are left alone
+
+mod common;
+
+use common::{error_of, output_of};
+
+#[test]
+fn banners_are_line_comments() {
+    let source = r#"
+#### Setup ####
+######## Main ########
+fun main(): null {
+    output("ran") ### after code
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["ran"]);
+}
+
+#[test]
+fn a_fence_in_a_line_comment_does_not_close_a_block() {
+    let source = r#"
+fun main(): null {
+    ###
+    # a ### here is part of the comment
+    output("skipped")
+    ###
+    output("ran")
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["ran"]);
+}
+
+#[test]
+fn fences_nest_by_length() {
+    let source = r#"
+fun main(): null {
+    ####
+    ###
+    output("inner")
+    ###
+    output("outer")
+    ####
+    ########
+    output("boxed")
+    ########
+    output("ran")
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["ran"]);
+}
+
+#[test]
+fn an_unclosed_block_is_an_error() {
+    let source = r#"
+fun main(): null {
+    ###
+    output("never closed")
+    #### not a closing fence
+    return null
+}
+"#;
+    assert_eq!(
+        error_of(source),
+        "Lexer error: Lexical error at line 3, column 5: unterminated comment: expected a closing ###"
+    );
+}
+
+#[test]
+fn text_can_follow_the_opening_fence_and_precede_the_closing_one() {
+    let source = r#"
+### This is a
+multiline comment ###
+fun main(): null {
+    #### skipped:
+    output("skipped") ###
+    still skipped ####
+    output("ran")
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["ran"]);
+}
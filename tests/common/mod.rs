@@ -0,0 +1,114 @@
+//! Runs Boba programs through the `boba` binary, on both engines
+
+#![allow(dead_code)]
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+pub const ENGINES: [&str; 2] = ["vm", "tree"];
+
+/// What a run of a program printed and how it ended
+#[derive(Debug, PartialEq)]
+pub struct Run {
+    pub stdout: String,
+    pub stderr: String,
+    pub success: bool,
+}
+
+impl Run {
+    fn from_output(output: Output) -> Self {
+        // The first line names the file, which is a temporary one for
+        // programs given as source
+        let stdout = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|line| !line.starts_with("Running Boba program:"))
+            .map(|line| format!("{}\n", line))
+            .collect();
+        Run {
+            stdout,
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            success: output.status.success(),
+        }
+    }
+
+    /// The lines the program printed, without the final success message
+    pub fn output_lines(&self) -> Vec<&str> {
+        self.stdout
+            .lines()
+            .filter(|line| *line != "Program executed successfully")
+            .collect()
+    }
+
+    /// The error message, without the `Error: ` prefix and the call stack
+    pub fn error(&self) -> &str {
+        let line = self.stderr.lines().find(|line| line.starts_with("Error: ")).unwrap_or("");
+        line.trim_start_matches("Error: ")
+    }
+
+    /// The run as text for a snapshot
+    pub fn render(&self) -> String {
+        format!(
+            "success: {}\n--- stdout\n{}--- stderr\n{}",
+            self.success, self.stdout, self.stderr
+        )
+    }
+}
+
+pub fn manifest_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+/// Runs a file with one engine. A relative path is relative to the crate.
+pub fn run_file(path: &Path, engine: &str, flags: &[&str]) -> Run {
+    let output = Command::new(env!("CARGO_BIN_EXE_boba"))
+        .current_dir(manifest_dir())
+        .arg("--engine")
+        .arg(engine)
+        .args(flags)
+        .arg("run")
+        .arg(path)
+        .output()
+        .expect("failed to run boba");
+    Run::from_output(output)
+}
+
+/// Runs source with one engine
+pub fn run_source(source: &str, engine: &str, flags: &[&str]) -> Run {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let dir = std::env::temp_dir().join(format!("boba-tests-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let path = dir.join(format!("program{}.bb", COUNTER.fetch_add(1, Ordering::Relaxed)));
+    fs::write(&path, source).unwrap();
+    let run = run_file(&path, engine, flags);
+    let _ = fs::remove_file(&path);
+    run
+}
+
+/// Runs source on both engines, checks they agree and returns the run
+pub fn run_both(source: &str) -> Run {
+    run_both_with(source, &[])
+}
+
+pub fn run_both_with(source: &str, flags: &[&str]) -> Run {
+    let vm = run_source(source, "vm", flags);
+    let tree = run_source(source, "tree", flags);
+    assert_eq!(vm, tree, "the engines disagree on:\n{}", source);
+    vm
+}
+
+/// Runs a program on both engines and returns the lines it printed,
+/// failing if it doesn't succeed
+pub fn output_of(source: &str) -> Vec<String> {
+    let run = run_both(source);
+    assert!(run.success, "program failed: {}\n{}", run.stderr, source);
+    run.output_lines().into_iter().map(String::from).collect()
+}
+
+/// Runs a program that should fail on both engines and returns its error
+pub fn error_of(source: &str) -> String {
+    let run = run_both(source);
+    assert!(!run.success, "program succeeded:\n{}", source);
+    run.error().to_string()
+}
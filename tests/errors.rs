@@ -0,0 +1,45 @@
+//! Runtime errors read the same on both engines, naming values by their
+//! type or, for primitives, their text
+
+mod common;
+
+use common::error_of;
+
+#[test]
+fn indexing_a_function_names_its_type() {
+    let source = r#"
+fun main(): null {
+    f = fun(): int {
+        return 1
+    }
+    a = f as any
+    output(a[0])
+    return null
+}
+"#;
+    assert_eq!(error_of(source), "Runtime error: Cannot index fun(): int with int");
+}
+
+#[test]
+fn a_missing_key_is_shown_as_written() {
+    let source = r#"
+fun main(): null {
+    ages = ["ann": 31]
+    output(ages["bob"])
+    return null
+}
+"#;
+    assert_eq!(error_of(source), "Runtime error: Key not found in map: \"bob\"");
+}
+
+#[test]
+fn a_field_of_a_list_names_its_type() {
+    let source = r#"
+fun main(): null {
+    x = [1, 2] as any
+    output(x.name)
+    return null
+}
+"#;
+    assert_eq!(error_of(source), "Runtime error: Cannot access field 'name' of [int]");
+}
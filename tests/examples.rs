@@ -0,0 +1,26 @@
+//! Every example program runs the same on the VM as on the tree-walker,
+//! which is the reference the VM is checked against
+
+mod common;
+
+use common::{run_file, ENGINES};
+use std::fs;
+
+#[test]
+fn examples_run_the_same_on_both_engines() {
+    let mut paths: Vec<_> = fs::read_dir(common::manifest_dir().join("examples"))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "bb"))
+        .collect();
+    paths.sort();
+    assert!(!paths.is_empty());
+
+    for path in paths {
+        let name = path.file_stem().unwrap().to_string_lossy().into_owned();
+        let relative = path.strip_prefix(common::manifest_dir()).unwrap();
+        let [vm, tree] = ENGINES.map(|engine| run_file(relative, engine, &[]));
+        assert_eq!(vm, tree, "the engines disagree on {}", relative.display());
+        insta::assert_snapshot!(format!("example_{}", name), tree.render());
+    }
+}
@@ -0,0 +1,42 @@
+//! Folding constants before the program runs keeps the warnings and the
+//! line numbers the program would have without it
+
+mod common;
+
+use common::{error_of, run_both};
+
+#[test]
+fn comparing_an_int_literal_with_a_float_still_warns() {
+    let source = r#"
+fun main(): null {
+    output(1 == 1.0, 2 != 2.5)
+    return null
+}
+"#;
+    let run = run_both(source);
+    assert!(run.success, "{}", run.stderr);
+    assert_eq!(run.output_lines(), ["true true"]);
+    let warnings: Vec<_> = run.stderr.lines().filter(|line| line.starts_with("Warning: ")).collect();
+    assert_eq!(
+        warnings,
+        [
+            "Warning: line 3: Comparing int with float using '==' converts the int to float first [mixed_comparison]",
+            "Warning: line 3: Comparing int with float using '!=' converts the int to float first [mixed_comparison]",
+        ]
+    );
+}
+
+#[test]
+fn folding_errors_give_the_line() {
+    let source = r#"
+fun main(): null {
+    output("start")
+    output(int("abc"))
+    return null
+}
+"#;
+    assert_eq!(
+        error_of(source),
+        "Compile error: In function 'main': line 4: Cannot convert 'abc' to int"
+    );
+}
@@ -0,0 +1,143 @@
+//! Formatted values in `outputf`, and ints kept where a float is expected,
+//! which print and compute as floats
+
+mod common;
+
+use common::{error_of, output_of, run_both_with};
+
+fn program(format: &str) -> String {
+    format!(
+        "fun main(): null {{\n    x = 1.5\n    w = \"ab\"\n    outputf(\"{}\")\n    return null\n}}\n",
+        format
+    )
+}
+
+#[test]
+fn widths_and_precisions_format_values() {
+    assert_eq!(output_of(&program("[{x:>8.3}] [{w:^6}] [{w:4}] [{x}]")), ["[   1.500] [  ab  ] [ab  ] [1.5]"]);
+}
+
+#[test]
+fn precision_rounds_floats_half_to_even_and_decimals_half_away_from_zero() {
+    let source = r#"
+fun main(): null {
+    a = 2.5
+    b = 0.125
+    c = 2.675
+    outputf("{a:.0} {b:.2} {c:.2}")
+    d = 2.5d
+    e = 0.125d
+    f = 2.675d
+    outputf("{d:.0} {e:.2} {f:.2}")
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["2 0.12 2.67", "3 0.13 2.68"]);
+}
+
+#[test]
+fn huge_widths_and_precisions_are_rejected() {
+    for (spec, message) in [
+        ("{x:>100000000000}", "the width can be at most 10000"),
+        ("{x:20000}", "the width can be at most 10000"),
+        ("{x:.100000000000}", "the precision can be at most 1000"),
+        ("{x:.1001}", "the precision can be at most 1000"),
+    ] {
+        let error = error_of(&program(spec));
+        assert!(error.starts_with("Parser error: Invalid format"), "{}: {}", spec, error);
+        assert!(error.ends_with(message), "{}: {}", spec, error);
+    }
+}
+
+#[test]
+fn decimals_are_formatted_to_at_most_28_places() {
+    let source = |precision: u32| {
+        format!(
+            "fun main(): null {{\n    d = 1.5d\n    outputf(\"{{d:.{}}}\")\n    return null\n}}\n",
+            precision
+        )
+    };
+    assert_eq!(output_of(&source(28)), [format!("1.5{}", "0".repeat(27))]);
+    assert_eq!(
+        error_of(&source(40)),
+        "Runtime error: A decimal can be formatted to at most 28 places, got 40"
+    );
+}
+
+#[test]
+fn padding_is_checked_against_the_string_length_limit() {
+    let run = run_both_with(&program("{w:>5000}"), &["--max-string-length", "100"]);
+    assert!(!run.success);
+    assert_eq!(
+        run.error(),
+        "Runtime error: Resource limit exceeded: a string of 5000 bytes, more than the limit of 100"
+    );
+
+    let run = run_both_with(&program("{x:.500}"), &["--max-string-length", "100"]);
+    assert!(!run.success);
+    assert!(run.error().ends_with("a string of 500 bytes, more than the limit of 100"), "{}", run.error());
+}
+
+#[test]
+fn an_int_assigned_to_a_float_variable_is_a_float() {
+    let source = r#"
+fun main(): null {
+    x = 1.5
+    x = 2
+    output(x, string(x), x / 2)
+    price = 2.5
+    price = 3
+    outputf("{price:.2}")
+    big = 0.5
+    big = 9223372036854775807
+    output(big + 1)
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["2.0 2.0 1.0", "3.00", "9.223372036854776e18"]);
+}
+
+#[test]
+fn ints_in_float_lists_maps_records_and_variants_are_floats() {
+    let source = r#"
+type P {
+    x: float
+}
+
+enum Shape {
+    Circle(float),
+}
+
+fun main(): null {
+    output([1.5, 2], [2, 1.5], [1: 2.5, 2.5: 3])
+    p = P { x: 3 }
+    output(p)
+    p.x = 4
+    xs = [1.5]
+    xs[0] = 7
+    output(p.x, xs, Shape.Circle(1), 5 as float)
+    return null
+}
+"#;
+    assert_eq!(
+        output_of(source),
+        [
+            "[1.5, 2.0] [2.0, 1.5] [1.0:2.5, 2.5:3.0]",
+            "P { x: 3.0 }",
+            "4.0 [7.0] Shape.Circle(1.0) 5.0",
+        ]
+    );
+}
+
+#[test]
+fn an_int_cast_back_from_any_to_float_is_a_float() {
+    let source = r#"
+fun main(): null {
+    a = 2 as any
+    xs = [1, 2] as any
+    output(a as float, (a as float) / 4, xs as [float])
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["2.0 0.5 [1.0, 2.0]"]);
+}
@@ -0,0 +1,39 @@
+//! Calls of generic functions take their type parameters from the
+//! arguments, and report an argument that doesn't fit its parameter
+
+mod common;
+
+use common::{error_of, output_of};
+
+#[test]
+fn type_parameters_are_taken_from_the_arguments() {
+    let source = r#"
+fun first<T>(xs: [T]): T {
+    return xs[0]
+}
+
+fun main(): null {
+    output(first([3, 4]), first(["a", "b"]))
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["3 a"]);
+}
+
+#[test]
+fn an_argument_of_the_wrong_shape_is_reported_as_a_mismatch() {
+    let source = r#"
+fun first<T>(xs: [T]): T {
+    return xs[0]
+}
+
+fun main(): null {
+    output(first(1))
+    return null
+}
+"#;
+    assert_eq!(
+        error_of(source),
+        "Type error: In function 'main': Function 'first' argument 0 has type int, expected [T]"
+    );
+}
@@ -0,0 +1,106 @@
+//! Int arithmetic reports overflow, whether it happens while the program
+//! runs or when the optimizer folds constants, and the wrapping and
+//! saturating builtins give defined results instead
+
+mod common;
+
+use common::{error_of, output_of};
+
+const LIMITS: &str = "
+const MAX = 9223372036854775807
+const MIN = -9223372036854775807 - 1
+";
+
+/// A program whose `main` runs `body`, with `MAX` and `MIN` defined and
+/// `max()` and `min()` giving them at runtime so they aren't folded
+fn program(body: &str) -> String {
+    format!(
+        "{}
+fun max(): int {{
+    return MAX
+}}
+
+fun min(): int {{
+    return MIN
+}}
+
+fun main(): null {{
+    {}
+    return null
+}}
+",
+        LIMITS, body
+    )
+}
+
+#[test]
+fn overflow_at_runtime_is_an_error() {
+    let cases = [
+        ("output(max() + 1)", "Integer overflow: 9223372036854775807 + 1"),
+        ("output(min() - 1)", "Integer overflow: -9223372036854775808 - 1"),
+        ("output(min() * -1)", "Integer overflow: -9223372036854775808 * -1"),
+        ("output(min() / -1)", "Integer overflow: -9223372036854775808 / -1"),
+        ("output(-min())", "Integer overflow: -(-9223372036854775808)"),
+    ];
+    for (body, message) in cases {
+        assert_eq!(error_of(&program(body)), format!("Runtime error: {}", message), "{}", body);
+    }
+}
+
+#[test]
+fn overflow_in_constants_is_reported_before_running() {
+    let cases = [
+        ("output(MAX + 1)", "Integer overflow: 9223372036854775807 + 1"),
+        ("output(MIN - 1)", "Integer overflow: -9223372036854775808 - 1"),
+        ("output(MIN * -1)", "Integer overflow: -9223372036854775808 * -1"),
+        ("output(MIN / -1)", "Integer overflow: -9223372036854775808 / -1"),
+        ("output(-MIN)", "Integer overflow: -(-9223372036854775808)"),
+    ];
+    for (body, message) in cases {
+        let source = program(body);
+        let line = source.lines().position(|line| line.trim() == body).unwrap() + 1;
+        assert_eq!(
+            error_of(&source),
+            format!("Compile error: In function 'main': line {}: {}", line, message),
+            "{}",
+            body
+        );
+    }
+}
+
+#[test]
+fn remainder_of_min_by_minus_one_is_zero() {
+    assert_eq!(output_of(&program("output(min() % -1, MIN % -1)")), ["0 0"]);
+}
+
+#[test]
+fn wrapping_builtins_wrap_around() {
+    let body = "
+    output(wrapping_add(max(), 1), wrapping_sub(min(), 1))
+    output(wrapping_mul(max(), 2), wrapping_mul(min(), -1))
+    output(wrapping_add(MAX, 1), wrapping_mul(MIN, -1))";
+    assert_eq!(
+        output_of(&program(body)),
+        [
+            "-9223372036854775808 9223372036854775807",
+            "-2 -9223372036854775808",
+            "-9223372036854775808 -9223372036854775808",
+        ]
+    );
+}
+
+#[test]
+fn saturating_builtins_stop_at_the_limits() {
+    let body = "
+    output(saturating_add(max(), 1), saturating_sub(min(), 1))
+    output(saturating_mul(min(), 2), saturating_mul(min(), -1))
+    output(saturating_add(MAX, 1), saturating_mul(MIN, -1))";
+    assert_eq!(
+        output_of(&program(body)),
+        [
+            "9223372036854775807 -9223372036854775808",
+            "-9223372036854775808 9223372036854775807",
+            "9223372036854775807 9223372036854775807",
+        ]
+    );
+}
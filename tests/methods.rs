@@ -0,0 +1,40 @@
+//! Method calls run the implementation of the receiver's declared type
+
+mod common;
+
+use common::output_of;
+
+#[test]
+fn a_method_is_chosen_by_the_declared_type() {
+    let source = r#"
+interface Describe {
+    fun describe(self): string
+}
+
+impl Describe for int {
+    fun describe(self): string {
+        return "int " + string(self)
+    }
+}
+
+impl Describe for float {
+    fun describe(self): string {
+        return "float " + string(self)
+    }
+}
+
+type Price {
+    amount: float
+}
+
+fun main(): null {
+    xs = [1.5, 2]
+    p = Price { amount: 3 }
+    x = 1.5
+    x = 4
+    output(xs[1].describe(), p.amount.describe(), x.describe(), (5).describe())
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["float 2.0 float 3.0 float 4.0 int 5"]);
+}
@@ -0,0 +1,120 @@
+//! Values passed to and returned from functions take the declared types: an
+//! int becomes a float where a float is expected, a float is never taken
+//! for an int, and every returned value is checked, not just the first
+
+mod common;
+
+use common::{error_of, output_of};
+
+#[test]
+fn an_int_passed_for_a_float_becomes_a_float() {
+    let source = r#"
+fun half(x: float): float {
+    output(x)
+    return x / 2
+}
+
+fun average(xs: [float]): float {
+    output(xs)
+    return 0.0
+}
+
+fun main(): null {
+    output(half(3))
+    average([1, 2])
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["3.0", "1.5", "[1.0, 2.0]"]);
+}
+
+#[test]
+fn an_int_returned_as_a_float_becomes_a_float() {
+    let source = r#"
+fun three(): float {
+    return 3
+}
+
+fun clamp(x: int): float {
+    if x > 10 {
+        return 10
+    }
+    return x
+}
+
+fun main(): null {
+    output(three(), clamp(20), clamp(4))
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["3.0 10.0 4.0"]);
+}
+
+#[test]
+fn an_int_list_passed_for_a_float_list_holds_floats() {
+    let source = r#"
+fun total(xs: [float]): float {
+    output(xs)
+    sum = 0.0
+    loop i = 0, i...len(xs) {
+        sum = sum + xs[i]
+    }
+    return sum
+}
+
+fun main(): null {
+    counts = [1, 2]
+    output(total(counts), counts)
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["[1.0, 2.0]", "3.0 [1, 2]"]);
+}
+
+#[test]
+fn every_returned_value_is_checked() {
+    let source = r#"
+fun pair(label: any): int, string {
+    return 1, label
+}
+
+fun main(): null {
+    pair(2)
+    return null
+}
+"#;
+    assert_eq!(error_of(source), "Runtime error: Function 'pair' returns string as value 2, got int");
+}
+
+#[test]
+fn a_float_is_not_taken_for_an_int() {
+    let argument = r#"
+fun f(n: int): int {
+    return n
+}
+
+fun main(): null {
+    output(f(2.5))
+    return null
+}
+"#;
+    assert_eq!(
+        error_of(argument),
+        "Type error: In function 'main': Function 'f' argument 0 has type float, expected int"
+    );
+
+    let returned = r#"
+fun g(): int {
+    return 2.5
+}
+
+fun main(): null {
+    output(g())
+    return null
+}
+"#;
+    assert_eq!(
+        error_of(returned),
+        "Type error: In function 'g': Return value 0 has type float, expected int"
+    );
+}
@@ -0,0 +1,76 @@
+//! Runtime errors print the same call stack on both engines
+
+mod common;
+
+use common::run_both;
+
+fn traceback(source: &str) -> String {
+    let run = run_both(source);
+    assert!(!run.success, "program succeeded:\n{}", source);
+    let start = run.stderr.find("Error: ").unwrap();
+    run.stderr[start..].to_string()
+}
+
+#[test]
+fn wrong_argument_type_shows_the_callee() {
+    let source = r#"
+fun g(x: int): int {
+    return x
+}
+
+fun main(): null {
+    a = "s" as any
+    output(g(a))
+    return null
+}
+"#;
+    assert_eq!(
+        traceback(source),
+        "Error: Runtime error: Function 'g' parameter 'x' expects int, got string\n\
+         Call stack, most recent call last:\n  main\n  g, called on line 8\n"
+    );
+}
+
+#[test]
+fn wrong_argument_count_shows_the_callee() {
+    let source = r#"
+fun g(x: int): int {
+    return x
+}
+
+fun main(): null {
+    f = g as any
+    output(f(1, 2))
+    return null
+}
+"#;
+    assert_eq!(
+        traceback(source),
+        "Error: Runtime error: Function 'g' expects 1 arguments, got 2\n\
+         Call stack, most recent call last:\n  main\n  g, called on line 8\n"
+    );
+}
+
+#[test]
+fn wrong_return_type_shows_the_function() {
+    let source = r#"
+fun h(): int {
+    y = "s" as any
+    return y
+}
+
+fun wrapper(): int {
+    return h()
+}
+
+fun main(): null {
+    output(wrapper())
+    return null
+}
+"#;
+    assert_eq!(
+        traceback(source),
+        "Error: Runtime error: Function 'h' returns int, got string\n\
+         Call stack, most recent call last:\n  main\n  wrapper, called on line 12\n  h, called on line 8\n"
+    );
+}
@@ -0,0 +1,144 @@
+//! Lists, maps and records are values: changing a copy, through assignment
+//! or in a function that was passed one, leaves the original unchanged
+
+mod common;
+
+use common::output_of;
+
+#[test]
+fn changing_a_copied_list_leaves_the_original() {
+    let source = r#"
+fun main(): null {
+    a = [1, 2, 3]
+    b = a
+    b[0] = 10
+    output(a)
+    output(b)
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["[1, 2, 3]", "[10, 2, 3]"]);
+}
+
+#[test]
+fn changing_a_copied_map_leaves_the_original() {
+    let source = r#"
+fun main(): null {
+    ages = ["ann": 31, "bob": 27]
+    older = ages
+    older["ann"] = 32
+    older["cid"] = 19
+    output(ages)
+    output(older)
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["[ann:31, bob:27]", "[ann:32, bob:27, cid:19]"]);
+}
+
+#[test]
+fn changing_a_copied_record_leaves_the_original() {
+    let source = r#"
+type Point {
+    x: int,
+    y: int,
+}
+
+fun main(): null {
+    p = Point { x: 1, y: 2 }
+    q = p
+    q.x = 5
+    output(p.x, p.y)
+    output(q.x, q.y)
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["1 2", "5 2"]);
+}
+
+#[test]
+fn changing_an_argument_leaves_the_callers_value() {
+    let source = r#"
+type Team {
+    name: string,
+    scores: [int],
+}
+
+fun reset(xs: [int]): [int] {
+    xs[0] = 0
+    return xs
+}
+
+fun rename(team: Team): Team {
+    team.name = "blue"
+    return team
+}
+
+fun add_key(m: [string:int]): [string:int] {
+    m["new"] = 1
+    return m
+}
+
+fun main(): null {
+    a = [1, 2, 3]
+    output(reset(a), a)
+    team = Team { name: "red", scores: [3] }
+    output(rename(team).name, team.name)
+    m = ["old": 0]
+    output(add_key(m), m)
+    return null
+}
+"#;
+    assert_eq!(
+        output_of(source),
+        ["[0, 2, 3] [1, 2, 3]", "blue red", "[old:0, new:1] [old:0]"]
+    );
+}
+
+#[test]
+fn changing_a_nested_copy_leaves_the_original() {
+    let source = r#"
+type Team {
+    name: string,
+    scores: [int],
+}
+
+fun main(): null {
+    team = Team { name: "red", scores: [3, 5] }
+    copy = team
+    copy.scores[1] = 8
+    output(team.scores, copy.scores)
+
+    teams = [team, copy]
+    teams[0].name = "blue"
+    output(teams[0].name, team.name)
+
+    grid = [[0, 0], [0, 0]]
+    row = grid[1]
+    grid[1][0] = 7
+    output(grid, row)
+    return null
+}
+"#;
+    assert_eq!(
+        output_of(source),
+        ["[3, 5] [3, 8]", "blue red", "[[0, 0], [7, 0]] [0, 0]"]
+    );
+}
+
+#[test]
+fn a_copy_taken_before_a_closure_changes_a_variable_keeps_its_value() {
+    let source = r#"
+fun main(): null {
+    items = [1, 2]
+    snapshot = items
+    set_first = fun(value: int): null {
+        items[0] = value
+    }
+    set_first(5)
+    output(items, snapshot)
+    return null
+}
+"#;
+    assert_eq!(output_of(source), ["[5, 2] [1, 2]"]);
+}